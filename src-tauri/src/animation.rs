@@ -0,0 +1,648 @@
+//! Animated emojis: frames from the provider, or local effects applied to one
+//! image, cut out against a single background color and encoded as GIF, APNG
+//! or WebP small enough for the target platform.
+
+use std::io::Cursor;
+use std::str::FromStr;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures::future::join_all;
+use image::RgbaImage;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::background::{self, BackgroundOptions, OutputOptions};
+use crate::effects::{self, Effect};
+use crate::error::{Error, ErrorKind, Result};
+use crate::generation::{self, GenerationProgress, GenerationRun, SlotStatus};
+use crate::provider::{ImageProvider, InputImage, Prompt};
+use crate::redact;
+use crate::retry::RetryBudget;
+
+/// Slack's emoji upload limit
+pub const SLACK_MAX_BYTES: usize = 128 * 1024;
+
+/// Discord's emoji upload limit
+pub const DISCORD_MAX_BYTES: usize = 256 * 1024;
+
+/// Frames generated by the provider when the caller doesn't say; each one is a request
+pub const DEFAULT_AI_FRAMES: usize = 4;
+
+/// Most frames the provider is asked for in one animation
+const MAX_AI_FRAMES: usize = 8;
+
+/// Most frames in any animation
+const MAX_FRAMES: usize = 48;
+
+pub const DEFAULT_FRAME_DELAY_MS: u32 = 80;
+
+/// When shrinking to fit, frames are dropped only down to this many...
+const MIN_FIT_FRAMES: usize = 4;
+
+/// ...and the canvas only down to this size
+const MIN_FIT_SIZE: u32 = 32;
+
+/// Motion asked for when generating frames without one
+const DEFAULT_MOTION: &str = "bouncing up and down";
+
+/// NeuQuant speed for GIF palettes: 1 is best, 30 fastest
+const GIF_SPEED: i32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+    Webp,
+}
+
+impl AnimationFormat {
+    pub const ALL: &[AnimationFormat] = &[AnimationFormat::Gif, AnimationFormat::Apng, AnimationFormat::Webp];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "apng",
+            AnimationFormat::Webp => "webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "image/gif",
+            AnimationFormat::Apng => "image/apng",
+            AnimationFormat::Webp => "image/webp",
+        }
+    }
+}
+
+impl FromStr for AnimationFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        AnimationFormat::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unknown format '{}', expected gif, apng or webp", s),
+                )
+            })
+    }
+}
+
+/// How to encode an animation
+#[derive(Debug, Clone)]
+pub struct AnimationOptions {
+    pub format: AnimationFormat,
+    /// Frames per loop; defaults depend on the effect, or `DEFAULT_AI_FRAMES`
+    pub frames: Option<usize>,
+    pub delay_ms: u32,
+    /// Canvas size, margin and padding; only the first size is used
+    pub output: OutputOptions,
+    /// Frames are dropped and the canvas shrunk until the file fits
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            format: AnimationFormat::Gif,
+            frames: None,
+            delay_ms: DEFAULT_FRAME_DELAY_MS,
+            output: OutputOptions::default(),
+            max_bytes: Some(SLACK_MAX_BYTES),
+        }
+    }
+}
+
+impl AnimationOptions {
+    fn frame_count(&self, default: usize, max: usize) -> Result<usize> {
+        let frames = self.frames.unwrap_or(default);
+        if !(2..=max).contains(&frames) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Frame count must be between 2 and {}, got {}", max, frames),
+            ));
+        }
+        Ok(frames)
+    }
+
+    /// Frames of an animation with `effect`, or of a generated one without
+    fn animation_frames(&self, effect: Option<Effect>) -> Result<usize> {
+        match effect {
+            Some(effect) => self.frame_count(effect.default_frames(), MAX_FRAMES),
+            None => self.frame_count(DEFAULT_AI_FRAMES, MAX_AI_FRAMES),
+        }
+    }
+
+    /// Images the provider is asked for: one to apply `effect` to, or every
+    /// frame. Fails on a frame count the animation would reject.
+    pub fn generated_images(&self, effect: Option<Effect>) -> Result<usize> {
+        let frames = self.animation_frames(effect)?;
+        Ok(if effect.is_some() { 1 } else { frames })
+    }
+}
+
+/// An encoded animation, sent to the frontend with the data as base64
+#[derive(Debug, Serialize)]
+pub struct Animation {
+    #[serde(serialize_with = "as_base64")]
+    pub data: Vec<u8>,
+    pub format: AnimationFormat,
+    pub mime_type: &'static str,
+    pub frames: usize,
+    pub size: u32,
+    pub delay_ms: u32,
+    pub warning: Option<String>,
+}
+
+fn as_base64<S: Serializer>(data: &[u8], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(data))
+}
+
+/// Cut out an existing image and animate it with a local effect. Needs no API key.
+pub fn animate_image(
+    image_bytes: &[u8],
+    effect: Effect,
+    options: &AnimationOptions,
+    background: &BackgroundOptions,
+) -> Result<Animation> {
+    let frames = options.animation_frames(Some(effect))?;
+    let image = image::load_from_memory(image_bytes)
+        .map_err(|e| Error::from(e).context("Failed to load image"))?
+        .to_rgba8();
+
+    let (mut cutout, warning) = background::cut_out_frames(&[image], background, &options.output)?;
+    let frames = effects::apply(effect, &cutout.remove(0), frames);
+    encode_fitted(frames, options, warning)
+}
+
+/// Prompt for one frame after the first, which is sent along as the input image
+pub fn build_frame_prompt(motion: &str, index: usize, count: usize) -> String {
+    format!(
+        "This emoji is frame 1 of a {}-frame looping animation of it {}. \
+         Draw frame {} of {}: the same emoji, same size, position, style and colors, \
+         changing only the pose for this step of the motion. \
+         Keep exactly the same solid background color.",
+        count,
+        motion.trim(),
+        index + 1,
+        count
+    )
+}
+
+/// Generate an animation with the provider.
+///
+/// With an `effect`, one image is generated and animated locally. Otherwise the
+/// first frame comes from `prompt` and the others are drawn from it in parallel,
+/// following `motion`. Frames are cut out with `run.background`. Retries and completed frames are reported through
+/// `on_progress`, with the frame number as the slot index.
+#[allow(clippy::too_many_arguments)]
+pub async fn generate_animation(
+    provider: &dyn ImageProvider,
+    prompt: &Prompt,
+    model: &str,
+    motion: Option<&str>,
+    effect: Option<Effect>,
+    options: &AnimationOptions,
+    run: &GenerationRun,
+    on_progress: &(dyn Fn(GenerationProgress) + Sync),
+) -> Result<Animation> {
+    let count = options.animation_frames(effect)?;
+    if effect.is_none() && !provider.capabilities().image_input {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Provider '{}' can't draw frames from an image; pick a local effect",
+                provider.id()
+            ),
+        ));
+    }
+    let budget = RetryBudget::new(run.policy.budget);
+    let generate = |index: usize, prompt: Prompt| {
+        let budget = &budget;
+        async move {
+            let result = tokio::select! {
+                result = generation::generate_with_retry(provider, &prompt, model, index, run, budget, on_progress) => result,
+                _ = run.cancel.cancelled() => Err(Error::new(ErrorKind::Cancelled, "Generation cancelled")),
+            };
+            let image = match result {
+                Ok(image) => image,
+                Err(e) => {
+                    let status = if e.kind == ErrorKind::Cancelled {
+                        SlotStatus::Cancelled
+                    } else {
+                        SlotStatus::Error
+                    };
+                    on_progress(GenerationProgress {
+                        usage: e.usage,
+                        error: Some(e.clone()),
+                        ..GenerationProgress::update(&run.id, index, status)
+                    });
+                    return Err(e);
+                }
+            };
+            on_progress(GenerationProgress {
+                usage: Some(image.usage),
+                ..GenerationProgress::update(&run.id, index, SlotStatus::Success)
+            });
+            Ok(image.data)
+        }
+    };
+
+    let first = generate(0, prompt.clone()).await?;
+    let background = run.background;
+    if let Some(effect) = effect {
+        let local = AnimationOptions {
+            frames: Some(count),
+            ..options.clone()
+        };
+        return blocking(move || animate_image(&first, effect, &local, &background)).await;
+    }
+
+    let motion = motion.filter(|m| !m.trim().is_empty()).unwrap_or(DEFAULT_MOTION);
+    let source = InputImage::from_bytes(first.clone())?;
+    let rest = join_all((1..count).map(|index| {
+        let prompt = Prompt::text(build_frame_prompt(motion, index, count)).with_images(vec![source.clone()]);
+        generate(index, prompt)
+    }))
+    .await;
+
+    let rest = rest.into_iter().collect::<Result<Vec<_>>>()?;
+    let options = options.clone();
+    blocking(move || {
+        let frames = std::iter::once(&first)
+            .chain(&rest)
+            .map(|bytes| decode(bytes))
+            .collect::<Result<Vec<_>>>()?;
+        let (frames, warning) = background::cut_out_frames(&frames, &background, &options.output)?;
+        encode_fitted(frames, &options, warning)
+    })
+    .await
+}
+
+/// Run cutting out and encoding off the async runtime; both take a while for many frames
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| Error::new(ErrorKind::ImageProcessing, format!("Animation task failed: {}", e)))?
+}
+
+fn decode(bytes: &[u8]) -> Result<RgbaImage> {
+    Ok(image::load_from_memory(bytes)
+        .map_err(|e| Error::from(e).context("Failed to load frame"))?
+        .to_rgba8())
+}
+
+/// Encode, shrinking the canvas and dropping frames until the result fits `max_bytes`
+fn encode_fitted(mut frames: Vec<RgbaImage>, options: &AnimationOptions, warning: Option<String>) -> Result<Animation> {
+    let mut delay_ms = options.delay_ms;
+    let mut reduced = false;
+    loop {
+        let data = encode(&frames, delay_ms, options.format)?;
+        let size = frames[0].width();
+        let fits = options.max_bytes.is_none_or(|max| data.len() <= max);
+        if fits {
+            let fit_warning = reduced.then(|| {
+                format!(
+                    "Reduced to {}px and {} frames to stay under {} KB",
+                    size,
+                    frames.len(),
+                    options.max_bytes.unwrap_or_default() / 1024
+                )
+            });
+            return Ok(Animation {
+                data,
+                format: options.format,
+                mime_type: options.format.mime_type(),
+                frames: frames.len(),
+                size,
+                delay_ms,
+                warning: [warning, fit_warning]
+                    .into_iter()
+                    .flatten()
+                    .reduce(|a, b| format!("{}; {}", a, b))
+                    .map(|warning| redact::redact(&warning)),
+            });
+        }
+
+        // A smaller canvas is barely visible at emoji size; choppier motion is
+        reduced = true;
+        let smaller = size * 3 / 4;
+        if smaller >= 64 || (frames.len() < MIN_FIT_FRAMES * 2 && smaller >= MIN_FIT_SIZE) {
+            frames = frames
+                .iter()
+                .map(|frame| image::imageops::resize(frame, smaller, smaller, image::imageops::FilterType::Lanczos3))
+                .collect();
+        } else if frames.len() >= MIN_FIT_FRAMES * 2 {
+            frames = frames.into_iter().step_by(2).collect();
+            delay_ms *= 2;
+        } else {
+            return Err(Error::new(
+                ErrorKind::ImageProcessing,
+                format!(
+                    "Animation is {} KB even at {}px with {} frames, over the {} KB limit",
+                    data.len() / 1024,
+                    size,
+                    frames.len(),
+                    options.max_bytes.unwrap_or_default() / 1024
+                ),
+            ));
+        }
+    }
+}
+
+/// Encode looping frames of the same size, each shown for `delay_ms`
+pub fn encode(frames: &[RgbaImage], delay_ms: u32, format: AnimationFormat) -> Result<Vec<u8>> {
+    if frames.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "No frames to encode"));
+    }
+    match format {
+        AnimationFormat::Gif => encode_gif(frames, delay_ms),
+        AnimationFormat::Apng => encode_apng(frames, delay_ms),
+        AnimationFormat::Webp => encode_webp(frames, delay_ms),
+    }
+}
+
+fn encoding_error(format: AnimationFormat, e: impl std::fmt::Display) -> Error {
+    Error::new(
+        ErrorKind::ImageProcessing,
+        format!("Failed to encode {}: {}", format.name().to_uppercase(), e),
+    )
+}
+
+fn encode_gif(frames: &[RgbaImage], delay_ms: u32) -> Result<Vec<u8>> {
+    let err = |e| encoding_error(AnimationFormat::Gif, e);
+    let (width, height) = frames[0].dimensions();
+    let mut out = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut out, width as u16, height as u16, &[]).map_err(err)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(err)?;
+        for frame in frames {
+            // GIF transparency is all or nothing
+            let mut data = frame.as_raw().clone();
+            for pixel in data.chunks_exact_mut(4) {
+                pixel[3] = if pixel[3] >= 128 { 255 } else { 0 };
+            }
+            let mut gif_frame = gif::Frame::from_rgba_speed(width as u16, height as u16, &mut data, GIF_SPEED);
+            gif_frame.delay = (delay_ms / 10) as u16;
+            // Clear each frame before the next, or transparent areas show the previous one
+            gif_frame.dispose = gif::DisposalMethod::Background;
+            encoder.write_frame(&gif_frame).map_err(err)?;
+        }
+    }
+    Ok(out)
+}
+
+fn encode_apng(frames: &[RgbaImage], delay_ms: u32) -> Result<Vec<u8>> {
+    let err = |e| encoding_error(AnimationFormat::Apng, e);
+    let (width, height) = frames[0].dimensions();
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0).map_err(err)?;
+    encoder
+        .set_frame_delay(delay_ms.min(u16::MAX as u32) as u16, 1000)
+        .map_err(err)?;
+    encoder.set_dispose_op(png::DisposeOp::Background).map_err(err)?;
+    encoder.set_blend_op(png::BlendOp::Source).map_err(err)?;
+
+    let mut writer = encoder.write_header().map_err(err)?;
+    for frame in frames {
+        writer.write_image_data(frame.as_raw()).map_err(err)?;
+    }
+    writer.finish().map_err(err)?;
+    Ok(out)
+}
+
+/// Animated WebP: each frame is encoded as a lossless still, and its VP8L
+/// chunk is wrapped in an ANMF chunk of one animated container
+fn encode_webp(frames: &[RgbaImage], delay_ms: u32) -> Result<Vec<u8>> {
+    let err = |e| encoding_error(AnimationFormat::Webp, e);
+    let (width, height) = frames[0].dimensions();
+
+    let mut body = b"WEBP".to_vec();
+    let mut vp8x = vec![0x10 | 0x02, 0, 0, 0]; // alpha, animation
+    vp8x.extend(u24(width - 1));
+    vp8x.extend(u24(height - 1));
+    push_chunk(&mut body, b"VP8X", &vp8x);
+    push_chunk(&mut body, b"ANIM", &[0, 0, 0, 0, 0, 0]); // transparent background, loop forever
+
+    for frame in frames {
+        let mut still = Cursor::new(Vec::new());
+        image::codecs::webp::WebPEncoder::new_lossless(&mut still)
+            .encode(frame.as_raw(), width, height, image::ExtendedColorType::Rgba8)
+            .map_err(err)?;
+        let vp8l = find_chunk(still.get_ref(), b"VP8L")
+            .ok_or_else(|| encoding_error(AnimationFormat::Webp, "encoder produced no VP8L chunk"))?;
+
+        let mut anmf = Vec::new();
+        anmf.extend(u24(0)); // x offset / 2
+        anmf.extend(u24(0)); // y offset / 2
+        anmf.extend(u24(width - 1));
+        anmf.extend(u24(height - 1));
+        anmf.extend(u24(delay_ms));
+        anmf.push(0x02); // don't blend with the previous frame
+        push_chunk(&mut anmf, b"VP8L", vp8l);
+        push_chunk(&mut body, b"ANMF", &anmf);
+    }
+
+    let mut out = b"RIFF".to_vec();
+    out.extend((body.len() as u32).to_le_bytes());
+    out.extend(body);
+    Ok(out)
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let bytes = value.to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+/// Append a RIFF chunk, padded to an even length
+fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend(fourcc);
+    out.extend((payload.len() as u32).to_le_bytes());
+    out.extend(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Payload of the first `fourcc` chunk in a WebP file
+fn find_chunk<'a>(webp: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut pos = 12; // RIFF header
+    while pos + 8 <= webp.len() {
+        let len = u32::from_le_bytes(webp[pos + 4..pos + 8].try_into().ok()?) as usize;
+        let payload = webp.get(pos + 8..pos + 8 + len)?;
+        if &webp[pos..pos + 4] == fourcc {
+            return Some(payload);
+        }
+        pos += 8 + len + len % 2;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::gemini::{GeminiClient, GeminiProvider};
+    use crate::mock_server::MockServer;
+    use crate::mock_server::gemini::{self as responses, green_background_png};
+    use crate::retry::RetryPolicy;
+    use image::AnimationDecoder;
+    use std::sync::Arc;
+
+    fn frames(count: usize) -> Vec<RgbaImage> {
+        (0..count)
+            .map(|i| {
+                RgbaImage::from_fn(32, 32, |x, y| {
+                    if x / 4 == i as u32 && y < 16 {
+                        image::Rgba([220, 30, 30, 255])
+                    } else {
+                        image::Rgba([0, 0, 0, 0])
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn decoded_frames(data: &[u8], format: AnimationFormat) -> Vec<image::Frame> {
+        let frames = match format {
+            AnimationFormat::Gif => image::codecs::gif::GifDecoder::new(Cursor::new(data))
+                .unwrap()
+                .into_frames(),
+            AnimationFormat::Apng => image::codecs::png::PngDecoder::new(Cursor::new(data))
+                .unwrap()
+                .apng()
+                .unwrap()
+                .into_frames(),
+            AnimationFormat::Webp => image::codecs::webp::WebPDecoder::new(Cursor::new(data))
+                .unwrap()
+                .into_frames(),
+        };
+        frames.collect_frames().unwrap()
+    }
+
+    #[test]
+    fn test_every_format_round_trips() {
+        for &format in AnimationFormat::ALL {
+            let data = encode(&frames(3), 100, format).unwrap();
+            let decoded = decoded_frames(&data, format);
+            assert_eq!(decoded.len(), 3, "{:?}", format);
+            assert_eq!(decoded[1].delay().numer_denom_ms(), (100, 1), "{:?}", format);
+
+            // Transparent areas stay transparent and frames don't pile up
+            let last = decoded[2].buffer();
+            assert_eq!(last.get_pixel(0, 0).0[3], 0, "{:?}", format);
+            assert_eq!(last.get_pixel(9, 0).0, [220, 30, 30, 255], "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_generated_images_checks_the_frame_count() {
+        let options = |frames| AnimationOptions {
+            frames,
+            ..AnimationOptions::default()
+        };
+        assert_eq!(options(None).generated_images(None).unwrap(), DEFAULT_AI_FRAMES);
+        assert_eq!(options(Some(6)).generated_images(Some(Effect::Spin)).unwrap(), 1);
+        for effect in [None, Some(Effect::Spin)] {
+            let err = options(Some(1000)).generated_images(effect).unwrap_err();
+            assert!(
+                err.message.starts_with("Frame count must be between 2 and"),
+                "{}",
+                err.message
+            );
+        }
+    }
+
+    #[test]
+    fn test_animate_image_fits_the_size_limit() {
+        let options = AnimationOptions {
+            format: AnimationFormat::Apng,
+            max_bytes: Some(40 * 1024),
+            ..AnimationOptions::default()
+        };
+        let animation = animate_image(
+            &green_background_png(),
+            Effect::Spin,
+            &options,
+            &BackgroundOptions::default(),
+        )
+        .unwrap();
+        assert!(animation.data.len() <= 40 * 1024);
+        assert_eq!(
+            decoded_frames(&animation.data, AnimationFormat::Apng).len(),
+            animation.frames
+        );
+
+        // Nothing fits in 100 bytes
+        let options = AnimationOptions {
+            max_bytes: Some(100),
+            ..options
+        };
+        let err = animate_image(
+            &green_background_png(),
+            Effect::Spin,
+            &options,
+            &BackgroundOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ImageProcessing);
+    }
+
+    #[tokio::test]
+    async fn test_generate_animation_sends_the_first_frame_with_each_later_one() {
+        let server = MockServer::start(|_| responses::image(&green_background_png())).await;
+        let provider = GeminiProvider::new(GeminiClient::with_base_url("test-key", &server.base_url));
+        let run = GenerationRun {
+            id: "animation".to_string(),
+            policy: RetryPolicy::default(),
+            cancel: Arc::new(CancelToken::default()),
+            background: BackgroundOptions::default(),
+            output: OutputOptions::default(),
+        };
+        let options = AnimationOptions {
+            frames: Some(3),
+            ..AnimationOptions::default()
+        };
+
+        let prompt = Prompt::text("a frog");
+        let animation = generate_animation(
+            &provider,
+            &prompt,
+            "model",
+            Some("waving"),
+            None,
+            &options,
+            &run,
+            &|_| {},
+        )
+        .await
+        .unwrap();
+        assert_eq!(animation.frames, 3);
+        assert_eq!(animation.mime_type, "image/gif");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        let bodies: Vec<String> = requests
+            .iter()
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .collect();
+        let later: Vec<_> = bodies.iter().filter(|body| body.contains("inlineData")).collect();
+        assert_eq!(later.len(), 2);
+        assert!(later.iter().all(|body| body.contains("waving")));
+    }
+}
@@ -0,0 +1,600 @@
+//! Credentials: the Gemini API key and upload tokens.
+//!
+//! Secrets are read from, in order:
+//! 1. the environment variable (`GEMINI_API_KEY`, `SLACK_TOKEN`, `DISCORD_BOT_TOKEN`)
+//! 2. the OS secret store (Keychain, Windows Credential Manager or Secret Service)
+//! 3. a file in the config directory, readable only by the owner and
+//!    optionally encrypted with a passphrase
+//!
+//! Saving goes to the secret store when there is one and falls back to the
+//! file otherwise. `migrate` moves plaintext files left by older versions into
+//! the secret store, or locks them down when there is none.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+
+use crate::config;
+use crate::error::{Error, ErrorKind, Result};
+use crate::redact;
+
+/// Service name for entries in the OS secret store
+const KEYRING_SERVICE: &str = "mojimix";
+
+/// Environment variable holding the passphrase for encrypted files
+const PASSPHRASE_ENV: &str = "MOJIMIX_PASSPHRASE";
+
+/// First line of an encrypted file; the second is base64 of salt, nonce and ciphertext
+const ENCRYPTED_HEADER: &str = "mojimix-encrypted-v1";
+
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 210_000;
+
+/// Passphrase entered in the app, kept in memory for the session
+static SESSION_PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Prefix of the file (and secret store account) holding a profile's key
+const PROFILE_PREFIX: &str = "api_key.";
+
+/// A credential kept in the secret store or config directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Secret {
+    GeminiApiKey,
+    SlackToken,
+    DiscordToken,
+    /// The Gemini key of a named profile
+    Profile(String),
+}
+
+impl Secret {
+    /// Secrets older versions may have left as plaintext files
+    const LEGACY: &[Secret] = &[Secret::GeminiApiKey, Secret::SlackToken, Secret::DiscordToken];
+
+    /// Environment variable that overrides the stored value; profiles have none
+    fn env_var(&self) -> Option<&'static str> {
+        match self {
+            Secret::GeminiApiKey => Some("GEMINI_API_KEY"),
+            Secret::SlackToken => Some("SLACK_TOKEN"),
+            Secret::DiscordToken => Some("DISCORD_BOT_TOKEN"),
+            Secret::Profile(_) => None,
+        }
+    }
+
+    /// File name in the config directory, also the account name in the secret store
+    fn file_name(&self) -> String {
+        match self {
+            Secret::GeminiApiKey => "api_key".to_string(),
+            Secret::SlackToken => "slack_token".to_string(),
+            Secret::DiscordToken => "discord_token".to_string(),
+            Secret::Profile(name) => format!("{}{}", PROFILE_PREFIX, name),
+        }
+    }
+
+    fn from_file_name(name: &str) -> Option<Secret> {
+        match name {
+            "api_key" => Some(Secret::GeminiApiKey),
+            "slack_token" => Some(Secret::SlackToken),
+            "discord_token" => Some(Secret::DiscordToken),
+            _ => name
+                .strip_prefix(PROFILE_PREFIX)
+                .map(|profile| Secret::Profile(profile.to_string())),
+        }
+    }
+
+    /// Human-readable name for error messages
+    fn label(&self) -> String {
+        match self {
+            Secret::GeminiApiKey => "API key".to_string(),
+            Secret::SlackToken => "Slack token".to_string(),
+            Secret::DiscordToken => "Discord bot token".to_string(),
+            Secret::Profile(name) => format!("API key of profile '{}'", name),
+        }
+    }
+}
+
+/// Where a secret is currently read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Storage {
+    Environment,
+    Keyring,
+    File,
+    EncryptedFile,
+    None,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StorageStatus {
+    pub storage: Storage,
+    /// The file is encrypted and no passphrase has been given
+    pub locked: bool,
+}
+
+/// The secret store and config directory; tests use a temp directory without a keyring
+struct Store {
+    dir: Option<PathBuf>,
+    keyring: bool,
+}
+
+impl Store {
+    fn system() -> Self {
+        Self {
+            dir: config::config_dir(),
+            keyring: true,
+        }
+    }
+
+    fn path(&self, secret: &Secret) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(secret.file_name()))
+    }
+
+    fn keyring_entry(&self, secret: &Secret) -> Option<keyring::Entry> {
+        if !self.keyring {
+            return None;
+        }
+        keyring::Entry::new(KEYRING_SERVICE, &secret.file_name()).ok()
+    }
+
+    /// The value in the secret store; `None` if it's empty or there is no secret store
+    fn keyring_get(&self, secret: &Secret) -> Option<String> {
+        let value = self.keyring_entry(secret)?.get_password().ok()?;
+        let value = value.trim().to_string();
+        (!value.is_empty()).then_some(value)
+    }
+
+    /// Store in the secret store, returning false if there is none
+    fn keyring_set(&self, secret: &Secret, value: &str) -> bool {
+        self.keyring_entry(secret)
+            .is_some_and(|entry| entry.set_password(value).is_ok())
+    }
+
+    fn keyring_delete(&self, secret: &Secret) {
+        if let Some(entry) = self.keyring_entry(secret) {
+            // Missing entries and an unavailable store both mean there's nothing to delete
+            let _ = entry.delete_credential();
+        }
+    }
+
+    fn read_file(&self, secret: &Secret) -> Option<String> {
+        let contents = fs::read_to_string(self.path(secret)?).ok()?;
+        let contents = contents.trim().to_string();
+        (!contents.is_empty()).then_some(contents)
+    }
+
+    fn get(&self, secret: &Secret, passphrase: Option<&str>) -> Result<Option<String>> {
+        // First check environment variable
+        if let Some(var) = secret.env_var()
+            && let Ok(value) = env::var(var)
+            && !value.trim().is_empty()
+        {
+            return Ok(Some(value));
+        }
+
+        // Then the secret store
+        if let Some(value) = self.keyring_get(secret) {
+            return Ok(Some(value));
+        }
+
+        // Then the config file
+        let Some(contents) = self.read_file(secret) else {
+            return Ok(None);
+        };
+        if !is_encrypted(&contents) {
+            return Ok(Some(contents));
+        }
+        let passphrase = passphrase.ok_or_else(|| {
+            Error::new(
+                ErrorKind::Locked,
+                format!(
+                    "The stored {} is encrypted; enter your passphrase to unlock it",
+                    secret.label()
+                ),
+            )
+        })?;
+        decrypt(&contents, passphrase).map(Some)
+    }
+
+    fn status(&self, secret: &Secret, passphrase: Option<&str>) -> StorageStatus {
+        let from_env = secret
+            .env_var()
+            .is_some_and(|var| env::var(var).is_ok_and(|v| !v.trim().is_empty()));
+        let (storage, locked) = if from_env {
+            (Storage::Environment, false)
+        } else if self.keyring_get(secret).is_some() {
+            (Storage::Keyring, false)
+        } else {
+            match self.read_file(secret) {
+                Some(contents) if is_encrypted(&contents) => (Storage::EncryptedFile, passphrase.is_none()),
+                Some(_) => (Storage::File, false),
+                None => (Storage::None, false),
+            }
+        };
+        StorageStatus { storage, locked }
+    }
+
+    fn save(&self, secret: &Secret, value: &str, passphrase: Option<&str>) -> Result<Storage> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} cannot be empty", secret.label()),
+            ));
+        }
+
+        if self.keyring_set(secret, value) {
+            // Don't leave an older copy behind that could be read instead
+            self.remove_file(secret)?;
+            return Ok(Storage::Keyring);
+        }
+        self.write_file(secret, value, passphrase)
+    }
+
+    fn write_file(&self, secret: &Secret, value: &str, passphrase: Option<&str>) -> Result<Storage> {
+        let path = self
+            .path(secret)
+            .ok_or_else(|| Error::new(ErrorKind::Storage, "Could not determine config directory"))?;
+        let (contents, storage) = match passphrase {
+            Some(passphrase) => (encrypt(value, passphrase)?, Storage::EncryptedFile),
+            None => (value.to_string(), Storage::File),
+        };
+        write_private(&path, &contents)
+            .map_err(|e| Error::from(e).context(&format!("Failed to save {}", secret.label())))?;
+        Ok(storage)
+    }
+
+    fn remove_file(&self, secret: &Secret) -> Result<()> {
+        if let Some(path) = self.path(secret)
+            && path.exists()
+        {
+            fs::remove_file(&path)
+                .map_err(|e| Error::from(e).context(&format!("Failed to remove {}", secret.label())))?;
+        }
+        Ok(())
+    }
+
+    fn clear(&self, secret: &Secret) -> Result<()> {
+        self.keyring_delete(secret);
+        self.remove_file(secret)
+    }
+
+    /// Move a plaintext file into the secret store, or rewrite it owner-only
+    /// (and encrypted, given a passphrase) when there is no secret store
+    fn migrate(&self, secret: &Secret, passphrase: Option<&str>) -> Result<()> {
+        let Some(contents) = self.read_file(secret) else {
+            return Ok(());
+        };
+        if is_encrypted(&contents) {
+            return Ok(());
+        }
+        if self.keyring_set(secret, &contents) {
+            return self.remove_file(secret);
+        }
+        self.write_file(secret, &contents, passphrase).map(|_| ())
+    }
+
+    /// Every secret kept in a file
+    fn file_secrets(&self) -> Vec<Secret> {
+        let Some(entries) = self.dir.as_ref().and_then(|dir| fs::read_dir(dir).ok()) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter_map(|entry| Secret::from_file_name(&entry.file_name().to_string_lossy()))
+            .collect()
+    }
+
+    /// Re-encrypt file-stored secrets with a new passphrase, or decrypt them with `None`.
+    /// Every file is decrypted before any is rewritten, so one that `old` can't
+    /// open leaves all of them under the passphrase they had.
+    fn change_passphrase(&self, old: Option<&str>, new: Option<&str>) -> Result<()> {
+        let mut values = Vec::new();
+        for secret in self.file_secrets() {
+            let Some(contents) = self.read_file(&secret) else {
+                continue;
+            };
+            let value = if is_encrypted(&contents) {
+                let old = old.ok_or_else(|| Error::new(ErrorKind::Locked, "Enter the current passphrase first"))?;
+                decrypt(&contents, old)?
+            } else {
+                contents
+            };
+            values.push((secret, value));
+        }
+        for (secret, value) in &values {
+            self.write_file(secret, value, new)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a file only the owner can read, creating its directory if needed.
+/// Like `output::write_atomic`, the value goes to a temp file that is then
+/// renamed over `path`, so a crash or full disk never loses the stored one.
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A leading dot keeps the temp file from being read as a secret of its own
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let tmp = path.with_file_name(format!(".{}.tmp", name));
+    // `mode` only applies to new files, so never reuse one left by a crash
+    match fs::remove_file(&tmp) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    let written = file.write_all(contents.as_bytes()).and_then(|_| file.sync_all());
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, path)
+}
+
+fn is_encrypted(contents: &str) -> bool {
+    contents.starts_with(ENCRYPTED_HEADER)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0u8; 32];
+    let iterations = NonZeroU32::new(PBKDF2_ITERATIONS).expect("iterations are non-zero");
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    LessSafeKey::new(UnboundKey::new(&aead::CHACHA20_POLY1305, &key).expect("key has the algorithm's length"))
+}
+
+fn encrypt(value: &str, passphrase: &str) -> Result<String> {
+    if passphrase.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Passphrase cannot be empty"));
+    }
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; aead::NONCE_LEN];
+    rng.fill(&mut salt)
+        .and_then(|_| rng.fill(&mut nonce))
+        .map_err(|_| Error::new(ErrorKind::Storage, "Failed to generate random bytes"))?;
+
+    let mut data = value.as_bytes().to_vec();
+    derive_key(passphrase, &salt)
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(ENCRYPTED_HEADER),
+            &mut data,
+        )
+        .map_err(|_| Error::new(ErrorKind::Storage, "Failed to encrypt"))?;
+
+    let mut payload = salt.to_vec();
+    payload.extend_from_slice(&nonce);
+    payload.extend_from_slice(&data);
+    Ok(format!("{}\n{}", ENCRYPTED_HEADER, STANDARD.encode(payload)))
+}
+
+fn decrypt(contents: &str, passphrase: &str) -> Result<String> {
+    let corrupt = || Error::new(ErrorKind::Storage, "Encrypted credential file is corrupt");
+    let payload = contents
+        .strip_prefix(ENCRYPTED_HEADER)
+        .and_then(|rest| STANDARD.decode(rest.trim()).ok())
+        .filter(|payload| payload.len() > SALT_LEN + aead::NONCE_LEN)
+        .ok_or_else(corrupt)?;
+    let (salt, rest) = payload.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(aead::NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| corrupt())?;
+
+    let mut data = ciphertext.to_vec();
+    let plaintext = derive_key(passphrase, salt)
+        .open_in_place(nonce, Aad::from(ENCRYPTED_HEADER), &mut data)
+        .map_err(|_| Error::new(ErrorKind::Locked, "Wrong passphrase"))?;
+    String::from_utf8(plaintext.to_vec()).map_err(|_| corrupt())
+}
+
+/// The passphrase entered this session, or `MOJIMIX_PASSPHRASE`
+fn passphrase() -> Option<String> {
+    SESSION_PASSPHRASE
+        .lock()
+        .unwrap()
+        .clone()
+        .or_else(|| config::env_override(PASSPHRASE_ENV))
+}
+
+/// Get a secret, or `None` if it isn't set or is encrypted and locked
+/// Every value read or saved here is registered with `redact`, so it never appears in errors or logs
+pub fn get(secret: &Secret) -> Option<String> {
+    let value = Store::system().get(secret, passphrase().as_deref()).ok().flatten();
+    value.inspect(|value| redact::register_secret(value))
+}
+
+/// Like `get`, but tells a locked file apart from a missing secret
+pub fn require(secret: &Secret) -> Result<String> {
+    Store::system()
+        .get(secret, passphrase().as_deref())?
+        .inspect(|value| redact::register_secret(value))
+        .ok_or_else(|| Error::new(ErrorKind::MissingApiKey, format!("No {} configured", secret.label())))
+}
+
+pub fn status(secret: &Secret) -> StorageStatus {
+    Store::system().status(secret, passphrase().as_deref())
+}
+
+/// Save to the secret store, or to the config file (encrypted if a passphrase is set)
+pub fn save(secret: &Secret, value: &str) -> Result<Storage> {
+    redact::register_secret(value);
+    Store::system().save(secret, value, passphrase().as_deref())
+}
+
+pub fn clear(secret: &Secret) -> Result<()> {
+    Store::system().clear(secret)
+}
+
+/// Move plaintext files from older versions into the secret store. Run at startup.
+pub fn migrate() -> Result<()> {
+    let store = Store::system();
+    let passphrase = passphrase();
+    for secret in Secret::LEGACY {
+        store.migrate(secret, passphrase.as_deref())?;
+    }
+    Ok(())
+}
+
+/// Remember the passphrase for this session after checking it opens every encrypted file
+pub fn unlock(passphrase: &str) -> Result<()> {
+    let store = Store::system();
+    for secret in store.file_secrets() {
+        if let Some(contents) = store.read_file(&secret)
+            && is_encrypted(&contents)
+        {
+            decrypt(&contents, passphrase)?;
+        }
+    }
+    *SESSION_PASSPHRASE.lock().unwrap() = Some(passphrase.to_string());
+    Ok(())
+}
+
+/// Encrypt file-stored secrets with `new`, or store them as owner-only plaintext with `None`
+pub fn set_passphrase(new: Option<&str>) -> Result<()> {
+    let new = new.filter(|p| !p.is_empty());
+    Store::system().change_passphrase(passphrase().as_deref(), new)?;
+    *SESSION_PASSPHRASE.lock().unwrap() = new.map(str::to_string);
+    Ok(())
+}
+
+/// Get the API key, or an error the frontend can use to show the key setup screen
+pub fn require_api_key() -> Result<String> {
+    require(&Secret::GeminiApiKey).map_err(|e| match e.kind {
+        ErrorKind::MissingApiKey => Error::new(ErrorKind::MissingApiKey, "No API key configured"),
+        _ => e,
+    })
+}
+
+pub fn save_api_key(key: &str) -> Result<()> {
+    save(&Secret::GeminiApiKey, key).map(|_| ())
+}
+
+pub fn clear_api_key() -> Result<()> {
+    clear(&Secret::GeminiApiKey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file-only store in a fresh temp directory, deleted with the returned
+    /// guard. Uses the Discord token, which no other test reads from the environment.
+    fn store() -> (tempfile::TempDir, Store) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store {
+            dir: Some(dir.path().to_path_buf()),
+            keyring: false,
+        };
+        (dir, store)
+    }
+
+    const SECRET: &Secret = &Secret::DiscordToken;
+
+    #[cfg(unix)]
+    fn mode(store: &Store) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(store.path(SECRET).unwrap()).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn test_file_fallback_is_owner_only() {
+        let (_dir, store) = store();
+        assert_eq!(store.save(SECRET, " token-1 ", None).unwrap(), Storage::File);
+        assert_eq!(store.get(SECRET, None).unwrap().as_deref(), Some("token-1"));
+        assert_eq!(store.status(SECRET, None).storage, Storage::File);
+        #[cfg(unix)]
+        assert_eq!(mode(&store), 0o600);
+
+        // Overwriting replaces the file whole and leaves no temp file behind
+        store.save(SECRET, "token-1b", None).unwrap();
+        assert_eq!(store.get(SECRET, None).unwrap().as_deref(), Some("token-1b"));
+        assert_eq!(fs::read_dir(store.dir.as_ref().unwrap()).unwrap().count(), 1);
+        #[cfg(unix)]
+        assert_eq!(mode(&store), 0o600);
+
+        store.clear(SECRET).unwrap();
+        assert_eq!(store.get(SECRET, None).unwrap(), None);
+        assert_eq!(store.status(SECRET, None).storage, Storage::None);
+    }
+
+    #[test]
+    fn test_encrypted_file_needs_the_passphrase() {
+        let (_dir, store) = store();
+        assert_eq!(
+            store.save(SECRET, "token-2", Some("hunter2")).unwrap(),
+            Storage::EncryptedFile
+        );
+        let contents = fs::read_to_string(store.path(SECRET).unwrap()).unwrap();
+        assert!(!contents.contains("token-2"));
+
+        assert_eq!(store.get(SECRET, None).unwrap_err().kind, ErrorKind::Locked);
+        assert!(store.status(SECRET, None).locked);
+        assert_eq!(store.get(SECRET, Some("wrong")).unwrap_err().kind, ErrorKind::Locked);
+        assert_eq!(store.get(SECRET, Some("hunter2")).unwrap().as_deref(), Some("token-2"));
+
+        // Dropping the passphrase leaves an owner-only plaintext file
+        store.change_passphrase(Some("hunter2"), None).unwrap();
+        assert_eq!(store.get(SECRET, None).unwrap().as_deref(), Some("token-2"));
+    }
+
+    #[test]
+    fn test_change_passphrase_rewrites_nothing_on_failure() {
+        let (_dir, store) = store();
+        let other = &Secret::Profile("work".to_string());
+        store.save(SECRET, "token-3", Some("hunter2")).unwrap();
+        store.save(other, "token-4", Some("different")).unwrap();
+
+        assert_eq!(
+            store.change_passphrase(Some("hunter2"), Some("new")).unwrap_err().kind,
+            ErrorKind::Locked
+        );
+        assert_eq!(store.get(SECRET, Some("hunter2")).unwrap().as_deref(), Some("token-3"));
+        assert_eq!(store.get(other, Some("different")).unwrap().as_deref(), Some("token-4"));
+    }
+
+    #[test]
+    fn test_migrate_locks_down_plaintext_files() {
+        let (_dir, store) = store();
+        let path = store.path(SECRET).unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "legacy-token\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        }
+
+        store.migrate(SECRET, Some("hunter2")).unwrap();
+        #[cfg(unix)]
+        assert_eq!(mode(&store), 0o600);
+        assert_eq!(store.status(SECRET, Some("hunter2")).storage, Storage::EncryptedFile);
+        assert_eq!(
+            store.get(SECRET, Some("hunter2")).unwrap().as_deref(),
+            Some("legacy-token")
+        );
+    }
+}
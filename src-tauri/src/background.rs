@@ -0,0 +1,934 @@
+//! Background removal and emoji sizing, the same for every provider and for
+//! images processed outside of generation.
+
+use std::io::Cursor;
+
+use image::{ImageFormat, Rgba, RgbaImage};
+
+use crate::error::{Error, ErrorKind, Result};
+
+/// Default distance (ΔE, see `color_distance`) under which a pixel counts as background
+pub const DEFAULT_TOLERANCE: f32 = 25.0;
+
+/// Accepted range for a caller-set tolerance
+const TOLERANCE_RANGE: std::ops::RangeInclusive<f32> = 1.0..=100.0;
+
+/// Lightness differences count half, so shading and gradients in the
+/// background still match while differently colored subjects don't
+const LIGHTNESS_WEIGHT: f32 = 0.5;
+
+/// At most this many border pixels are compared when detecting the background
+const BORDER_SAMPLES: usize = 512;
+
+/// The background color must cover at least this share of the border...
+const MIN_BORDER_COVERAGE: f32 = 0.3;
+
+/// ...and below this share a warning suggests the subject runs into the edges
+const BORDER_MAJORITY: f32 = 0.8;
+
+/// In the soft matte, alpha ramps from 0 at the tolerance to 1 this much further out
+const MATTE_RAMP: f32 = 40.0;
+
+/// Default canvas size: Slack and Discord both display emojis at 128x128
+pub const DEFAULT_SIZE: u32 = 128;
+
+/// Default empty space between the content and each canvas edge
+pub const DEFAULT_MARGIN: u32 = 4;
+
+/// Default source pixels kept around the content when cropping
+pub const DEFAULT_PADDING: u32 = 4;
+
+/// Accepted canvas sizes
+const SIZE_RANGE: std::ops::RangeInclusive<u32> = 16..=2048;
+
+/// Most source pixels kept around the content when cropping
+const MAX_PADDING: u32 = *SIZE_RANGE.end();
+
+/// Backgrounds with a channel spread below this are treated as neutral (no spill)
+const MIN_KEY_SATURATION: u8 = 64;
+
+/// How far (in pixels) from the keyed-out background the soft matte reaches
+const MATTE_EDGE_BAND: usize = 2;
+
+/// Caller-tunable settings for background removal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackgroundOptions {
+    /// Max perceptual distance from the background color that is still keyed out.
+    /// Lower keeps more of greenish subjects, higher eats more of a gradient background.
+    pub tolerance: f32,
+}
+
+impl Default for BackgroundOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: DEFAULT_TOLERANCE,
+        }
+    }
+}
+
+impl BackgroundOptions {
+    /// Defaults with an optional caller-set tolerance, checked against the accepted range
+    pub fn with_tolerance(tolerance: Option<f32>) -> Result<Self> {
+        let tolerance = tolerance.unwrap_or(DEFAULT_TOLERANCE);
+        if !TOLERANCE_RANGE.contains(&tolerance) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Tolerance must be between {} and {}, got {}",
+                    TOLERANCE_RANGE.start(),
+                    TOLERANCE_RANGE.end(),
+                    tolerance
+                ),
+            ));
+        }
+        Ok(Self { tolerance })
+    }
+}
+
+/// Emojis that are mostly green, and would be keyed out on a green background
+const GREEN_SUBJECTS: &[&str] = &[
+    "🐸", "🥒", "🌵", "🍀", "☘", "🌿", "🌱", "🌲", "🌳", "🌴", "🪴", "🍃", "🎄", "🎋", "🥦", "🥬", "🫑", "🫛", "🥝",
+    "🍏", "🍐", "🫒", "🥑", "🐢", "🐊", "🦎", "🐍", "🦖", "🦕", "🐉", "🐲", "🐛", "🦗", "🦠", "🧟", "🤢", "🤮", "💚",
+    "🟢", "🟩", "✅", "❇", "♻", "💵", "🔋",
+];
+
+/// Mostly magenta or pink emojis, which rule out a magenta background
+const MAGENTA_SUBJECTS: &[&str] = &[
+    "🌸", "🌺", "🪷", "🦩", "🩷", "💗", "💖", "💕", "💓", "💞", "💝", "💜", "🟣", "🟪", "🍇", "🔮", "👾", "🐷", "🐽",
+    "🌷", "🎀", "🦄", "🪼",
+];
+
+/// Background color the prompt asks for; chosen to be far from the subject's colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyColor {
+    Green,
+    Magenta,
+    Blue,
+}
+
+impl KeyColor {
+    /// Green unless the subject is green: then magenta, or blue if the subject is also pink
+    pub fn for_subject(emojis: &[String], modifier: Option<&str>) -> Self {
+        let modifier = modifier.unwrap_or_default().to_lowercase();
+        let mentions = |subjects: &[&str], words: &[&str]| {
+            emojis.iter().any(|emoji| subjects.iter().any(|s| emoji.contains(s)))
+                || words.iter().any(|word| modifier.contains(word))
+        };
+
+        if !mentions(GREEN_SUBJECTS, &["green"]) {
+            KeyColor::Green
+        } else if mentions(MAGENTA_SUBJECTS, &["pink", "magenta", "purple"]) {
+            KeyColor::Blue
+        } else {
+            KeyColor::Magenta
+        }
+    }
+
+    /// How the prompt names the color
+    pub fn prompt_text(self) -> &'static str {
+        match self {
+            KeyColor::Green => "bright green (#00FF00)",
+            KeyColor::Magenta => "bright magenta (#FF00FF)",
+            KeyColor::Blue => "bright blue (#0000FF)",
+        }
+    }
+}
+
+/// Canvas sizes and spacing of the final PNGs
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputOptions {
+    /// Square canvas sizes in pixels. The first one is the main output;
+    /// the rest are rendered from the same matte.
+    pub sizes: Vec<u32>,
+    /// Empty space between the content and each canvas edge, in output pixels
+    pub margin: u32,
+    /// Source pixels kept around the content when cropping, at most `MAX_PADDING`
+    pub padding: u32,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            sizes: vec![DEFAULT_SIZE],
+            margin: DEFAULT_MARGIN,
+            padding: DEFAULT_PADDING,
+        }
+    }
+}
+
+impl OutputOptions {
+    /// Defaults overridden by whatever the caller set, checked so every canvas has room for content
+    pub fn new(sizes: Option<Vec<u32>>, margin: Option<u32>, padding: Option<u32>) -> Result<Self> {
+        let defaults = Self::default();
+        let options = Self {
+            sizes: sizes.filter(|sizes| !sizes.is_empty()).unwrap_or(defaults.sizes),
+            margin: margin.unwrap_or(defaults.margin),
+            padding: padding.unwrap_or(defaults.padding),
+        };
+        if options.padding > MAX_PADDING {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Padding must be at most {}, got {}", MAX_PADDING, options.padding),
+            ));
+        }
+        for &size in &options.sizes {
+            if !SIZE_RANGE.contains(&size) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Size must be between {} and {}, got {}",
+                        SIZE_RANGE.start(),
+                        SIZE_RANGE.end(),
+                        size
+                    ),
+                ));
+            }
+            if options.margin >= size.div_ceil(2) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Margin {} leaves no room for content on a {}px canvas",
+                        options.margin, size
+                    ),
+                ));
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Result containing all background removal methods, at the first requested size
+pub struct ProcessedEmoji {
+    pub flood_fill: Vec<u8>,
+    pub color_key: Vec<u8>,
+    /// Flood fill with partial alpha and green spill removed on the edges
+    pub soft_matte: Vec<u8>,
+    /// The same variants at the remaining requested sizes
+    pub extra_sizes: Vec<SizedEmoji>,
+    pub warning: Option<String>,
+}
+
+/// All variants at one additional canvas size
+pub struct SizedEmoji {
+    pub size: u32,
+    pub flood_fill: Vec<u8>,
+    pub color_key: Vec<u8>,
+    pub soft_matte: Vec<u8>,
+}
+
+pub fn resize_to_emoji(
+    image_bytes: &[u8],
+    options: &BackgroundOptions,
+    output: &OutputOptions,
+) -> Result<ProcessedEmoji> {
+    let img = image::load_from_memory(image_bytes).map_err(|e| Error::from(e).context("Failed to load image"))?;
+
+    let rgba = img.to_rgba8();
+    let (bg, warning) = background_color(&rgba, options.tolerance)?;
+    let cutouts = cut_out(&rgba, bg, options.tolerance);
+
+    // Crop once, then scale the same cutouts to every size
+    let flood_fill = crop_to_content(cutouts.flood_fill, output.padding);
+    let color_key = crop_to_content(cutouts.color_key, output.padding);
+    let soft_matte = crop_to_content(cutouts.soft_matte, output.padding);
+    let mut sized = output.sizes.iter().map(|&size| {
+        Ok(SizedEmoji {
+            size,
+            flood_fill: fit_to_canvas(&flood_fill, size, output.margin)?,
+            color_key: fit_to_canvas(&color_key, size, output.margin)?,
+            soft_matte: fit_to_canvas(&soft_matte, size, output.margin)?,
+        })
+    });
+    let main: SizedEmoji = sized
+        .next()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No output size requested"))??;
+
+    Ok(ProcessedEmoji {
+        flood_fill: main.flood_fill,
+        color_key: main.color_key,
+        soft_matte: main.soft_matte,
+        extra_sizes: sized.collect::<Result<_>>()?,
+        warning,
+    })
+}
+
+/// Background color of `rgba`, plus a warning when the subject seems to run
+/// into the border
+fn background_color(rgba: &RgbaImage, tolerance: f32) -> Result<([u8; 3], Option<String>)> {
+    let (width, height) = rgba.dimensions();
+    let (bg, coverage) = detect_background(rgba, tolerance)?;
+    let bg_lab = to_lab(&Rgba([bg[0], bg[1], bg[2], 255]));
+
+    // Corners are the first place a subject touches the border, so name them
+    let corners = [
+        rgba.get_pixel(0, 0),
+        rgba.get_pixel(width - 1, 0),
+        rgba.get_pixel(0, height - 1),
+        rgba.get_pixel(width - 1, height - 1),
+    ];
+    let off_corners = corners
+        .iter()
+        .filter(|c| color_distance(bg_lab, to_lab(c)) >= tolerance)
+        .count();
+    let warning = if off_corners > 0 {
+        Some(format!(
+            "{} corner(s) excluded from background detection (differ from the border color)",
+            off_corners
+        ))
+    } else if coverage < BORDER_MAJORITY {
+        Some(format!("Background covers only {:.0}% of the border", coverage * 100.0))
+    } else {
+        None
+    };
+    Ok((bg, warning))
+}
+
+/// Full-size cutouts of one image
+struct Cutouts {
+    flood_fill: RgbaImage,
+    color_key: RgbaImage,
+    soft_matte: RgbaImage,
+}
+
+/// Key out background color `bg` from `rgba` with every method
+fn cut_out(rgba: &RgbaImage, bg: [u8; 3], tolerance: f32) -> Cutouts {
+    let (width, height) = rgba.dimensions();
+    let bg_lab = to_lab(&Rgba([bg[0], bg[1], bg[2], 255]));
+
+    let bg_distance = |pixel: &Rgba<u8>| color_distance(bg_lab, to_lab(pixel));
+
+    // Helper to check if pixel matches background
+    let matches_bg = |pixel: &Rgba<u8>| bg_distance(pixel) < tolerance;
+
+    // Background reachable from the edges, shared by flood fill and soft matte
+    let bg_mask = {
+        let mut mask = vec![false; (width * height) as usize];
+        let mut visited = vec![false; (width * height) as usize];
+        let mut stack: Vec<(u32, u32)> = Vec::new();
+
+        // Seed flood fill from all edge pixels
+        for x in 0..width {
+            stack.push((x, 0));
+            stack.push((x, height - 1));
+        }
+        for y in 1..height - 1 {
+            stack.push((0, y));
+            stack.push((width - 1, y));
+        }
+
+        // Flood fill
+        while let Some((x, y)) = stack.pop() {
+            if x >= width || y >= height {
+                continue;
+            }
+            let i = (y * width + x) as usize;
+            if visited[i] {
+                continue;
+            }
+            visited[i] = true;
+
+            if !matches_bg(rgba.get_pixel(x, y)) {
+                continue;
+            }
+            mask[i] = true;
+
+            // Add neighbors
+            if x > 0 {
+                stack.push((x - 1, y));
+            }
+            if x < width - 1 {
+                stack.push((x + 1, y));
+            }
+            if y > 0 {
+                stack.push((x, y - 1));
+            }
+            if y < height - 1 {
+                stack.push((x, y + 1));
+            }
+        }
+        mask
+    };
+
+    // Process with flood fill
+    let flood_fill_result = {
+        let mut rgba_ff = rgba.clone();
+        for (x, y, pixel) in rgba_ff.enumerate_pixels_mut() {
+            if bg_mask[(y * width + x) as usize] {
+                *pixel = Rgba([0, 0, 0, 0]);
+            }
+        }
+        rgba_ff
+    };
+
+    // Process with color key (all matching pixels)
+    let color_key_result = {
+        let mut rgba_ck = rgba.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = rgba_ck.get_pixel(x, y);
+                if matches_bg(pixel) {
+                    rgba_ck.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                }
+            }
+        }
+        rgba_ck
+    };
+
+    // Process with soft matte: flood fill, then partial alpha on the pixels
+    // bordering the background so anti-aliased edges blend instead of fringing
+    let soft_matte_result = {
+        let mut rgba_sm = flood_fill_result.clone();
+        let edge = dilate(&bg_mask, width as usize, height as usize, MATTE_EDGE_BAND);
+        for (x, y, pixel) in rgba_sm.enumerate_pixels_mut() {
+            let i = (y * width + x) as usize;
+            if bg_mask[i] || !edge[i] {
+                continue;
+            }
+            let alpha = ((bg_distance(pixel) - tolerance) / MATTE_RAMP).clamp(0.0, 1.0);
+            *pixel = decontaminate(*pixel, bg, alpha);
+        }
+        rgba_sm
+    };
+
+    Cutouts {
+        flood_fill: flood_fill_result,
+        color_key: color_key_result,
+        soft_matte: soft_matte_result,
+    }
+}
+
+/// Cut out animation frames against the background of the first frame and
+/// fit them to the first output size. All frames are cropped to their combined
+/// content, so the subject doesn't jump around between frames.
+pub fn cut_out_frames(
+    frames: &[RgbaImage],
+    options: &BackgroundOptions,
+    output: &OutputOptions,
+) -> Result<(Vec<RgbaImage>, Option<String>)> {
+    let first = frames
+        .first()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No frames to cut out"))?;
+    let size = *output
+        .sizes
+        .first()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No output size requested"))?;
+    // Already cut out, e.g. an exported emoji: nothing to key
+    let (bg, warning) = if transparent_border(first) {
+        (None, None)
+    } else {
+        let (bg, warning) = background_color(first, options.tolerance)?;
+        (Some(bg), warning)
+    };
+
+    // Providers don't promise the same dimensions for every frame
+    let (width, height) = first.dimensions();
+    let mattes: Vec<RgbaImage> = frames
+        .iter()
+        .map(|frame| {
+            let frame = if frame.dimensions() == (width, height) {
+                frame.clone()
+            } else {
+                image::imageops::resize(frame, width, height, image::imageops::FilterType::Lanczos3)
+            };
+            match bg {
+                Some(bg) => cut_out(&frame, bg, options.tolerance).soft_matte,
+                None => frame,
+            }
+        })
+        .collect();
+
+    let bounds = mattes
+        .iter()
+        .filter_map(content_bounds)
+        .reduce(|a, b| [a[0].min(b[0]), a[1].min(b[1]), a[2].max(b[2]), a[3].max(b[3])]);
+    let canvases = mattes
+        .into_iter()
+        .map(|matte| {
+            let cropped = match bounds {
+                Some(bounds) => crop_padded(&matte, bounds, output.padding),
+                None => matte,
+            };
+            fit_image(&cropped, size, output.margin)
+        })
+        .collect();
+    Ok((canvases, warning))
+}
+
+/// Whether most of the border is already fully transparent
+fn transparent_border(rgba: &RgbaImage) -> bool {
+    let (width, height) = rgba.dimensions();
+    let border = (0..width)
+        .flat_map(|x| [(x, 0), (x, height - 1)])
+        .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]));
+    let (transparent, total) = border.fold((0, 0), |(transparent, total), (x, y)| {
+        (transparent + (rgba.get_pixel(x, y).0[3] == 0) as usize, total + 1)
+    });
+    transparent as f32 >= total as f32 * BORDER_MAJORITY
+}
+
+/// The background color and the share of the border it covers.
+///
+/// Takes the border color that most border pixels are within `tolerance` of,
+/// averaged over those pixels, so any solid hue works, not just green.
+fn detect_background(rgba: &RgbaImage, tolerance: f32) -> Result<([u8; 3], f32)> {
+    let (width, height) = rgba.dimensions();
+    let mut border: Vec<(u32, u32)> = (0..width).flat_map(|x| [(x, 0), (x, height - 1)]).collect();
+    border.extend((1..height.saturating_sub(1)).flat_map(|y| [(0, y), (width - 1, y)]));
+    let step = border.len().div_ceil(BORDER_SAMPLES);
+    let samples: Vec<&Rgba<u8>> = border
+        .iter()
+        .step_by(step)
+        .map(|&(x, y)| rgba.get_pixel(x, y))
+        .collect();
+    let labs: Vec<[f32; 3]> = samples.iter().map(|p| to_lab(p)).collect();
+
+    let neighbors = |center: &[f32; 3]| {
+        labs.iter()
+            .filter(|lab| color_distance(*center, **lab) < tolerance)
+            .count()
+    };
+    let (center, count) = labs
+        .iter()
+        .map(|lab| (lab, neighbors(lab)))
+        .max_by_key(|&(_, count)| count)
+        .ok_or_else(|| Error::new(ErrorKind::ImageProcessing, "Image is empty"))?;
+
+    let coverage = count as f32 / labs.len() as f32;
+    if coverage < MIN_BORDER_COVERAGE {
+        return Err(Error::new(
+            ErrorKind::NoBackground,
+            format!(
+                "No solid background detected: the most common border color covers only {:.0}% of the border",
+                coverage * 100.0
+            ),
+        ));
+    }
+
+    let mut sum = [0u32; 3];
+    for (pixel, lab) in samples.iter().zip(&labs) {
+        if color_distance(*center, *lab) < tolerance {
+            for (total, &channel) in sum.iter_mut().zip(&pixel.0[..3]) {
+                *total += channel as u32;
+            }
+        }
+    }
+    let bg = sum.map(|total| (total / count as u32) as u8);
+    Ok((bg, coverage))
+}
+
+/// CIELAB coordinates of an sRGB pixel (D65 white point)
+fn to_lab(pixel: &Rgba<u8>) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(pixel.0[0]), linear(pixel.0[1]), linear(pixel.0[2]));
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// CIE76 ΔE with lightness down-weighted by `LIGHTNESS_WEIGHT`
+fn color_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let dl = (a[0] - b[0]) * LIGHTNESS_WEIGHT;
+    let da = a[1] - b[1];
+    let db = a[2] - b[2];
+    (dl * dl + da * da + db * db).sqrt()
+}
+
+/// Pixels within `radius` (Chebyshev distance) of a set pixel in `mask`
+fn dilate(mask: &[bool], width: usize, height: usize, radius: usize) -> Vec<bool> {
+    let mut out = vec![false; mask.len()];
+    for y in 0..height {
+        for x in 0..width {
+            if !mask[y * width + x] {
+                continue;
+            }
+            for ny in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                for nx in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                    out[ny * width + nx] = true;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Undo the blend with the background for an edge pixel of coverage `alpha`,
+/// then clamp the background's key channels so no color spill remains
+fn decontaminate(pixel: Rgba<u8>, bg: [u8; 3], alpha: f32) -> Rgba<u8> {
+    if alpha <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+
+    // observed = alpha * foreground + (1 - alpha) * background
+    let mut fg = [0f32; 3];
+    for (i, channel) in fg.iter_mut().enumerate() {
+        *channel = ((pixel.0[i] as f32 - (1.0 - alpha) * bg[i] as f32) / alpha).clamp(0.0, 255.0);
+    }
+
+    // Spill suppression: the key channels (G for green, R and B for magenta)
+    // may not exceed the foreground's other channels. Grey and white
+    // backgrounds have no key channels and no spill.
+    let (lo, hi) = (*bg.iter().min().unwrap_or(&0), *bg.iter().max().unwrap_or(&0));
+    if hi - lo >= MIN_KEY_SATURATION {
+        let is_key = |i: usize| bg[i] as u16 * 2 > lo as u16 + hi as u16;
+        let others = (0..3).filter(|&i| !is_key(i)).map(|i| fg[i]).fold(0.0, f32::max);
+        let spill = (0..3).filter(|&i| is_key(i)).map(|i| fg[i]).fold(255.0, f32::min) - others;
+        if spill > 0.0 {
+            for i in (0..3).filter(|&i| is_key(i)) {
+                fg[i] -= spill;
+            }
+        }
+    }
+
+    Rgba([
+        fg[0].round() as u8,
+        fg[1].round() as u8,
+        fg[2].round() as u8,
+        (pixel.0[3] as f32 * alpha).round() as u8,
+    ])
+}
+
+/// Bounding box `[min_x, min_y, max_x, max_y]` of the visible pixels, if any
+fn content_bounds(rgba: &RgbaImage) -> Option<[u32; 4]> {
+    let (width, height) = rgba.dimensions();
+
+    // Find bounding box of non-transparent pixels
+    let mut min_x = width;
+    let mut min_y = height;
+    let mut max_x = 0u32;
+    let mut max_y = 0u32;
+
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        if pixel.0[3] > 10 {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    (max_x >= min_x && max_y >= min_y).then_some([min_x, min_y, max_x, max_y])
+}
+
+/// Crop to `bounds`, keeping `padding` pixels around it where the image allows
+fn crop_padded(rgba: &RgbaImage, bounds: [u32; 4], padding: u32) -> RgbaImage {
+    let (width, height) = rgba.dimensions();
+    let [min_x, min_y, max_x, max_y] = bounds;
+    let crop_x = min_x.saturating_sub(padding);
+    let crop_y = min_y.saturating_sub(padding);
+    let crop_w = (max_x - min_x + 1)
+        .saturating_add(padding.saturating_mul(2))
+        .min(width - crop_x);
+    let crop_h = (max_y - min_y + 1)
+        .saturating_add(padding.saturating_mul(2))
+        .min(height - crop_y);
+    image::imageops::crop_imm(rgba, crop_x, crop_y, crop_w, crop_h).to_image()
+}
+
+/// Crop to the visible content, keeping `padding` pixels around it
+fn crop_to_content(rgba: RgbaImage, padding: u32) -> RgbaImage {
+    match content_bounds(&rgba) {
+        Some(bounds) => crop_padded(&rgba, bounds, padding),
+        None => rgba,
+    }
+}
+
+/// Scale into a `size - 2 * margin` box, centered on a `size`x`size` canvas
+fn fit_image(cropped: &RgbaImage, size: u32, margin: u32) -> RgbaImage {
+    // Resize maintaining aspect ratio
+    let inner = (size - margin * 2) as f32;
+    let (cw, ch) = cropped.dimensions();
+    let scale = (inner / cw as f32).min(inner / ch as f32);
+    let new_w = ((cw as f32 * scale) as u32).max(1);
+    let new_h = ((ch as f32 * scale) as u32).max(1);
+
+    let scaled = image::imageops::resize(cropped, new_w, new_h, image::imageops::FilterType::Lanczos3);
+
+    // Center on the canvas
+    let mut final_img = RgbaImage::new(size, size);
+    let offset_x = (size - new_w) / 2;
+    let offset_y = (size - new_h) / 2;
+
+    for (x, y, pixel) in scaled.enumerate_pixels() {
+        final_img.put_pixel(offset_x + x, offset_y + y, *pixel);
+    }
+    final_img
+}
+
+/// `fit_image`, encoded as PNG
+fn fit_to_canvas(cropped: &RgbaImage, size: u32, margin: u32) -> Result<Vec<u8>> {
+    let mut output = Cursor::new(Vec::new());
+    fit_image(cropped, size, margin)
+        .write_to(&mut output, ImageFormat::Png)
+        .map_err(|e| Error::from(e).context("Failed to encode PNG"))?;
+
+    Ok(output.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize_inconsistent_background_returns_warning() {
+        // This image has a green background but one corner has a hand (skin tone)
+        // Previously this would fail; now it should succeed with a warning
+        let image_bytes = include_bytes!("../tests/fixtures/cowboy_friends.png");
+        let result = resize_to_emoji(image_bytes, &BackgroundOptions::default(), &OutputOptions::default());
+
+        assert!(
+            result.is_ok(),
+            "Should succeed with warning, not fail: {:?}",
+            result.err()
+        );
+        let processed = result.unwrap();
+        assert!(
+            processed.warning.is_some(),
+            "Should have a warning about excluded corners"
+        );
+        assert!(!processed.flood_fill.is_empty(), "Should have flood_fill output");
+        assert!(!processed.color_key.is_empty(), "Should have color_key output");
+        assert!(!processed.soft_matte.is_empty(), "Should have soft_matte output");
+
+        // Verify the warning mentions excluded corners
+        let warning = processed.warning.unwrap();
+        assert!(
+            warning.contains("corner"),
+            "Warning should mention corners: {}",
+            warning
+        );
+    }
+
+    #[test]
+    fn test_decontaminate_removes_green_spill() {
+        let bg = [0, 255, 0];
+        // Half-covered red edge pixel: 50% red over green
+        let pixel = decontaminate(Rgba([110, 128, 0, 255]), bg, 0.5);
+        assert_eq!(pixel.0[3], 128);
+        assert!(pixel.0[0] > 200, "foreground red is recovered: {:?}", pixel);
+        assert!(
+            pixel.0[1] <= pixel.0[0].max(pixel.0[2]),
+            "green spill is clamped: {:?}",
+            pixel
+        );
+
+        assert_eq!(decontaminate(Rgba([0, 255, 0, 255]), bg, 0.0), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_soft_matte_has_partial_alpha_on_edges() {
+        // A red disc with an anti-aliased rim on green
+        let mut img = RgbaImage::from_pixel(64, 64, Rgba([0, 255, 0, 255]));
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let d = ((x as f32 - 32.0).powi(2) + (y as f32 - 32.0).powi(2)).sqrt();
+            let coverage = (20.0 - d).clamp(0.0, 1.0);
+            let blend = |fg: f32, bg: f32| (fg * coverage + bg * (1.0 - coverage)) as u8;
+            *pixel = Rgba([blend(220.0, 0.0), blend(30.0, 255.0), blend(30.0, 0.0), 255]);
+        }
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, ImageFormat::Png).unwrap();
+
+        let processed = resize_to_emoji(
+            &png.into_inner(),
+            &BackgroundOptions::default(),
+            &OutputOptions::default(),
+        )
+        .unwrap();
+        let alphas = |bytes: &[u8]| -> Vec<u8> {
+            image::load_from_memory(bytes)
+                .unwrap()
+                .to_rgba8()
+                .pixels()
+                .map(|p| p.0[3])
+                .collect()
+        };
+        let partial = |alphas: &[u8]| alphas.iter().filter(|&&a| a > 0 && a < 255).count();
+
+        let flood_fill = alphas(&processed.flood_fill);
+        let soft_matte = alphas(&processed.soft_matte);
+        assert!(partial(&soft_matte) > partial(&flood_fill));
+    }
+
+    #[test]
+    fn test_color_distance_keeps_greenish_subjects() {
+        let bg = to_lab(&Rgba([0, 255, 0, 255]));
+        let distance = |r, g, b| color_distance(bg, to_lab(&Rgba([r, g, b, 255])));
+
+        // Shading of the background itself stays within the default tolerance...
+        assert!(distance(0, 215, 0) < DEFAULT_TOLERANCE);
+        // ...while frog and cactus greens don't
+        assert!(distance(80, 180, 60) > DEFAULT_TOLERANCE);
+        assert!(distance(100, 200, 50) > DEFAULT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_tolerance_is_validated() {
+        assert_eq!(
+            BackgroundOptions::with_tolerance(None).unwrap(),
+            BackgroundOptions::default()
+        );
+        assert_eq!(BackgroundOptions::with_tolerance(Some(40.0)).unwrap().tolerance, 40.0);
+        for bad in [0.0, 150.0, f32::NAN] {
+            let err = BackgroundOptions::with_tolerance(Some(bad)).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::InvalidInput);
+        }
+    }
+
+    fn solid_png(bg: Rgba<u8>, subject: Rgba<u8>) -> Vec<u8> {
+        let mut img = RgbaImage::from_pixel(64, 64, bg);
+        for y in 16..48 {
+            for x in 16..48 {
+                img.put_pixel(x, y, subject);
+            }
+        }
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, ImageFormat::Png).unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn test_detects_non_green_background() {
+        // A green frog on magenta: the old green-only detection failed here
+        let png = solid_png(Rgba([255, 0, 255, 255]), Rgba([60, 170, 50, 255]));
+        let processed = resize_to_emoji(&png, &BackgroundOptions::default(), &OutputOptions::default()).unwrap();
+        assert!(processed.warning.is_none());
+
+        let out = image::load_from_memory(&processed.flood_fill).unwrap().to_rgba8();
+        assert_eq!(out.get_pixel(0, 0).0[3], 0, "magenta background is removed");
+        assert_eq!(
+            *out.get_pixel(64, 64),
+            Rgba([60, 170, 50, 255]),
+            "green subject is kept"
+        );
+    }
+
+    #[test]
+    fn test_no_dominant_border_color_is_an_error() {
+        let palette = [[255, 0, 0], [0, 0, 255], [255, 255, 0], [0, 0, 0]];
+        let img = RgbaImage::from_fn(16, 16, |x, y| {
+            let [r, g, b] = palette[((x + y) % 4) as usize];
+            Rgba([r, g, b, 255])
+        });
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, ImageFormat::Png).unwrap();
+
+        let err = resize_to_emoji(
+            &png.into_inner(),
+            &BackgroundOptions::default(),
+            &OutputOptions::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind, ErrorKind::NoBackground);
+    }
+
+    #[test]
+    fn test_key_color_avoids_the_subject() {
+        let emojis = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(KeyColor::for_subject(&emojis(&["🤠", "😱"]), None), KeyColor::Green);
+        assert_eq!(KeyColor::for_subject(&emojis(&["🐸", "🤠"]), None), KeyColor::Magenta);
+        assert_eq!(KeyColor::for_subject(&emojis(&["🌵", "🌸"]), None), KeyColor::Blue);
+        assert_eq!(
+            KeyColor::for_subject(&emojis(&["🤠"]), Some("Green skin")),
+            KeyColor::Magenta
+        );
+    }
+
+    #[test]
+    fn test_renders_every_requested_size() {
+        let png = solid_png(Rgba([0, 255, 0, 255]), Rgba([220, 30, 30, 255]));
+        let output = OutputOptions::new(Some(vec![64, 512]), Some(8), None).unwrap();
+        let processed = resize_to_emoji(&png, &BackgroundOptions::default(), &output).unwrap();
+
+        let dimensions = |bytes: &[u8]| image::load_from_memory(bytes).unwrap().to_rgba8().dimensions();
+        assert_eq!(dimensions(&processed.soft_matte), (64, 64));
+        assert_eq!(processed.extra_sizes.len(), 1);
+        assert_eq!(processed.extra_sizes[0].size, 512);
+        assert_eq!(dimensions(&processed.extra_sizes[0].flood_fill), (512, 512));
+
+        // The margin stays empty
+        let main = image::load_from_memory(&processed.flood_fill).unwrap().to_rgba8();
+        assert_eq!(main.get_pixel(7, 32).0[3], 0);
+        assert_ne!(main.get_pixel(32, 32).0[3], 0);
+    }
+
+    #[test]
+    fn test_output_options_are_validated() {
+        assert_eq!(OutputOptions::new(None, None, None).unwrap(), OutputOptions::default());
+        assert_eq!(
+            OutputOptions::new(Some(vec![]), None, None).unwrap().sizes,
+            vec![DEFAULT_SIZE]
+        );
+        assert_eq!(
+            OutputOptions::new(Some(vec![8]), None, None).unwrap_err().kind,
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            OutputOptions::new(Some(vec![64]), Some(32), None).unwrap_err().kind,
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            OutputOptions::new(Some(vec![65]), Some(33), None).unwrap_err().kind,
+            ErrorKind::InvalidInput
+        );
+        assert!(OutputOptions::new(Some(vec![65]), Some(32), Some(MAX_PADDING)).is_ok());
+    }
+
+    #[test]
+    fn test_output_options_reject_huge_values() {
+        let err = OutputOptions::new(None, Some(u32::MAX), None).unwrap_err();
+        assert!(err.message.contains("Margin"), "{}", err.message);
+        let err = OutputOptions::new(None, None, Some(u32::MAX)).unwrap_err();
+        assert!(err.message.contains("Padding"), "{}", err.message);
+
+        // Cropping itself never overflows, whatever the padding
+        let rgba = RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 255]));
+        assert_eq!(crop_padded(&rgba, [2, 2, 5, 5], u32::MAX).dimensions(), (8, 8));
+    }
+
+    #[test]
+    fn test_cut_out_frames_share_one_crop() {
+        let frame = |x: u32| {
+            RgbaImage::from_fn(64, 64, |px, py| {
+                if (x..x + 16).contains(&px) && (24..40).contains(&py) {
+                    Rgba([220, 30, 30, 255])
+                } else {
+                    Rgba([0, 255, 0, 255])
+                }
+            })
+        };
+        let (frames, _) = cut_out_frames(
+            &[frame(8), frame(40)],
+            &BackgroundOptions::default(),
+            &OutputOptions::default(),
+        )
+        .unwrap();
+
+        // The subject moves across the canvas instead of being re-centered in each frame
+        let bounds: Vec<_> = frames.iter().map(|f| content_bounds(f).unwrap()).collect();
+        assert!(bounds[0][0] < bounds[1][0]);
+        assert!(frames.iter().all(|f| f.dimensions() == (DEFAULT_SIZE, DEFAULT_SIZE)));
+
+        // Frames that are already transparent aren't keyed again
+        let (again, warning) =
+            cut_out_frames(&frames, &BackgroundOptions::default(), &OutputOptions::default()).unwrap();
+        assert!(warning.is_none());
+        assert!(again[0].pixels().any(|p| p.0 == [220, 30, 30, 255]));
+    }
+}
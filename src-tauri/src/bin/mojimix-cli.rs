@@ -0,0 +1,696 @@
+//! Headless front end for scripts and CI: generate, process and name emojis
+//! without a display. Uses the same pipeline as the desktop app.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use clap::{Args, Parser, Subcommand};
+
+use mojimix::animation::{self, AnimationFormat, AnimationOptions};
+use mojimix::background::{BackgroundOptions, OutputOptions};
+use mojimix::cancel::{self, CancelToken};
+use mojimix::effects::Effect;
+use mojimix::error::{Error, ErrorKind, Result};
+use mojimix::generation::{self, GenerationProgress, GenerationRun, SlotStatus};
+use mojimix::profiles::{self, Credentials, ProfileStore, Reservation};
+use mojimix::usage::{Usage, UsageRecord, UsageStore};
+use mojimix::{api_key, gemini, output, process, provider, retry};
+
+/// Largest `--max-kb`, far above any chat app's limit
+const MAX_KB: u64 = 10 * 1024;
+
+#[derive(Parser)]
+#[command(
+    name = "mojimix-cli",
+    version,
+    about = "Generate emoji mashups from the command line"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate emoji mashups and write the processed PNGs
+    Generate {
+        /// Unicode emojis to combine
+        emojis: Vec<String>,
+        /// Extra style or modification, e.g. "pixel art"
+        #[arg(short, long)]
+        modifier: Option<String>,
+        /// Local PNG/GIF custom emoji to mix in (repeatable)
+        #[arg(short, long = "reference")]
+        references: Vec<PathBuf>,
+        /// Number of images to generate
+        #[arg(short = 'n', long, default_value_t = 4)]
+        count: usize,
+        /// Use the faster, cheaper model
+        #[arg(long)]
+        fast: bool,
+        /// Model id, overriding --fast
+        #[arg(long)]
+        model: Option<String>,
+        #[arg(long, default_value = provider::DEFAULT_PROVIDER)]
+        provider: String,
+        /// File name stem; asks Gemini for one if omitted
+        #[arg(long)]
+        name: Option<String>,
+        /// Also write the raw image returned by the provider
+        #[arg(long)]
+        raw: bool,
+        #[command(flatten)]
+        processing: ProcessingArgs,
+        /// Output directory
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Remove the background of existing images and fit them to 128x128. Needs no API key.
+    Process {
+        /// An image file, or a directory of images
+        input: PathBuf,
+        /// Output directory; defaults to the input's directory, or <dir>/processed for a directory
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    /// Generate an animated emoji, or animate an existing image with --input
+    Animate {
+        /// Unicode emojis to combine
+        emojis: Vec<String>,
+        /// Animate this image locally instead of generating one; needs --effect and no API key
+        #[arg(short, long, conflicts_with_all = ["motion", "emojis"])]
+        input: Option<PathBuf>,
+        /// Local effect: shake, spin, pulse, rainbow, bounce, zoom_in, wave, intensifies or conveyor
+        #[arg(short, long)]
+        effect: Option<Effect>,
+        /// Motion for generated frames, e.g. "waving its hand"; used when no --effect is given
+        #[arg(long)]
+        motion: Option<String>,
+        /// Frames per loop
+        #[arg(long)]
+        frames: Option<usize>,
+        /// Output format: gif, apng or webp
+        #[arg(short, long, default_value = "gif")]
+        format: AnimationFormat,
+        /// Delay between frames in milliseconds
+        #[arg(long, default_value_t = animation::DEFAULT_FRAME_DELAY_MS)]
+        delay: u32,
+        /// File size limit in KB (1-10240); 128 fits Slack, 256 Discord
+        #[arg(
+            long,
+            default_value_t = animation::SLACK_MAX_BYTES / 1024,
+            value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=MAX_KB)
+        )]
+        max_kb: usize,
+        #[arg(short, long)]
+        modifier: Option<String>,
+        #[arg(long)]
+        fast: bool,
+        #[arg(long)]
+        model: Option<String>,
+        #[arg(long, default_value = provider::DEFAULT_PROVIDER)]
+        provider: String,
+        /// File name stem; defaults to the input's name, or asks Gemini
+        #[arg(long)]
+        name: Option<String>,
+        #[command(flatten)]
+        processing: ProcessingArgs,
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Suggest a file name for an emoji combination
+    Name {
+        #[arg(required = true)]
+        emojis: Vec<String>,
+        #[arg(short, long)]
+        modifier: Option<String>,
+    },
+}
+
+/// Background removal and output settings shared by `generate` and `process`
+#[derive(Args)]
+struct ProcessingArgs {
+    /// Background color tolerance (perceptual ΔE, 1-100); lower keeps more of greenish subjects
+    #[arg(short, long)]
+    tolerance: Option<f32>,
+    /// Canvas size in pixels (repeatable); the first size is written unsuffixed, others as *_<size>.png
+    #[arg(short, long = "size")]
+    sizes: Vec<u32>,
+    /// Empty space between the emoji and the canvas edges, in pixels
+    #[arg(long)]
+    margin: Option<u32>,
+    /// Source pixels kept around the emoji when cropping (at most 2048)
+    #[arg(long)]
+    padding: Option<u32>,
+}
+
+impl ProcessingArgs {
+    fn options(&self) -> Result<(BackgroundOptions, OutputOptions)> {
+        Ok((
+            BackgroundOptions::with_tolerance(self.tolerance)?,
+            OutputOptions::new(Some(self.sizes.clone()), self.margin, self.padding)?,
+        ))
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    if let Err(e) = api_key::migrate() {
+        eprintln!("warning: failed to migrate stored API keys: {}", e);
+    }
+    let result = match Cli::parse().command {
+        Command::Generate {
+            emojis,
+            modifier,
+            references,
+            count,
+            fast,
+            model,
+            provider,
+            name,
+            raw,
+            processing,
+            out,
+        } => {
+            let options = GenerateOptions {
+                provider,
+                model,
+                fast,
+                count,
+                name,
+                raw,
+                processing,
+            };
+            generate(&emojis, modifier.as_deref(), &references, &options, &out).await
+        }
+        Command::Process { input, out, processing } => process(&input, out.as_deref(), &processing),
+        Command::Animate {
+            emojis,
+            input,
+            effect,
+            motion,
+            frames,
+            format,
+            delay,
+            max_kb,
+            modifier,
+            fast,
+            model,
+            provider,
+            name,
+            processing,
+            out,
+        } => {
+            let options = AnimateOptions {
+                input,
+                effect,
+                motion,
+                frames,
+                format,
+                delay,
+                max_kb,
+                provider,
+                model,
+                fast,
+                name,
+                processing,
+            };
+            animate(&emojis, modifier.as_deref(), &options, &out).await
+        }
+        Command::Name { emojis, modifier } => name(&emojis, modifier.as_deref()).await,
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct GenerateOptions {
+    provider: String,
+    model: Option<String>,
+    fast: bool,
+    count: usize,
+    name: Option<String>,
+    raw: bool,
+    processing: ProcessingArgs,
+}
+
+async fn generate(
+    emojis: &[String],
+    modifier: Option<&str>,
+    references: &[PathBuf],
+    options: &GenerateOptions,
+    out: &Path,
+) -> Result<()> {
+    let (background, output) = options.processing.options()?;
+    let profiles = ProfileStore::open_default();
+    let credentials = profiles::credentials(&profiles)?;
+    let provider = provider::create_provider(&options.provider, &credentials.api_key)?;
+    let provider = provider.as_ref();
+    let model = provider.resolve_model(
+        options.model.as_deref().or(credentials.default_model.as_deref()),
+        options.fast,
+    )?;
+    let count = options.count.min(provider.capabilities().max_images);
+    let prompt = generation::prepare_prompt(emojis, modifier, references)?;
+    // Held against the profile's daily budget like a generation in the app
+    let reservation = reserve(&profiles, &credentials, count)?;
+
+    let generation_id = cancel::new_generation_id();
+    let stem = match &options.name {
+        Some(name) => output::sanitize_filename(name),
+        None => suggest_name(&credentials, &generation_id, emojis, modifier).await,
+    };
+    fs::create_dir_all(out).map_err(|e| Error::from(e).context("Failed to create output directory"))?;
+
+    // Ctrl-C stops outstanding requests; finished images are already on disk
+    let run = GenerationRun {
+        id: generation_id,
+        policy: retry::RetryPolicy::default(),
+        cancel: cancel_on_ctrl_c(),
+        background,
+        output,
+    };
+
+    let write_failures = AtomicUsize::new(0);
+    // The provider billed for an image even when background removal failed
+    let generated = AtomicUsize::new(0);
+    let slots: Vec<usize> = (0..count).collect();
+    let result = generation::generate_slots(provider, &prompt, &model, &slots, &run, &|progress| {
+        if progress.status == SlotStatus::Success || progress.raw_image.is_some() {
+            generated.fetch_add(1, Ordering::Relaxed);
+        }
+        report_progress(&progress);
+        if let Some(slot_usage) = progress.usage {
+            record_usage(&run.id, &model, &credentials, slot_usage);
+        }
+        if progress.status == SlotStatus::Success
+            && let Some(index) = progress.index
+            && let Err(e) = write_slot(out, &stem, index, &progress, options.raw)
+        {
+            eprintln!("[{}] {}", index, e);
+            write_failures.fetch_add(1, Ordering::SeqCst);
+        }
+    })
+    .await;
+    finish_reservation(reservation, generated.into_inner());
+    let result = result?;
+
+    // Partial failures were reported per slot above; make them fail the command too
+    let failed = result.results.iter().filter(|r| r.error.is_some()).count() + write_failures.into_inner();
+    if failed > 0 {
+        return Err(Error::new(
+            ErrorKind::ApiError,
+            format!("{} of {} images failed", failed, count),
+        ));
+    }
+    Ok(())
+}
+
+/// A token cancelled when the user presses Ctrl-C
+fn cancel_on_ctrl_c() -> Arc<CancelToken> {
+    let token = Arc::new(CancelToken::default());
+    tokio::spawn({
+        let token = token.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                token.cancel();
+            }
+        }
+    });
+    token
+}
+
+/// Progress goes to stderr so stdout only lists the written files
+fn report_progress(progress: &GenerationProgress) {
+    // The generation-wide `Started` event has nothing to report
+    let Some(index) = progress.index else { return };
+    match progress.status {
+        SlotStatus::Started | SlotStatus::Pending | SlotStatus::Success => {}
+        SlotStatus::Retrying => eprintln!(
+            "[{}] attempt {} failed, retrying in {} ms: {}",
+            index,
+            progress.attempt.unwrap_or_default(),
+            progress.retry_in_ms.unwrap_or_default(),
+            progress.error.as_ref().map(|e| e.message.as_str()).unwrap_or_default()
+        ),
+        SlotStatus::Error | SlotStatus::Cancelled => eprintln!(
+            "[{}] failed: {}",
+            index,
+            progress
+                .error
+                .as_ref()
+                .map(|e| e.message.as_str())
+                .unwrap_or("unknown error")
+        ),
+    }
+    if let Some(warning) = &progress.warning {
+        eprintln!("[{}] warning: {}", index, warning);
+    }
+}
+
+fn write_slot(out: &Path, stem: &str, index: usize, progress: &GenerationProgress, raw: bool) -> Result<()> {
+    let mut images = vec![
+        ("flood_fill".to_string(), progress.flood_fill.as_ref()),
+        ("color_key".to_string(), progress.color_key.as_ref()),
+        ("soft_matte".to_string(), progress.soft_matte.as_ref()),
+    ];
+    for sized in &progress.extra_sizes {
+        images.push((format!("flood_fill_{}", sized.size), Some(&sized.flood_fill)));
+        images.push((format!("color_key_{}", sized.size), Some(&sized.color_key)));
+        images.push((format!("soft_matte_{}", sized.size), Some(&sized.soft_matte)));
+    }
+    if raw {
+        images.push(("raw".to_string(), progress.raw_image.as_ref()));
+    }
+    for (variant, data) in images {
+        let Some(data) = data else { continue };
+        let bytes = STANDARD
+            .decode(data)
+            .map_err(|e| Error::new(ErrorKind::InvalidResponse, format!("Invalid image data: {}", e)))?;
+        let path = output::write_unique(out, &format!("{}_{}_{}", stem, index, variant), "png", &bytes)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Hold `images` against the active profile's daily budget, if there is one
+fn reserve<'a>(
+    profiles: &'a ProfileStore,
+    credentials: &Credentials,
+    images: usize,
+) -> Result<Option<Reservation<'a>>> {
+    credentials
+        .profile
+        .as_deref()
+        .map(|profile| profiles.reserve(profile, images))
+        .transpose()
+}
+
+/// Count the images the provider returned against the profile's budget
+fn finish_reservation(reservation: Option<Reservation<'_>>, generated: usize) {
+    if let Some(reservation) = reservation
+        && let Err(e) = reservation.finish(generated)
+    {
+        eprintln!("warning: failed to record profile usage: {}", e);
+    }
+}
+
+/// Append what a request consumed to the usage log, like the desktop app
+fn record_usage(generation_id: &str, model: &str, credentials: &Credentials, usage: Usage) {
+    let record = UsageRecord::new(generation_id, model, credentials.profile.as_deref(), usage);
+    if let Err(e) = UsageStore::open_default().record(&record) {
+        eprintln!("warning: {}", e);
+    }
+}
+
+/// Ask Gemini for a file name, falling back to "emoji" like the desktop app.
+/// The request is recorded as part of `generation_id`.
+async fn suggest_name(
+    credentials: &Credentials,
+    generation_id: &str,
+    emojis: &[String],
+    modifier: Option<&str>,
+) -> String {
+    if emojis.is_empty() {
+        return "emoji".to_string();
+    }
+    let client = gemini::GeminiClient::new(&credentials.api_key);
+    let name = match gemini::generate_filename(&client, emojis, modifier).await {
+        Ok(suggested) => {
+            record_usage(generation_id, gemini::MODEL_TEXT, credentials, suggested.usage);
+            suggested.name
+        }
+        Err(e) => {
+            if let Some(usage) = e.usage {
+                record_usage(generation_id, gemini::MODEL_TEXT, credentials, usage);
+            }
+            String::new()
+        }
+    };
+    match output::sanitize_filename(&name) {
+        name if name.is_empty() => "emoji".to_string(),
+        name => name,
+    }
+}
+
+fn process(input: &Path, out: Option<&Path>, processing: &ProcessingArgs) -> Result<()> {
+    let (options, output) = processing.options()?;
+    let results = process::process_path(input, out, &options, &output)?;
+
+    let mut failed = 0;
+    for result in &results {
+        let name = result.input.display();
+        if let Some(warning) = &result.warning {
+            eprintln!("{}: warning: {}", name, warning);
+        }
+        if let Some(e) = &result.error {
+            eprintln!("{}: {}", name, e);
+            failed += 1;
+        }
+        let main = [&result.flood_fill, &result.color_key, &result.soft_matte]
+            .into_iter()
+            .flatten();
+        for path in main.chain(&result.extra_sizes) {
+            println!("{}", path.display());
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::new(
+            ErrorKind::ImageProcessing,
+            format!("{} of {} images failed", failed, results.len()),
+        ));
+    }
+    Ok(())
+}
+
+struct AnimateOptions {
+    input: Option<PathBuf>,
+    effect: Option<Effect>,
+    motion: Option<String>,
+    frames: Option<usize>,
+    format: AnimationFormat,
+    delay: u32,
+    max_kb: usize,
+    provider: String,
+    model: Option<String>,
+    fast: bool,
+    name: Option<String>,
+    processing: ProcessingArgs,
+}
+
+async fn animate(emojis: &[String], modifier: Option<&str>, options: &AnimateOptions, out: &Path) -> Result<()> {
+    let (background, output) = options.processing.options()?;
+    let animation_options = AnimationOptions {
+        format: options.format,
+        frames: options.frames,
+        delay_ms: options.delay,
+        output: output.clone(),
+        max_bytes: Some(options.max_kb * 1024),
+    };
+
+    let (animation, stem) = if let Some(input) = &options.input {
+        let effect = options
+            .effect
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "--input needs an --effect"))?;
+        let bytes = fs::read(input).map_err(|e| Error::from(e).context("Failed to read input"))?;
+        let stem = match &options.name {
+            Some(name) => output::sanitize_filename(name),
+            None => input
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "emoji".to_string()),
+        };
+        let animation = animation::animate_image(&bytes, effect, &animation_options, &background)?;
+        (animation, format!("{}_{}", stem, effect.name()))
+    } else {
+        let profiles = ProfileStore::open_default();
+        let credentials = profiles::credentials(&profiles)?;
+        let provider = provider::create_provider(&options.provider, &credentials.api_key)?;
+        let provider = provider.as_ref();
+        let model = provider.resolve_model(
+            options.model.as_deref().or(credentials.default_model.as_deref()),
+            options.fast,
+        )?;
+        let prompt = generation::prepare_prompt(emojis, modifier, &[] as &[PathBuf])?;
+        let reservation = reserve(
+            &profiles,
+            &credentials,
+            animation_options.generated_images(options.effect)?,
+        )?;
+        let generation_id = cancel::new_generation_id();
+        let stem = match &options.name {
+            Some(name) => output::sanitize_filename(name),
+            None => suggest_name(&credentials, &generation_id, emojis, modifier).await,
+        };
+        // Ctrl-C stops outstanding frames, which are reported as cancelled
+        let run = GenerationRun {
+            id: generation_id,
+            policy: retry::RetryPolicy::default(),
+            cancel: cancel_on_ctrl_c(),
+            background,
+            output,
+        };
+        // Every image the provider returned is billed, even if a later frame fails
+        let generated = AtomicUsize::new(0);
+        let animation = animation::generate_animation(
+            provider,
+            &prompt,
+            &model,
+            options.motion.as_deref(),
+            options.effect,
+            &animation_options,
+            &run,
+            &|progress| {
+                if progress.status == SlotStatus::Success {
+                    generated.fetch_add(1, Ordering::Relaxed);
+                }
+                report_progress(&progress);
+                if let Some(frame_usage) = progress.usage {
+                    record_usage(&run.id, &model, &credentials, frame_usage);
+                }
+            },
+        )
+        .await;
+        finish_reservation(reservation, generated.into_inner());
+        (animation?, stem)
+    };
+
+    if let Some(warning) = &animation.warning {
+        eprintln!("warning: {}", warning);
+    }
+    fs::create_dir_all(out).map_err(|e| Error::from(e).context("Failed to create output directory"))?;
+    let path = output::write_unique(out, &stem, options.format.extension(), &animation.data)?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+async fn name(emojis: &[String], modifier: Option<&str>) -> Result<()> {
+    let credentials = profiles::credentials(&ProfileStore::open_default())?;
+    let client = gemini::GeminiClient::new(&credentials.api_key);
+    let generation_id = cancel::new_generation_id();
+    let suggested = gemini::generate_filename(&client, emojis, modifier)
+        .await
+        .inspect_err(|e| {
+            if let Some(usage) = e.usage {
+                record_usage(&generation_id, gemini::MODEL_TEXT, &credentials, usage);
+            }
+        })?;
+    record_usage(&generation_id, gemini::MODEL_TEXT, &credentials, suggested.usage);
+    println!("{}", output::sanitize_filename(&suggested.name));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_generate() {
+        let cli = Cli::try_parse_from([
+            "mojimix-cli",
+            "generate",
+            "🤠",
+            "😱",
+            "-m",
+            "pixel art",
+            "-n",
+            "2",
+            "--out",
+            "dist",
+        ])
+        .unwrap();
+        let Command::Generate {
+            emojis,
+            modifier,
+            count,
+            out,
+            ..
+        } = cli.command
+        else {
+            panic!("expected generate");
+        };
+        assert_eq!(emojis, vec!["🤠", "😱"]);
+        assert_eq!(modifier.as_deref(), Some("pixel art"));
+        assert_eq!(count, 2);
+        assert_eq!(out, PathBuf::from("dist"));
+    }
+
+    #[test]
+    fn test_parse_process_options() {
+        let cli = Cli::try_parse_from([
+            "mojimix-cli",
+            "process",
+            "frog.png",
+            "--tolerance",
+            "12.5",
+            "-s",
+            "128",
+            "-s",
+            "512",
+        ])
+        .unwrap();
+        let Command::Process { input, processing, .. } = cli.command else {
+            panic!("expected process");
+        };
+        assert_eq!(input, PathBuf::from("frog.png"));
+        assert_eq!(processing.tolerance, Some(12.5));
+        assert_eq!(processing.sizes, vec![128, 512]);
+    }
+
+    #[test]
+    fn test_parse_animate() {
+        let cli = Cli::try_parse_from([
+            "mojimix-cli",
+            "animate",
+            "--input",
+            "cowboy.png",
+            "-e",
+            "spin",
+            "-f",
+            "webp",
+            "--max-kb",
+            "256",
+        ])
+        .unwrap();
+        let Command::Animate {
+            input,
+            effect,
+            format,
+            max_kb,
+            ..
+        } = cli.command
+        else {
+            panic!("expected animate");
+        };
+        assert_eq!(input, Some(PathBuf::from("cowboy.png")));
+        assert_eq!(effect, Some(Effect::Spin));
+        assert_eq!(format, AnimationFormat::Webp);
+        assert_eq!(max_kb, 256);
+
+        for max_kb in ["0", "10241", "18446744073709551615"] {
+            assert!(
+                Cli::try_parse_from(["mojimix-cli", "animate", "--max-kb", max_kb]).is_err(),
+                "{}",
+                max_kb
+            );
+        }
+    }
+}
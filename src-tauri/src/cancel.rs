@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::history::now_millis;
+
+/// Signal shared by all slots of a generation to stop early
+#[derive(Default)]
+pub struct CancelToken {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once `cancel` has been called
+    pub async fn cancelled(&self) {
+        loop {
+            // Register before checking the flag so a cancel in between isn't missed
+            let notified = self.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Generations that are still running, by id. Managed as Tauri state so
+/// `cancel_generation` can reach them.
+#[derive(Default)]
+pub struct ActiveGenerations {
+    tokens: Mutex<HashMap<String, Arc<CancelToken>>>,
+}
+
+impl ActiveGenerations {
+    /// Register a new generation. It stays cancellable until the guard is dropped.
+    pub fn start(&self) -> ActiveGeneration<'_> {
+        self.register(new_generation_id())
+    }
+
+    /// Register more work under an existing generation id, e.g. regenerating one
+    /// slot. Fails if that generation is still running.
+    pub fn resume(&self, id: &str) -> Result<ActiveGeneration<'_>> {
+        if self.tokens.lock().unwrap().contains_key(id) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Generation {} is still running", id),
+            ));
+        }
+        Ok(self.register(id.to_string()))
+    }
+
+    fn register(&self, id: String) -> ActiveGeneration<'_> {
+        let token = Arc::new(CancelToken::default());
+        self.tokens.lock().unwrap().insert(id.clone(), token.clone());
+        ActiveGeneration {
+            registry: self,
+            id,
+            token,
+        }
+    }
+
+    /// Cancel a running generation. Returns false if it already finished or never existed.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.tokens.lock().unwrap().get(id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub struct ActiveGeneration<'a> {
+    registry: &'a ActiveGenerations,
+    pub id: String,
+    pub token: Arc<CancelToken>,
+}
+
+impl Drop for ActiveGeneration<'_> {
+    fn drop(&mut self) {
+        self.registry.tokens.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Unique, roughly time-ordered id for a generation
+pub fn new_generation_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!("{:x}-{:x}", now_millis(), COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_cancel_and_cleanup() {
+        let registry = ActiveGenerations::default();
+        let generation = registry.start();
+        let id = generation.id.clone();
+
+        assert!(registry.cancel(&id));
+        assert!(generation.token.is_cancelled());
+
+        drop(generation);
+        assert!(!registry.cancel(&id), "finished generations are forgotten");
+    }
+
+    #[test]
+    fn test_resume_rejects_running_generation() {
+        let registry = ActiveGenerations::default();
+        let generation = registry.start();
+        assert!(registry.resume(&generation.id).is_err());
+
+        let id = generation.id.clone();
+        drop(generation);
+        let resumed = registry.resume(&id).unwrap();
+        assert!(registry.cancel(&resumed.id));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_resolves_after_cancel() {
+        let token = Arc::new(CancelToken::default());
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        token.cancel();
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiter)
+            .await
+            .expect("cancelled() should resolve")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_generation_ids_are_unique() {
+        assert_ne!(new_generation_id(), new_generation_id());
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::usage::ModelPrice;
+
+/// Get the MojiMix config directory (~/.config/mojimix on Linux)
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|p| p.join("mojimix"))
+}
+
+/// Get the path to the settings file
+fn settings_path() -> Option<PathBuf> {
+    config_dir().map(|p| p.join("config.json"))
+}
+
+/// Optional settings read from config.json. Every field has a default, so a
+/// missing or partial file is fine.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Override for the Gemini API base URL (e.g. a proxy or local mock)
+    pub gemini_base_url: Option<String>,
+    /// Override for the Slack Web API base URL
+    pub slack_base_url: Option<String>,
+    /// Override for the Discord REST API base URL
+    pub discord_base_url: Option<String>,
+    /// Discord server that uploads go to when none is given
+    pub discord_guild_id: Option<String>,
+    /// Prices per model id, replacing or adding to the built-in table
+    pub prices: BTreeMap<String, ModelPrice>,
+}
+
+impl Settings {
+    /// Load settings from disk, falling back to defaults if the file is missing or invalid
+    pub fn load() -> Self {
+        settings_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Read a setting from an environment variable, ignoring empty values
+pub fn env_override(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
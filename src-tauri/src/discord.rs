@@ -0,0 +1,674 @@
+//! Upload finished emojis and stickers to a Discord server (guild) through
+//! the REST API.
+
+use std::io::Cursor;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, Frames, RgbaImage};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{self, AnimationFormat, DEFAULT_FRAME_DELAY_MS, DISCORD_MAX_BYTES};
+use crate::config::{self, Settings};
+use crate::error::{Error, ErrorKind, Result};
+use crate::retry;
+
+const DEFAULT_BASE_URL: &str = "https://discord.com/api/v10";
+
+/// Environment variable that overrides the API base URL
+const BASE_URL_ENV: &str = "MOJIMIX_DISCORD_BASE_URL";
+
+/// Allowed emoji name lengths
+const NAME_LEN: std::ops::RangeInclusive<usize> = 2..=32;
+
+/// Allowed sticker name lengths
+const STICKER_NAME_LEN: std::ops::RangeInclusive<usize> = 2..=30;
+
+/// Longest tag list Discord accepts for a sticker
+const STICKER_TAGS_MAX_LEN: usize = 200;
+
+/// Discord's sticker file size limit
+pub const STICKER_MAX_BYTES: usize = 512 * 1024;
+
+/// JSON error codes for a full emoji or sticker list
+const MAX_EMOJIS: u64 = 30008;
+const MAX_ANIMATED_EMOJIS: u64 = 30018;
+const MAX_STICKERS: u64 = 30039;
+
+/// JSON error code for a bot without Manage Expressions
+const MISSING_PERMISSIONS: u64 = 50013;
+
+/// Resolve the API base URL: env var first, then config.json, then the public endpoint
+pub fn base_url() -> String {
+    config::env_override(BASE_URL_ENV)
+        .or_else(|| Settings::load().discord_base_url)
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+}
+
+pub struct DiscordClient {
+    http: Client,
+    base_url: String,
+    token: String,
+}
+
+impl DiscordClient {
+    /// Create a client using the configured base URL
+    pub fn new(token: &str) -> Self {
+        Self::with_base_url(token, &base_url())
+    }
+
+    pub fn with_base_url(token: &str, base_url: &str) -> Self {
+        Self {
+            http: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.http
+            .get(format!("{}{}", self.base_url, path))
+            .header("Authorization", format!("Bot {}", self.token))
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.http
+            .post(format!("{}{}", self.base_url, path))
+            .header("Authorization", format!("Bot {}", self.token))
+    }
+}
+
+/// An emoji as stored in the guild
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscordEmoji {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub animated: bool,
+}
+
+/// A sticker as stored in the guild
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscordSticker {
+    pub id: String,
+    pub name: String,
+    /// Comma-separated autocomplete keywords
+    #[serde(default)]
+    pub tags: String,
+}
+
+#[derive(Deserialize)]
+struct Guild {
+    #[serde(default)]
+    premium_tier: u8,
+}
+
+#[derive(Serialize)]
+struct CreateEmoji<'a> {
+    name: &'a str,
+    /// Data URI of the image
+    image: String,
+    roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct DiscordErrorBody {
+    #[serde(default)]
+    code: u64,
+    #[serde(default)]
+    message: String,
+    /// Seconds to wait after a 429
+    retry_after: Option<f64>,
+}
+
+/// An image converted to a format Discord accepts
+struct PreparedImage {
+    data: Vec<u8>,
+    mime_type: &'static str,
+    animated: bool,
+}
+
+/// Check an emoji name against Discord's rules (2-32 letters, digits or
+/// underscores) and return it without surrounding colons
+pub fn validate_name(name: &str) -> Result<String> {
+    let name = name.trim().trim_matches(':');
+    if !NAME_LEN.contains(&name.len()) || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid emoji name '{}': use 2-32 letters, numbers and underscores",
+                name
+            ),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Check a sticker name against Discord's rules (2-30 characters) and return it trimmed
+pub fn validate_sticker_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if !STICKER_NAME_LEN.contains(&name.chars().count()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid sticker name '{}': use 2-30 characters", name),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+fn validate_guild_id(guild_id: &str) -> Result<()> {
+    if guild_id.is_empty() || !guild_id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid server ID '{}'", guild_id),
+        ));
+    }
+    Ok(())
+}
+
+/// Emoji slots per type (static and animated each) for a server boost tier
+fn slot_limit(premium_tier: u8) -> usize {
+    match premium_tier {
+        0 => 50,
+        1 => 100,
+        2 => 150,
+        _ => 250,
+    }
+}
+
+/// Sticker slots for a server boost tier
+fn sticker_slot_limit(premium_tier: u8) -> usize {
+    match premium_tier {
+        0 => 5,
+        1 => 15,
+        2 => 30,
+        _ => 60,
+    }
+}
+
+/// Whether a GIF has more than one frame; Discord files single-frame GIFs as static
+fn is_animated_gif(bytes: &[u8]) -> Result<bool> {
+    let frames = GifDecoder::new(Cursor::new(bytes))?.into_frames().take(2);
+    Ok(frames.collect::<std::result::Result<Vec<_>, _>>()?.len() > 1)
+}
+
+/// Convert to a format Discord accepts: PNG, JPEG and GIF pass through;
+/// animated PNG and WebP become GIF, which Discord animates, and other
+/// images become PNG
+fn prepare_image(bytes: &[u8]) -> Result<PreparedImage> {
+    let format = image::guess_format(bytes).map_err(|e| Error::from(e).context("Unrecognized image"))?;
+    let prepared = match format {
+        image::ImageFormat::Gif => PreparedImage {
+            data: bytes.to_vec(),
+            mime_type: "image/gif",
+            animated: is_animated_gif(bytes)?,
+        },
+        image::ImageFormat::Jpeg => PreparedImage {
+            data: bytes.to_vec(),
+            mime_type: "image/jpeg",
+            animated: false,
+        },
+        image::ImageFormat::Png => {
+            let decoder = PngDecoder::new(Cursor::new(bytes))?;
+            if decoder.is_apng()? {
+                reencode(decoder.apng()?.into_frames(), AnimationFormat::Gif)?
+            } else {
+                PreparedImage {
+                    data: bytes.to_vec(),
+                    mime_type: "image/png",
+                    animated: false,
+                }
+            }
+        }
+        image::ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+            if decoder.has_animation() {
+                reencode(decoder.into_frames(), AnimationFormat::Gif)?
+            } else {
+                to_png(image::load_from_memory(bytes)?.to_rgba8())?
+            }
+        }
+        _ => to_png(image::load_from_memory(bytes)?.to_rgba8())?,
+    };
+    check_size(prepared, DISCORD_MAX_BYTES)
+}
+
+/// Convert to a format Discord accepts for stickers: PNG, APNG and GIF pass
+/// through; animated WebP becomes APNG and other images become PNG
+fn prepare_sticker(bytes: &[u8]) -> Result<PreparedImage> {
+    let format = image::guess_format(bytes).map_err(|e| Error::from(e).context("Unrecognized image"))?;
+    let prepared = match format {
+        image::ImageFormat::Gif => PreparedImage {
+            data: bytes.to_vec(),
+            mime_type: "image/gif",
+            animated: is_animated_gif(bytes)?,
+        },
+        image::ImageFormat::Png => PreparedImage {
+            data: bytes.to_vec(),
+            mime_type: "image/png",
+            animated: PngDecoder::new(Cursor::new(bytes))?.is_apng()?,
+        },
+        image::ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes))?;
+            if decoder.has_animation() {
+                reencode(decoder.into_frames(), AnimationFormat::Apng)?
+            } else {
+                to_png(image::load_from_memory(bytes)?.to_rgba8())?
+            }
+        }
+        _ => to_png(image::load_from_memory(bytes)?.to_rgba8())?,
+    };
+    check_size(prepared, STICKER_MAX_BYTES)
+}
+
+fn check_size(prepared: PreparedImage, max_bytes: usize) -> Result<PreparedImage> {
+    if prepared.data.len() > max_bytes {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Image is {} KB, Discord's limit is {} KB",
+                prepared.data.len().div_ceil(1024),
+                max_bytes / 1024
+            ),
+        ));
+    }
+    Ok(prepared)
+}
+
+fn to_png(image: RgbaImage) -> Result<PreparedImage> {
+    let mut data = Cursor::new(Vec::new());
+    image.write_to(&mut data, image::ImageFormat::Png)?;
+    Ok(PreparedImage {
+        data: data.into_inner(),
+        mime_type: "image/png",
+        animated: false,
+    })
+}
+
+/// Re-encode decoded animation frames as a GIF or APNG, keeping the first frame's delay
+fn reencode(frames: Frames<'_>, format: AnimationFormat) -> Result<PreparedImage> {
+    let frames = frames.collect_frames()?;
+    let delay_ms = frames
+        .first()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            numer / denom.max(1)
+        })
+        .filter(|&ms| ms > 0)
+        .unwrap_or(DEFAULT_FRAME_DELAY_MS);
+    let images: Vec<RgbaImage> = frames.into_iter().map(|frame| frame.into_buffer()).collect();
+    Ok(PreparedImage {
+        data: animation::encode(&images, delay_ms, format)?,
+        // Discord takes APNG as PNG
+        mime_type: match format {
+            AnimationFormat::Gif => "image/gif",
+            _ => "image/png",
+        },
+        animated: images.len() > 1,
+    })
+}
+
+/// Check that the guild has a free slot of the right type and no emoji named `name`
+async fn check_slots(client: &DiscordClient, guild_id: &str, name: &str, animated: bool) -> Result<()> {
+    let guild: Guild = send(client.get(&format!("/guilds/{}", guild_id))).await?;
+    let emojis: Vec<DiscordEmoji> = send(client.get(&format!("/guilds/{}/emojis", guild_id))).await?;
+
+    if emojis.iter().any(|emoji| emoji.name == name) {
+        return Err(Error::new(
+            ErrorKind::NameTaken,
+            format!("The server already has an emoji named :{}:", name),
+        ));
+    }
+    let used = emojis.iter().filter(|emoji| emoji.animated == animated).count();
+    let limit = slot_limit(guild.premium_tier);
+    if used >= limit {
+        let kind = if animated { "animated" } else { "static" };
+        return Err(Error::new(
+            ErrorKind::LimitReached,
+            format!(
+                "The server has no free {} emoji slots ({} of {} used)",
+                kind, used, limit
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Add `image` to the guild as `:name:`, converting it to a format Discord accepts
+pub async fn upload_emoji(client: &DiscordClient, guild_id: &str, name: &str, image: &[u8]) -> Result<DiscordEmoji> {
+    let name = validate_name(name)?;
+    validate_guild_id(guild_id)?;
+    let prepared = prepare_image(image)?;
+    check_slots(client, guild_id, &name, prepared.animated).await?;
+
+    let body = CreateEmoji {
+        name: &name,
+        image: format!("data:{};base64,{}", prepared.mime_type, STANDARD.encode(&prepared.data)),
+        roles: Vec::new(),
+    };
+    send(client.post(&format!("/guilds/{}/emojis", guild_id)).json(&body)).await
+}
+
+/// Check that the guild has a free sticker slot and no sticker named `name`
+async fn check_sticker_slots(client: &DiscordClient, guild_id: &str, name: &str) -> Result<()> {
+    let guild: Guild = send(client.get(&format!("/guilds/{}", guild_id))).await?;
+    let stickers: Vec<DiscordSticker> = send(client.get(&format!("/guilds/{}/stickers", guild_id))).await?;
+
+    if stickers.iter().any(|sticker| sticker.name == name) {
+        return Err(Error::new(
+            ErrorKind::NameTaken,
+            format!("The server already has a sticker named '{}'", name),
+        ));
+    }
+    let limit = sticker_slot_limit(guild.premium_tier);
+    if stickers.len() >= limit {
+        return Err(Error::new(
+            ErrorKind::LimitReached,
+            format!(
+                "The server has no free sticker slots ({} of {} used)",
+                stickers.len(),
+                limit
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Add `image` to the guild as a sticker called `name`, converting it to a
+/// format Discord accepts. `tags` are autocomplete keywords and default to the name.
+pub async fn upload_sticker(
+    client: &DiscordClient,
+    guild_id: &str,
+    name: &str,
+    tags: Option<&str>,
+    image: &[u8],
+) -> Result<DiscordSticker> {
+    let name = validate_sticker_name(name)?;
+    let tags = tags.map(str::trim).filter(|tags| !tags.is_empty()).unwrap_or(&name);
+    if tags.chars().count() > STICKER_TAGS_MAX_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Sticker tags are limited to {} characters", STICKER_TAGS_MAX_LEN),
+        ));
+    }
+    validate_guild_id(guild_id)?;
+    let prepared = prepare_sticker(image)?;
+    check_sticker_slots(client, guild_id, &name).await?;
+
+    let extension = prepared.mime_type.trim_start_matches("image/");
+    let file = Part::bytes(prepared.data)
+        .file_name(format!("sticker.{}", extension))
+        .mime_str(prepared.mime_type)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid sticker file: {}", e)))?;
+    let form = Form::new()
+        .text("name", name.clone())
+        .text("description", "")
+        .text("tags", tags.to_string())
+        .part("file", file);
+    send(client.post(&format!("/guilds/{}/stickers", guild_id)).multipart(form)).await
+}
+
+/// Send a request and parse the JSON response, mapping Discord's error codes
+async fn send<T: serde::de::DeserializeOwned>(request: RequestBuilder) -> Result<T> {
+    let response = request
+        .send()
+        .await
+        .map_err(|e| Error::from(e).context("Failed to reach Discord"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(retry::parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+        return Err(discord_error(status.as_u16(), &body, retry_after));
+    }
+
+    response.json().await.map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidResponse,
+            format!("Failed to parse Discord response: {}", e),
+        )
+    })
+}
+
+fn discord_error(status: u16, body: &str, retry_after: Option<std::time::Duration>) -> Error {
+    let Ok(parsed) = serde_json::from_str::<DiscordErrorBody>(body) else {
+        return Error::from_status(status, format!("Discord error {}: {}", status, body)).with_retry_after(retry_after);
+    };
+    let retry_after = retry_after.or_else(|| parsed.retry_after.map(std::time::Duration::from_secs_f64));
+    match parsed.code {
+        MAX_EMOJIS | MAX_ANIMATED_EMOJIS | MAX_STICKERS => Error::new(ErrorKind::LimitReached, parsed.message),
+        MISSING_PERMISSIONS => Error::new(
+            ErrorKind::PermissionDenied,
+            "The bot needs the Manage Expressions permission on this server",
+        ),
+        _ => Error::from_status(status, format!("Discord error {}: {}", status, parsed.message))
+            .with_retry_after(retry_after),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer, RecordedRequest, gemini::green_background_png};
+    use serde_json::json;
+
+    fn client(server: &MockServer) -> DiscordClient {
+        DiscordClient::with_base_url("bot-token", &server.base_url)
+    }
+
+    /// A guild at `tier` holding `emojis`; creating an emoji echoes the name back
+    fn guild(tier: u8, emojis: serde_json::Value) -> impl Fn(&RecordedRequest) -> MockResponse {
+        move |request| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/guilds/42") => MockResponse::json(200, json!({ "id": "42", "premium_tier": tier })),
+            ("GET", "/guilds/42/emojis") => MockResponse::json(200, emojis.clone()),
+            ("POST", "/guilds/42/emojis") => {
+                let name = request.body_json()["name"].clone();
+                MockResponse::json(201, json!({ "id": "7", "name": name, "animated": false }))
+            }
+            ("GET", "/guilds/42/stickers") => MockResponse::json(200, emojis.clone()),
+            ("POST", "/guilds/42/stickers") => MockResponse::json(201, json!({ "id": "8", "name": "sticker" })),
+            _ => MockResponse::json(404, json!({ "code": 0, "message": "404: Not Found" })),
+        }
+    }
+
+    fn emojis(count: usize, animated: bool) -> serde_json::Value {
+        (0..count)
+            .map(|i| json!({ "id": i.to_string(), "name": format!("emoji_{}", i), "animated": animated }))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert_eq!(validate_name(":Cowboy_Scream2:").unwrap(), "Cowboy_Scream2");
+        for bad in ["a", "cowboy-scream", "cowboy scream", "🤠🤠", &"a".repeat(33)] {
+            assert_eq!(
+                validate_name(bad).unwrap_err().kind,
+                ErrorKind::InvalidInput,
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_creates_emoji() {
+        let server = MockServer::start(guild(0, emojis(3, false))).await;
+        let emoji = upload_emoji(&client(&server), "42", "cowboy", &green_background_png())
+            .await
+            .unwrap();
+        assert_eq!(emoji.name, "cowboy");
+
+        let post = server.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(post.header("authorization"), Some("Bot bot-token"));
+        let body = post.body_json();
+        assert!(body["image"].as_str().unwrap().starts_with("data:image/png;base64,"));
+    }
+
+    #[tokio::test]
+    async fn test_upload_converts_animated_webp_to_gif() {
+        let frames = vec![
+            RgbaImage::from_pixel(16, 16, image::Rgba([255, 0, 0, 255])),
+            RgbaImage::new(16, 16),
+        ];
+        let webp = animation::encode(&frames, 100, AnimationFormat::Webp).unwrap();
+        let server = MockServer::start(guild(0, emojis(0, false))).await;
+        upload_emoji(&client(&server), "42", "blink", &webp).await.unwrap();
+
+        let post = server.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert!(
+            post.body_json()["image"]
+                .as_str()
+                .unwrap()
+                .starts_with("data:image/gif;base64,")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload_classifies_gifs_by_frame_count() {
+        // A single-frame GIF takes a static slot, so it fits next to 50 animated emojis
+        let still = animation::encode(&[RgbaImage::new(16, 16)], 100, AnimationFormat::Gif).unwrap();
+        assert!(!is_animated_gif(&still).unwrap());
+        let server = MockServer::start(guild(0, emojis(50, true))).await;
+        upload_emoji(&client(&server), "42", "still", &still).await.unwrap();
+
+        let frames = vec![
+            RgbaImage::from_pixel(16, 16, image::Rgba([255, 0, 0, 255])),
+            RgbaImage::new(16, 16),
+        ];
+        let animated = animation::encode(&frames, 100, AnimationFormat::Gif).unwrap();
+        assert!(is_animated_gif(&animated).unwrap());
+        let server = MockServer::start(guild(0, emojis(50, true))).await;
+        let err = upload_emoji(&client(&server), "42", "blink", &animated)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::LimitReached);
+        assert!(err.message.contains("animated"));
+    }
+
+    #[tokio::test]
+    async fn test_upload_sticker() {
+        let server = MockServer::start(guild(0, emojis(2, false))).await;
+        let sticker = upload_sticker(&client(&server), "42", "Cowboy scream", None, &green_background_png())
+            .await
+            .unwrap();
+        assert_eq!(sticker.id, "8");
+
+        let post = server.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(post.path, "/guilds/42/stickers");
+        assert!(post.header("content-type").unwrap().starts_with("multipart/form-data"));
+        let body = String::from_utf8_lossy(&post.body);
+        assert!(body.contains("name=\"tags\"\r\n\r\nCowboy scream\r\n"));
+        assert!(body.contains("filename=\"sticker.png\""));
+
+        // Five stickers fill a server without boosts
+        let server = MockServer::start(guild(0, emojis(5, false))).await;
+        let err = upload_sticker(
+            &client(&server),
+            "42",
+            "cowboy",
+            Some("cowboy"),
+            &green_background_png(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::LimitReached);
+        assert!(err.message.contains("5 of 5"));
+
+        let err = upload_sticker(&client(&server), "42", "emoji_1", None, &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NameTaken);
+        let err = upload_sticker(&client(&server), "42", "a", None, &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn test_upload_reports_full_slots_and_taken_names() {
+        // 50 static emojis fill a server without boosts
+        let server = MockServer::start(guild(0, emojis(50, false))).await;
+        let err = upload_emoji(&client(&server), "42", "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::LimitReached);
+        assert!(err.message.contains("50 of 50"));
+        assert!(server.requests().iter().all(|r| r.method == "GET"));
+
+        // Boosted servers have more room
+        let server = MockServer::start(guild(1, emojis(50, false))).await;
+        upload_emoji(&client(&server), "42", "cowboy", &green_background_png())
+            .await
+            .unwrap();
+
+        let server = MockServer::start(guild(0, emojis(3, false))).await;
+        let err = upload_emoji(&client(&server), "42", "emoji_1", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NameTaken);
+    }
+
+    #[tokio::test]
+    async fn test_upload_maps_discord_errors() {
+        let server = MockServer::start(|request| match request.method.as_str() {
+            "GET" if request.path.ends_with("/emojis") => MockResponse::json(200, json!([])),
+            "GET" => MockResponse::json(200, json!({ "premium_tier": 0 })),
+            _ => MockResponse::json(
+                400,
+                json!({ "code": 30008, "message": "Maximum number of emojis reached (50)" }),
+            ),
+        })
+        .await;
+        let err = upload_emoji(&client(&server), "42", "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::LimitReached);
+
+        let server =
+            MockServer::start(|_| MockResponse::json(403, json!({ "code": 50013, "message": "Missing Permissions" })))
+                .await;
+        let err = upload_emoji(&client(&server), "42", "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::PermissionDenied);
+        assert!(err.message.contains("Manage Expressions"));
+
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                429,
+                json!({ "message": "You are being rate limited.", "retry_after": 1.5 }),
+            )
+        })
+        .await;
+        let err = upload_emoji(&client(&server), "42", "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::RateLimited);
+        assert_eq!(err.retry_after_ms, Some(1500));
+    }
+
+    #[tokio::test]
+    async fn test_upload_checks_input_before_sending() {
+        let server = MockServer::start(guild(0, emojis(0, false))).await;
+        let err = upload_emoji(&client(&server), "not-a-guild", "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidInput);
+
+        let mut too_big = green_background_png();
+        too_big.resize(DISCORD_MAX_BYTES + 1, 0);
+        let err = upload_emoji(&client(&server), "42", "cowboy", &too_big)
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("256 KB"));
+        assert!(server.requests().is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! Local animation effects: frames derived from one cut-out emoji, no API needed.
+
+use std::f32::consts::TAU;
+use std::str::FromStr;
+
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, ErrorKind};
+
+/// Jerky offsets for `Shake`, in units of `SHAKE_AMPLITUDE`
+const SHAKE_OFFSETS: &[(f32, f32)] = &[
+    (1.0, 0.0),
+    (-1.0, 1.0),
+    (0.0, -1.0),
+    (1.0, 1.0),
+    (-1.0, 0.0),
+    (0.0, 1.0),
+    (1.0, -1.0),
+    (-1.0, -1.0),
+];
+
+/// Shake distance as a share of the canvas size (4px at 128)
+const SHAKE_AMPLITUDE: f32 = 1.0 / 32.0;
+
+/// Smallest scale `Pulse` shrinks to
+const PULSE_MIN_SCALE: f32 = 0.85;
+
+/// Height of a `Bounce` hop as a share of the canvas size
+const BOUNCE_HEIGHT: f32 = 0.15;
+
+/// Scale `ZoomIn` reaches at the end of the loop
+const ZOOM_MAX_SCALE: f32 = 2.5;
+
+/// Sideways sway of `Wave` as a share of the canvas size, and waves across the emoji
+const WAVE_AMPLITUDE: f32 = 1.0 / 16.0;
+const WAVE_CYCLES: f32 = 1.5;
+
+/// Jitter distance of `Intensifies`, wilder than `Shake`
+const INTENSIFIES_AMPLITUDE: f32 = 1.0 / 12.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    /// Jitter in place, party parrot style
+    Shake,
+    /// One full turn per loop
+    Spin,
+    /// Shrink and grow back
+    Pulse,
+    /// Cycle the hue through the rainbow
+    Rainbow,
+    /// Hop up and land again
+    Bounce,
+    /// Grow towards the viewer until it fills the canvas
+    ZoomIn,
+    /// Ripple sideways like a flag
+    Wave,
+    /// Random jitter, as in "[this] intensifies"
+    Intensifies,
+    /// Slide off one side and back in from the other
+    Conveyor,
+}
+
+impl Effect {
+    pub const ALL: &[Effect] = &[
+        Effect::Shake,
+        Effect::Spin,
+        Effect::Pulse,
+        Effect::Rainbow,
+        Effect::Bounce,
+        Effect::ZoomIn,
+        Effect::Wave,
+        Effect::Intensifies,
+        Effect::Conveyor,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Effect::Shake => "shake",
+            Effect::Spin => "spin",
+            Effect::Pulse => "pulse",
+            Effect::Rainbow => "rainbow",
+            Effect::Bounce => "bounce",
+            Effect::ZoomIn => "zoom_in",
+            Effect::Wave => "wave",
+            Effect::Intensifies => "intensifies",
+            Effect::Conveyor => "conveyor",
+        }
+    }
+
+    /// Frames per loop when the caller doesn't ask for a number
+    pub fn default_frames(self) -> usize {
+        match self {
+            Effect::Shake | Effect::Intensifies | Effect::ZoomIn => 8,
+            Effect::Spin | Effect::Rainbow | Effect::Wave => 12,
+            Effect::Pulse | Effect::Bounce => 10,
+            Effect::Conveyor => 16,
+        }
+    }
+}
+
+impl FromStr for Effect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Effect::ALL
+            .iter()
+            .copied()
+            .find(|effect| effect.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Effect::ALL.iter().map(|e| e.name()).collect();
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unknown effect '{}', expected one of: {}", s, names.join(", ")),
+                )
+            })
+    }
+}
+
+/// Render one loop of `effect` as `frames` frames. `base` is a cut-out emoji on a
+/// transparent square canvas; every frame has the same size.
+pub fn apply(effect: Effect, base: &RgbaImage, frames: usize) -> Vec<RgbaImage> {
+    let size = base.width() as f32;
+    (0..frames)
+        .map(|i| {
+            // Position in the loop, 0 inclusive to 1 exclusive
+            let t = i as f32 / frames as f32;
+            match effect {
+                Effect::Shake => {
+                    let (dx, dy) = SHAKE_OFFSETS[i % SHAKE_OFFSETS.len()];
+                    let amplitude = size * SHAKE_AMPLITUDE;
+                    transform(base, 0.0, 1.0, dx * amplitude, dy * amplitude)
+                }
+                Effect::Spin => transform(base, TAU * t, 1.0, 0.0, 0.0),
+                Effect::Pulse => {
+                    let scale = 1.0 - (1.0 - PULSE_MIN_SCALE) * (1.0 - (TAU * t).cos()) / 2.0;
+                    transform(base, 0.0, scale, 0.0, 0.0)
+                }
+                Effect::Rainbow => rotate_hue(base, TAU * t),
+                Effect::Bounce => {
+                    let lift = size * BOUNCE_HEIGHT * (TAU / 2.0 * t).sin();
+                    transform(base, 0.0, 1.0, 0.0, -lift)
+                }
+                Effect::ZoomIn => transform(base, 0.0, 1.0 + (ZOOM_MAX_SCALE - 1.0) * t * t, 0.0, 0.0),
+                Effect::Wave => {
+                    let amplitude = size * WAVE_AMPLITUDE;
+                    // Rows sway more towards the bottom, as if the top were held
+                    remap(base, |x, y| {
+                        let phase = TAU * (WAVE_CYCLES * y / size - t);
+                        (x - amplitude * (y / size) * phase.sin(), y)
+                    })
+                }
+                Effect::Intensifies => {
+                    let amplitude = size * INTENSIFIES_AMPLITUDE;
+                    let (dx, dy) = (jitter(i * 2), jitter(i * 2 + 1));
+                    transform(base, 0.0, 1.0, dx * amplitude, dy * amplitude)
+                }
+                Effect::Conveyor => {
+                    let shift = size * t;
+                    remap(base, |x, y| ((x - shift).rem_euclid(size), y))
+                }
+            }
+        })
+        .collect()
+}
+
+/// Rotate by `angle` and scale around the center, then shift by (`dx`, `dy`).
+/// Pixels moved in from outside the canvas are transparent.
+fn transform(base: &RgbaImage, angle: f32, scale: f32, dx: f32, dy: f32) -> RgbaImage {
+    let (width, height) = base.dimensions();
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let (sin, cos) = angle.sin_cos();
+
+    remap(base, |x, y| {
+        let px = x - cx - dx;
+        let py = y - cy - dy;
+        ((cos * px + sin * py) / scale + cx, (-sin * px + cos * py) / scale + cy)
+    })
+}
+
+/// Build a frame by mapping every output pixel center back to where it comes
+/// from in `base`, both in pixel coordinates
+fn remap(base: &RgbaImage, source: impl Fn(f32, f32) -> (f32, f32)) -> RgbaImage {
+    let (width, height) = base.dimensions();
+    RgbaImage::from_fn(width, height, |x, y| {
+        let (sx, sy) = source(x as f32 + 0.5, y as f32 + 0.5);
+        sample(base, sx - 0.5, sy - 0.5)
+    })
+}
+
+/// Repeatable pseudo-random offset in -1..=1, so the same input always renders the same frames
+fn jitter(n: usize) -> f32 {
+    let mut h = (n as u32).wrapping_add(1).wrapping_mul(0x9E37_79B9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85EB_CA6B);
+    h ^= h >> 13;
+    (h % 2001) as f32 / 1000.0 - 1.0
+}
+
+/// Bilinear sample with premultiplied alpha, so transparent neighbors don't darken edges
+fn sample(img: &RgbaImage, x: f32, y: f32) -> Rgba<u8> {
+    let (width, height) = img.dimensions();
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let mut acc = [0f32; 4];
+    for (ox, oy, weight) in [
+        (0, 0, (1.0 - fx) * (1.0 - fy)),
+        (1, 0, fx * (1.0 - fy)),
+        (0, 1, (1.0 - fx) * fy),
+        (1, 1, fx * fy),
+    ] {
+        let (sx, sy) = (x0 as i64 + ox, y0 as i64 + oy);
+        if weight == 0.0 || sx < 0 || sy < 0 || sx >= width as i64 || sy >= height as i64 {
+            continue;
+        }
+        let p = img.get_pixel(sx as u32, sy as u32).0;
+        let alpha = p[3] as f32 / 255.0 * weight;
+        acc[0] += p[0] as f32 * alpha;
+        acc[1] += p[1] as f32 * alpha;
+        acc[2] += p[2] as f32 * alpha;
+        acc[3] += alpha;
+    }
+    if acc[3] <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    Rgba([
+        (acc[0] / acc[3]).round() as u8,
+        (acc[1] / acc[3]).round() as u8,
+        (acc[2] / acc[3]).round() as u8,
+        (acc[3] * 255.0).round() as u8,
+    ])
+}
+
+/// Rotate the hue of every pixel by `angle`, keeping luminance (the CSS `hue-rotate` matrix)
+fn rotate_hue(base: &RgbaImage, angle: f32) -> RgbaImage {
+    let (sin, cos) = angle.sin_cos();
+    let m = [
+        [
+            0.213 + cos * 0.787 - sin * 0.213,
+            0.715 - cos * 0.715 - sin * 0.715,
+            0.072 - cos * 0.072 + sin * 0.928,
+        ],
+        [
+            0.213 - cos * 0.213 + sin * 0.143,
+            0.715 + cos * 0.285 + sin * 0.140,
+            0.072 - cos * 0.072 - sin * 0.283,
+        ],
+        [
+            0.213 - cos * 0.213 - sin * 0.787,
+            0.715 - cos * 0.715 + sin * 0.715,
+            0.072 + cos * 0.928 + sin * 0.072,
+        ],
+    ];
+    let mut out = base.clone();
+    for pixel in out.pixels_mut() {
+        let [r, g, b, a] = pixel.0;
+        let rgb = [r as f32, g as f32, b as f32];
+        let channel = |row: &[f32; 3]| {
+            (row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2])
+                .round()
+                .clamp(0.0, 255.0) as u8
+        };
+        *pixel = Rgba([channel(&m[0]), channel(&m[1]), channel(&m[2]), a]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A red square in the middle of a transparent 32x32 canvas
+    fn base() -> RgbaImage {
+        RgbaImage::from_fn(32, 32, |x, y| {
+            if (8..24).contains(&x) && (8..24).contains(&y) {
+                Rgba([220, 30, 30, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn test_every_effect_renders_the_requested_frames() {
+        for &effect in Effect::ALL {
+            let frames = apply(effect, &base(), 6);
+            assert_eq!(frames.len(), 6, "{:?}", effect);
+            assert!(frames.iter().all(|f| f.dimensions() == (32, 32)), "{:?}", effect);
+            // The first frame of a loop is the untouched image, except for the
+            // jittery and wavy effects which start offset
+            if !matches!(effect, Effect::Shake | Effect::Intensifies | Effect::Wave) {
+                assert_eq!(frames[0], base(), "{:?}", effect);
+            }
+        }
+    }
+
+    #[test]
+    fn test_shake_moves_and_rainbow_recolors() {
+        let shaken = apply(Effect::Shake, &base(), 2);
+        assert_ne!(shaken[0], shaken[1]);
+
+        let rainbow = apply(Effect::Rainbow, &base(), 3);
+        let center = |img: &RgbaImage| img.get_pixel(16, 16).0;
+        assert_ne!(center(&rainbow[0]), center(&rainbow[1]));
+        assert_eq!(center(&rainbow[1])[3], 255);
+    }
+
+    #[test]
+    fn test_bounce_lifts_and_conveyor_wraps() {
+        let bounced = apply(Effect::Bounce, &base(), 2);
+        // Halfway through the loop the square is at its highest
+        assert_eq!(bounced[1].get_pixel(16, 8).0[3], 255);
+        assert_eq!(bounced[1].get_pixel(16, 22).0[3], 0);
+
+        // Half a canvas along, the square straddles the left and right edges
+        let conveyed = apply(Effect::Conveyor, &base(), 2);
+        assert_eq!(conveyed[1].get_pixel(2, 16).0[3], 255);
+        assert_eq!(conveyed[1].get_pixel(30, 16).0[3], 255);
+        assert_eq!(conveyed[1].get_pixel(16, 16).0[3], 0);
+    }
+
+    #[test]
+    fn test_effects_are_deterministic() {
+        for &effect in Effect::ALL {
+            assert_eq!(apply(effect, &base(), 4), apply(effect, &base(), 4), "{:?}", effect);
+        }
+    }
+
+    #[test]
+    fn test_effect_from_str() {
+        assert_eq!("zoom_in".parse::<Effect>().unwrap(), Effect::ZoomIn);
+        assert_eq!("spin".parse::<Effect>().unwrap(), Effect::Spin);
+        assert_eq!("wobble".parse::<Effect>().unwrap_err().kind, ErrorKind::InvalidInput);
+    }
+}
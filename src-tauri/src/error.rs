@@ -0,0 +1,175 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::redact;
+use crate::usage::Usage;
+
+/// What went wrong, so the frontend can react (e.g. ask for a new API key)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// No API key is configured
+    MissingApiKey,
+    /// The API rejected the key (HTTP 401)
+    InvalidApiKey,
+    /// The key is valid but can't use this API or model (HTTP 403)
+    PermissionDenied,
+    /// Rate limit or quota exhausted (HTTP 429)
+    RateLimited,
+    /// The prompt or result was blocked by a safety filter
+    SafetyBlocked,
+    /// The API had an internal problem (HTTP 5xx)
+    ServerError,
+    /// The API couldn't be reached
+    Network,
+    /// Any other error response from the API
+    ApiError,
+    /// The API answered, but not with what we asked for
+    InvalidResponse,
+    /// No usable background color was found in the image
+    NoBackground,
+    /// Decoding, keying or encoding an image failed
+    ImageProcessing,
+    /// The request itself was invalid
+    InvalidInput,
+    /// Stored credentials are encrypted and need the passphrase
+    Locked,
+    /// The destination already has an emoji with that name
+    NameTaken,
+    /// The destination has no room left, e.g. every emoji slot is used
+    LimitReached,
+    /// Reading or writing local files failed
+    Storage,
+    /// The user cancelled the operation
+    Cancelled,
+}
+
+/// Error type shared by the whole backend, serialized as-is to the frontend
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Error {
+    pub kind: ErrorKind,
+    /// Always redacted, including when read back from history
+    #[serde(deserialize_with = "deserialize_redacted")]
+    pub message: String,
+    /// Suggested wait before retrying, for transient errors
+    pub retry_after_ms: Option<u64>,
+    /// What the request was billed when the provider answered without a usable result
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn deserialize_redacted<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<String, D::Error> {
+    String::deserialize(deserializer).map(|message| redact::redact(&message))
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            // API error bodies and reqwest errors can echo keys or signed URLs
+            message: redact::redact(&message.into()),
+            retry_after_ms: None,
+            usage: None,
+        }
+    }
+
+    pub fn with_usage(mut self, usage: Usage) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Option<Duration>) -> Self {
+        self.retry_after_ms = retry_after.map(|d| d.as_millis() as u64);
+        self
+    }
+
+    /// Map an unsuccessful HTTP status to an error
+    pub fn from_status(status: u16, message: impl Into<String>) -> Self {
+        let kind = match status {
+            401 => ErrorKind::InvalidApiKey,
+            403 => ErrorKind::PermissionDenied,
+            429 => ErrorKind::RateLimited,
+            500..=599 => ErrorKind::ServerError,
+            _ => ErrorKind::ApiError,
+        };
+        Self::new(kind, message)
+    }
+
+    /// Transient failures that are worth retrying
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::RateLimited | ErrorKind::ServerError | ErrorKind::Network
+        )
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after_ms.map(Duration::from_millis)
+    }
+
+    /// Prefix the message with context, keeping the kind
+    pub fn context(mut self, context: &str) -> Self {
+        self.message = redact::redact(&format!("{}: {}", context, self.message));
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        let kind = if e.is_decode() {
+            ErrorKind::InvalidResponse
+        } else {
+            ErrorKind::Network
+        };
+        Self::new(kind, e.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::new(ErrorKind::Storage, e.to_string())
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Self::new(ErrorKind::ImageProcessing, e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_status() {
+        assert_eq!(Error::from_status(401, "").kind, ErrorKind::InvalidApiKey);
+        assert_eq!(Error::from_status(403, "").kind, ErrorKind::PermissionDenied);
+        assert_eq!(Error::from_status(429, "").kind, ErrorKind::RateLimited);
+        assert_eq!(Error::from_status(503, "").kind, ErrorKind::ServerError);
+        assert_eq!(Error::from_status(400, "").kind, ErrorKind::ApiError);
+        assert!(Error::from_status(429, "").is_retryable());
+        assert!(!Error::from_status(401, "").is_retryable());
+    }
+
+    #[test]
+    fn test_serializes_kind_in_snake_case() {
+        let err = Error::new(ErrorKind::InvalidApiKey, "bad key").with_retry_after(Some(Duration::from_secs(2)));
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["kind"], "invalid_api_key");
+        assert_eq!(json["message"], "bad key");
+        assert_eq!(json["retry_after_ms"], 2000);
+    }
+}
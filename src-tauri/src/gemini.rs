@@ -1,15 +1,35 @@
-use base64::{engine::general_purpose::STANDARD, Engine};
+use std::time::Duration;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use futures::future::BoxFuture;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-const GEMINI_API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+use crate::config::{self, Settings};
+use crate::error::{Error, ErrorKind, Result};
+use crate::provider::{Capabilities, GeneratedImage, ImageProvider, ModelInfo, Prompt};
+use crate::redact;
+use crate::retry;
+use crate::usage::Usage;
+
+const DEFAULT_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// Environment variable that overrides the API base URL
+const BASE_URL_ENV: &str = "MOJIMIX_GEMINI_BASE_URL";
 
 // Image generation models
-const MODEL_PRO: &str = "gemini-3-pro-image-preview";    // Nano Banana Pro - higher quality
+const MODEL_PRO: &str = "gemini-3-pro-image-preview"; // Nano Banana Pro - higher quality
 const MODEL_FAST: &str = "gemini-2.5-flash-image"; // Nano Banana - faster/cheaper
 
-const GEMINI_TEXT_ENDPOINT: &str =
-    "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash:generateContent";
+// Text model used for naming files
+pub const MODEL_TEXT: &str = "gemini-2.0-flash";
+
+/// Resolve the API base URL: env var first, then config.json, then the public endpoint
+pub fn base_url() -> String {
+    config::env_override(BASE_URL_ENV)
+        .or_else(|| Settings::load().gemini_base_url)
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+}
 
 #[derive(Serialize)]
 struct GeminiRequest {
@@ -23,9 +43,39 @@ struct Content {
     parts: Vec<Part>,
 }
 
+/// Either text or an inline image
 #[derive(Serialize)]
 struct Part {
-    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(rename = "inlineData", skip_serializing_if = "Option::is_none")]
+    inline_data: Option<InlineDataRequest>,
+}
+
+impl Part {
+    fn text(text: &str) -> Self {
+        Self {
+            text: Some(text.to_string()),
+            inline_data: None,
+        }
+    }
+
+    fn image(mime_type: &str, data: &[u8]) -> Self {
+        Self {
+            text: None,
+            inline_data: Some(InlineDataRequest {
+                mime_type: mime_type.to_string(),
+                data: STANDARD.encode(data),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct InlineDataRequest {
+    #[serde(rename = "mimeType")]
+    mime_type: String,
+    data: String,
 }
 
 #[derive(Serialize)]
@@ -44,16 +94,63 @@ struct ImageConfig {
 
 #[derive(Deserialize)]
 struct GeminiResponse {
+    #[serde(default)]
     candidates: Vec<Candidate>,
+    #[serde(rename = "promptFeedback")]
+    prompt_feedback: Option<PromptFeedback>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+/// Token counts Gemini bills a request for
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct UsageMetadata {
+    prompt_token_count: u64,
+    /// Output tokens, image tokens included
+    candidates_token_count: u64,
+    /// Thinking tokens, billed as output (Pro models)
+    thoughts_token_count: u64,
+}
+
+impl UsageMetadata {
+    fn usage(&self, images: u32) -> Usage {
+        Usage {
+            requests: 1,
+            input_tokens: self.prompt_token_count,
+            output_tokens: self.candidates_token_count + self.thoughts_token_count,
+            images,
+        }
+    }
+
+    /// What a successful response that didn't parse as a whole was billed:
+    /// its `usageMetadata` if that part is readable, otherwise just the request
+    fn salvage(body: &str) -> Usage {
+        serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|value| serde_json::from_value::<UsageMetadata>(value.get("usageMetadata")?.clone()).ok())
+            .unwrap_or_default()
+            .usage(0)
+    }
+}
+
+#[derive(Deserialize)]
+struct PromptFeedback {
+    #[serde(rename = "blockReason")]
+    block_reason: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct Candidate {
-    content: ContentResponse,
+    // Missing when the candidate was stopped by a safety filter
+    content: Option<ContentResponse>,
+    #[serde(rename = "finishReason")]
+    finish_reason: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct ContentResponse {
+    #[serde(default)]
     parts: Vec<PartResponse>,
 }
 
@@ -70,18 +167,95 @@ struct InlineData {
     data: String,
 }
 
-pub async fn generate_emoji_image(prompt: &str, api_key: &str, use_fast_model: bool) -> Result<Vec<u8>, String> {
-    let client = Client::new();
+/// HTTP client for the Gemini `generateContent` API
+pub struct GeminiClient {
+    http: Client,
+    base_url: String,
+    api_key: String,
+}
+
+impl GeminiClient {
+    /// Create a client using the configured base URL
+    pub fn new(api_key: &str) -> Self {
+        Self::with_base_url(api_key, &base_url())
+    }
+
+    pub fn with_base_url(api_key: &str, base_url: &str) -> Self {
+        Self {
+            http: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    fn endpoint(&self, model: &str) -> String {
+        format!("{}/models/{}:generateContent", self.base_url, model)
+    }
+}
+
+/// The image models the app offers by default
+fn known_models() -> Vec<ModelInfo> {
+    vec![
+        ModelInfo {
+            id: MODEL_PRO.to_string(),
+            name: "Nano Banana Pro".to_string(),
+            fast: false,
+        },
+        ModelInfo {
+            id: MODEL_FAST.to_string(),
+            name: "Nano Banana".to_string(),
+            fast: true,
+        },
+    ]
+}
+
+/// Gemini image generation ("Nano Banana") as an `ImageProvider`
+pub struct GeminiProvider {
+    client: GeminiClient,
+}
+
+impl GeminiProvider {
+    pub fn new(client: GeminiClient) -> Self {
+        Self { client }
+    }
+}
 
-    let model = if use_fast_model { MODEL_FAST } else { MODEL_PRO };
-    let endpoint = format!("{}/{}:generateContent", GEMINI_API_BASE, model);
+impl ImageProvider for GeminiProvider {
+    fn id(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn name(&self) -> &'static str {
+        "Google Gemini"
+    }
+
+    fn models(&self) -> Vec<ModelInfo> {
+        known_models()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            image_input: true,
+            max_images: 8,
+        }
+    }
+
+    fn generate<'a>(&'a self, prompt: &'a Prompt, model: &'a str) -> BoxFuture<'a, Result<GeneratedImage>> {
+        Box::pin(generate_emoji_image(&self.client, prompt, model))
+    }
+}
+
+pub async fn generate_emoji_image(client: &GeminiClient, prompt: &Prompt, model: &str) -> Result<GeneratedImage> {
+    // Input images go first, so the text can refer to them as "image 1", "image 2", ...
+    let mut parts: Vec<Part> = prompt
+        .images
+        .iter()
+        .map(|image| Part::image(&image.mime_type, &image.data))
+        .collect();
+    parts.push(Part::text(&prompt.text));
 
     let request = GeminiRequest {
-        contents: vec![Content {
-            parts: vec![Part {
-                text: prompt.to_string(),
-            }],
-        }],
+        contents: vec![Content { parts }],
         generation_config: GenerationConfig {
             response_modalities: vec!["IMAGE".to_string()],
             image_config: ImageConfig {
@@ -91,250 +265,87 @@ pub async fn generate_emoji_image(prompt: &str, api_key: &str, use_fast_model: b
     };
 
     let response = client
-        .post(&endpoint)
-        .header("x-goog-api-key", api_key)
+        .http
+        .post(client.endpoint(model))
+        .header("x-goog-api-key", &client.api_key)
         .header("Content-Type", "application/json")
         .json(&request)
         .send()
         .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
+        .map_err(|e| Error::from(e).context("Failed to send request"))?;
 
     if !response.status().is_success() {
         let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(retry::parse_retry_after);
         let body = response.text().await.unwrap_or_default();
-        return Err(format!("API error {}: {}", status, body));
+        return Err(
+            api_error(status.as_u16(), &body, format!("API error {}: {}", status, body))
+                .with_retry_after(retry_after.or_else(|| body_retry_delay(&body))),
+        );
     }
 
-    let gemini_response: GeminiResponse = response
-        .json()
+    // From here on the request is billed, so every error carries its usage
+    let body = response
+        .text()
         .await
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
+        .map_err(|e| Error::from(e).context("Failed to read response"))?;
+    let gemini_response: GeminiResponse = serde_json::from_str(&body).map_err(|e| {
+        Error::new(ErrorKind::InvalidResponse, format!("Failed to parse response: {}", e))
+            .with_usage(UsageMetadata::salvage(&body))
+    })?;
+
+    let metadata = gemini_response.usage_metadata.unwrap_or_default();
+    let billed = metadata.usage(0);
+    if let Some(reason) = gemini_response.prompt_feedback.and_then(|f| f.block_reason) {
+        return Err(Error::new(ErrorKind::SafetyBlocked, format!("Prompt blocked: {}", reason)).with_usage(billed));
+    }
 
+    let mut finish_reason = None;
     for candidate in gemini_response.candidates {
-        for part in candidate.content.parts {
-            if let Some(inline_data) = part.inline_data {
-                if inline_data.mime_type.starts_with("image/") {
-                    let image_bytes = STANDARD
-                        .decode(&inline_data.data)
-                        .map_err(|e| format!("Failed to decode base64: {}", e))?;
-                    return Ok(image_bytes);
-                }
+        let parts = candidate.content.map(|c| c.parts).unwrap_or_default();
+        for part in parts {
+            if let Some(inline_data) = part.inline_data
+                && inline_data.mime_type.starts_with("image/")
+            {
+                let image_bytes = STANDARD.decode(&inline_data.data).map_err(|e| {
+                    Error::new(ErrorKind::InvalidResponse, format!("Failed to decode base64: {}", e)).with_usage(billed)
+                })?;
+                return Ok(GeneratedImage {
+                    data: image_bytes,
+                    usage: metadata.usage(1),
+                });
             }
         }
+        finish_reason = finish_reason.or(candidate.finish_reason);
     }
 
-    Err("No image found in response".to_string())
-}
-
-/// Result containing both background removal methods
-pub struct ProcessedEmoji {
-    pub flood_fill: Vec<u8>,
-    pub color_key: Vec<u8>,
-    pub warning: Option<String>,
-}
-
-pub fn resize_to_emoji(image_bytes: &[u8]) -> Result<ProcessedEmoji, String> {
-    use image::{ImageFormat, Rgba, RgbaImage};
-    use std::io::Cursor;
-
-    let img = image::load_from_memory(image_bytes)
-        .map_err(|e| format!("Failed to load image: {}", e))?;
-
-    let rgba = img.to_rgba8();
-    let (width, height) = rgba.dimensions();
-
-    // Sample the 4 corners to detect background color
-    let corners = [
-        rgba.get_pixel(0, 0),
-        rgba.get_pixel(width - 1, 0),
-        rgba.get_pixel(0, height - 1),
-        rgba.get_pixel(width - 1, height - 1),
-    ];
-
-    let mut warning: Option<String> = None;
-
-    // Helper to check if a pixel is "green-ish" (green dominant)
-    let is_greenish = |pixel: &Rgba<u8>| -> bool {
-        pixel.0[1] > pixel.0[0] && pixel.0[1] > pixel.0[2]
-    };
-
-    // Filter corners to only green-ish ones
-    let green_corners: Vec<_> = corners.iter().filter(|c| is_greenish(c)).collect();
-
-    // Determine background color
-    let (bg_r, bg_g, bg_b) = if green_corners.is_empty() {
-        // No green corners at all - this is a hard error
-        return Err(format!(
-            "No green background detected in any corner: ({},{},{}), ({},{},{}), ({},{},{}), ({},{},{})",
-            corners[0].0[0], corners[0].0[1], corners[0].0[2],
-            corners[1].0[0], corners[1].0[1], corners[1].0[2],
-            corners[2].0[0], corners[2].0[1], corners[2].0[2],
-            corners[3].0[0], corners[3].0[1], corners[3].0[2],
-        ));
-    } else if green_corners.len() < 4 {
-        // Some corners aren't green - warn but continue with green corners only
-        let non_green_count = 4 - green_corners.len();
-        warning = Some(format!(
-            "{} corner(s) excluded from background detection (not green)",
-            non_green_count
-        ));
-        let count = green_corners.len() as u16;
-        (
-            green_corners.iter().map(|c| c.0[0] as u16).sum::<u16>() / count,
-            green_corners.iter().map(|c| c.0[1] as u16).sum::<u16>() / count,
-            green_corners.iter().map(|c| c.0[2] as u16).sum::<u16>() / count,
-        )
-    } else {
-        // All corners are green - check consistency
-        let tolerance = 50i16;
-        let ref_pixel = corners[0];
-        for corner in corners.iter().skip(1) {
-            let dr = (corner.0[0] as i16 - ref_pixel.0[0] as i16).abs();
-            let dg = (corner.0[1] as i16 - ref_pixel.0[1] as i16).abs();
-            let db = (corner.0[2] as i16 - ref_pixel.0[2] as i16).abs();
-            if dr > tolerance || dg > tolerance || db > tolerance {
-                warning = Some(
-                    "Background color varies between corners (using average)".to_string()
-                );
-                break;
-            }
-        }
-        // Use average of all corners
-        (
-            corners.iter().map(|c| c.0[0] as u16).sum::<u16>() / 4,
-            corners.iter().map(|c| c.0[1] as u16).sum::<u16>() / 4,
-            corners.iter().map(|c| c.0[2] as u16).sum::<u16>() / 4,
-        )
-    };
-
-    let bg_tolerance = 40i16;
-
-    // Helper to check if pixel matches background
-    let matches_bg = |pixel: &Rgba<u8>| {
-        let dr = (pixel.0[0] as i16 - bg_r as i16).abs();
-        let dg = (pixel.0[1] as i16 - bg_g as i16).abs();
-        let db = (pixel.0[2] as i16 - bg_b as i16).abs();
-        dr < bg_tolerance && dg < bg_tolerance && db < bg_tolerance
-    };
-
-    // Process with flood fill
-    let flood_fill_result = {
-        let mut rgba_ff = rgba.clone();
-        let mut visited = vec![vec![false; height as usize]; width as usize];
-        let mut stack: Vec<(u32, u32)> = Vec::new();
-
-        // Seed flood fill from all edge pixels
-        for x in 0..width {
-            stack.push((x, 0));
-            stack.push((x, height - 1));
-        }
-        for y in 1..height - 1 {
-            stack.push((0, y));
-            stack.push((width - 1, y));
-        }
-
-        // Flood fill
-        while let Some((x, y)) = stack.pop() {
-            if x >= width || y >= height {
-                continue;
-            }
-            if visited[x as usize][y as usize] {
-                continue;
-            }
-            visited[x as usize][y as usize] = true;
-
-            let pixel = rgba_ff.get_pixel(x, y);
-            if !matches_bg(pixel) {
-                continue;
-            }
-
-            // Mark as transparent
-            rgba_ff.put_pixel(x, y, Rgba([0, 0, 0, 0]));
-
-            // Add neighbors
-            if x > 0 { stack.push((x - 1, y)); }
-            if x < width - 1 { stack.push((x + 1, y)); }
-            if y > 0 { stack.push((x, y - 1)); }
-            if y < height - 1 { stack.push((x, y + 1)); }
-        }
-        rgba_ff
-    };
-
-    // Process with color key (all matching pixels)
-    let color_key_result = {
-        let mut rgba_ck = rgba.clone();
-        for y in 0..height {
-            for x in 0..width {
-                let pixel = rgba_ck.get_pixel(x, y);
-                if matches_bg(pixel) {
-                    rgba_ck.put_pixel(x, y, Rgba([0, 0, 0, 0]));
-                }
-            }
-        }
-        rgba_ck
-    };
-
-    // Helper to crop, resize, and encode
-    let finalize = |rgba: RgbaImage| -> Result<Vec<u8>, String> {
-        let (width, height) = rgba.dimensions();
-
-        // Find bounding box of non-transparent pixels
-        let mut min_x = width;
-        let mut min_y = height;
-        let mut max_x = 0u32;
-        let mut max_y = 0u32;
-
-        for (x, y, pixel) in rgba.enumerate_pixels() {
-            if pixel.0[3] > 10 {
-                min_x = min_x.min(x);
-                min_y = min_y.min(y);
-                max_x = max_x.max(x);
-                max_y = max_y.max(y);
-            }
-        }
-
-        // Crop to bounding box with small padding
-        let padding = 4u32;
-        let crop_x = min_x.saturating_sub(padding);
-        let crop_y = min_y.saturating_sub(padding);
-        let crop_w = (max_x - min_x + 1 + padding * 2).min(width - crop_x);
-        let crop_h = (max_y - min_y + 1 + padding * 2).min(height - crop_y);
-
-        let cropped = if crop_w > 0 && crop_h > 0 && max_x >= min_x && max_y >= min_y {
-            image::imageops::crop_imm(&rgba, crop_x, crop_y, crop_w, crop_h).to_image()
-        } else {
-            rgba
-        };
-
-        // Resize to 128x128, maintaining aspect ratio and centering
-        let (cw, ch) = cropped.dimensions();
-        let scale = (120.0 / cw as f32).min(120.0 / ch as f32);
-        let new_w = (cw as f32 * scale) as u32;
-        let new_h = (ch as f32 * scale) as u32;
-
-        let scaled = image::imageops::resize(&cropped, new_w, new_h, image::imageops::FilterType::Lanczos3);
-
-        // Center on 128x128 canvas
-        let mut final_img = RgbaImage::new(128, 128);
-        let offset_x = (128 - new_w) / 2;
-        let offset_y = (128 - new_h) / 2;
-
-        for (x, y, pixel) in scaled.enumerate_pixels() {
-            final_img.put_pixel(offset_x + x, offset_y + y, *pixel);
+    match finish_reason {
+        Some(reason) if reason != "STOP" => {
+            // SAFETY, PROHIBITED_CONTENT, IMAGE_SAFETY, ... all mean a filter stopped it
+            let kind = if reason.contains("SAFETY") || reason.contains("PROHIBITED") || reason.contains("BLOCK") {
+                ErrorKind::SafetyBlocked
+            } else {
+                ErrorKind::InvalidResponse
+            };
+            Err(Error::new(kind, format!("No image found in response (finish reason: {})", reason)).with_usage(billed))
         }
+        _ => Err(Error::new(ErrorKind::InvalidResponse, "No image found in response").with_usage(billed)),
+    }
+}
 
-        let mut output = Cursor::new(Vec::new());
-        final_img.write_to(&mut output, ImageFormat::Png)
-            .map_err(|e| format!("Failed to encode PNG: {}", e))?;
-
-        Ok(output.into_inner())
-    };
-
-    Ok(ProcessedEmoji {
-        flood_fill: finalize(flood_fill_result)?,
-        color_key: finalize(color_key_result)?,
-        warning,
-    })
+/// Read the `RetryInfo.retryDelay` (e.g. "13s") Gemini includes in 429 error bodies
+fn body_retry_delay(body: &str) -> Option<Duration> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    value["error"]["details"]
+        .as_array()?
+        .iter()
+        .find_map(|d| d["retryDelay"].as_str())
+        .and_then(|delay| delay.trim_end_matches('s').parse::<f64>().ok())
+        .map(Duration::from_secs_f64)
 }
 
 #[derive(Serialize)]
@@ -344,16 +355,27 @@ struct TextRequest {
 
 #[derive(Deserialize)]
 struct TextResponse {
+    #[serde(default)]
     candidates: Vec<TextCandidate>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+/// A file name suggested by `generate_filename` and what asking for it consumed
+#[derive(Debug)]
+pub struct SuggestedName {
+    pub name: String,
+    pub usage: Usage,
 }
 
 #[derive(Deserialize)]
 struct TextCandidate {
-    content: TextContent,
+    content: Option<TextContent>,
 }
 
 #[derive(Deserialize)]
 struct TextContent {
+    #[serde(default)]
     parts: Vec<TextPart>,
 }
 
@@ -362,18 +384,18 @@ struct TextPart {
     text: String,
 }
 
+/// Ask `MODEL_TEXT` for a file name. Errors after the model answered carry the request's usage.
 pub async fn generate_filename(
+    client: &GeminiClient,
     emojis: &[String],
     modifier: Option<&str>,
-    api_key: &str,
-) -> Result<String, String> {
-    let client = Client::new();
-
+) -> Result<SuggestedName> {
     let emoji_str = emojis.join(" ");
     let prompt = match modifier {
         Some(m) if !m.trim().is_empty() => format!(
             "Generate a short filename (2-4 words, snake_case, no extension) for an emoji that combines: {} with style: {}. Reply with ONLY the filename, nothing else.",
-            emoji_str, m.trim()
+            emoji_str,
+            m.trim()
         ),
         _ => format!(
             "Generate a short filename (2-4 words, snake_case, no extension) for an emoji that combines: {}. Reply with ONLY the filename, nothing else.",
@@ -383,56 +405,526 @@ pub async fn generate_filename(
 
     let request = TextRequest {
         contents: vec![Content {
-            parts: vec![Part { text: prompt }],
+            parts: vec![Part::text(&prompt)],
         }],
     };
 
     let response = client
-        .post(GEMINI_TEXT_ENDPOINT)
-        .header("x-goog-api-key", api_key)
+        .http
+        .post(client.endpoint(MODEL_TEXT))
+        .header("x-goog-api-key", &client.api_key)
         .header("Content-Type", "application/json")
         .json(&request)
         .send()
         .await
-        .map_err(|e| format!("Failed to send request: {}", e))?;
+        .map_err(|e| Error::from(e).context("Failed to send request"))?;
 
     if !response.status().is_success() {
-        return Err("API error".to_string());
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(api_error(status.as_u16(), &body, format!("API error {}", status)));
     }
 
-    let text_response: TextResponse = response
-        .json()
+    let body = response
+        .text()
         .await
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
+        .map_err(|e| Error::from(e).context("Failed to read response"))?;
+    let text_response: TextResponse = serde_json::from_str(&body).map_err(|e| {
+        Error::new(ErrorKind::InvalidResponse, format!("Failed to parse response: {}", e))
+            .with_usage(UsageMetadata::salvage(&body))
+    })?;
+
+    let usage = text_response.usage_metadata.unwrap_or_default().usage(0);
+    if let Some(content) = text_response.candidates.first().and_then(|c| c.content.as_ref())
+        && let Some(part) = content.parts.first()
+    {
+        return Ok(SuggestedName {
+            name: part.text.trim().to_string(),
+            usage,
+        });
+    }
 
-    if let Some(candidate) = text_response.candidates.first() {
-        if let Some(part) = candidate.content.parts.first() {
-            return Ok(part.text.trim().to_string());
+    Err(Error::new(ErrorKind::InvalidResponse, "No text in response").with_usage(usage))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelsResponse {
+    #[serde(default)]
+    models: Vec<ListedModel>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListedModel {
+    /// "models/gemini-2.5-flash-image"
+    name: String,
+    display_name: Option<String>,
+    #[serde(default)]
+    supported_generation_methods: Vec<String>,
+}
+
+/// Outcome of `validate_api_key`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStatus {
+    Valid,
+    /// Gemini doesn't recognise the key
+    Invalid,
+    /// The key works but can't use image models, e.g. the API or model isn't enabled for its project
+    PermissionDenied,
+    /// Gemini couldn't be reached, so the key is untested
+    NetworkUnreachable,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeyValidation {
+    pub status: KeyStatus,
+    /// Why the key isn't usable; absent when valid
+    pub message: Option<String>,
+    /// Image models the key can generate with
+    pub image_models: Vec<ModelInfo>,
+}
+
+/// How a failed model list call reflects on the key, if it says anything about it
+fn key_status(status: u16, body: &str) -> Option<KeyStatus> {
+    match status {
+        // Gemini answers an unknown key with 400 API_KEY_INVALID rather than 401
+        400 if body.contains("API_KEY_INVALID") => Some(KeyStatus::Invalid),
+        401 => Some(KeyStatus::Invalid),
+        403 => Some(KeyStatus::PermissionDenied),
+        _ => None,
+    }
+}
+
+/// Map an unsuccessful response to an error, telling a rejected key apart
+/// from other bad requests by its body, as `validate_api_key` does
+fn api_error(status: u16, body: &str, message: String) -> Error {
+    match key_status(status, body) {
+        Some(KeyStatus::Invalid) => Error::new(ErrorKind::InvalidApiKey, message),
+        _ => Error::from_status(status, message),
+    }
+}
+
+/// Check a key with a model list call and report which image models it can use.
+/// Failures that say nothing about the key, like rate limits or server errors, are returned as errors.
+pub async fn validate_api_key(client: &GeminiClient) -> Result<KeyValidation> {
+    // Error bodies and reqwest errors can echo the key, so messages are redacted like `Error`'s
+    let rejected = |status, message: String| KeyValidation {
+        status,
+        message: Some(redact::redact(&message)),
+        image_models: Vec::new(),
+    };
+
+    let mut models: Vec<ListedModel> = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut request = client
+            .http
+            .get(format!("{}/models", client.base_url))
+            .header("x-goog-api-key", &client.api_key)
+            .query(&[("pageSize", "1000")]);
+        if let Some(token) = &page_token {
+            request = request.query(&[("pageToken", token)]);
+        }
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                return Ok(rejected(
+                    KeyStatus::NetworkUnreachable,
+                    format!("Failed to reach Gemini: {}", e),
+                ));
+            }
+        };
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let message = format!("API error {}: {}", status, body);
+            return match key_status(status.as_u16(), &body) {
+                Some(key_status) => Ok(rejected(key_status, message)),
+                None => Err(Error::from_status(status.as_u16(), message)),
+            };
+        }
+
+        let page: ModelsResponse = response
+            .json()
+            .await
+            .map_err(|e| Error::new(ErrorKind::InvalidResponse, format!("Failed to parse model list: {}", e)))?;
+        models.extend(page.models);
+        match page.next_page_token.filter(|t| !t.is_empty()) {
+            Some(token) => page_token = Some(token),
+            None => break,
         }
     }
 
-    Err("No text in response".to_string())
+    let known = known_models();
+    let image_models: Vec<ModelInfo> = models
+        .into_iter()
+        .filter(|m| m.supported_generation_methods.iter().any(|g| g == "generateContent"))
+        .filter_map(|m| {
+            let id = m.name.strip_prefix("models/").unwrap_or(&m.name).to_string();
+            if let Some(info) = known.iter().find(|k| k.id == id) {
+                return Some(info.clone());
+            }
+            id.contains("-image").then(|| ModelInfo {
+                name: m.display_name.unwrap_or_else(|| id.clone()),
+                id,
+                fast: false,
+            })
+        })
+        .collect();
+
+    if image_models.is_empty() {
+        return Ok(rejected(
+            KeyStatus::PermissionDenied,
+            "The key works but has no image generation models enabled".to_string(),
+        ));
+    }
+    Ok(KeyValidation {
+        status: KeyStatus::Valid,
+        message: None,
+        image_models,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_server::{MockResponse, MockServer, gemini as responses};
+    use crate::provider::InputImage;
+
+    fn client(server: &MockServer) -> GeminiClient {
+        GeminiClient::with_base_url("test-key", &server.base_url)
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_returns_decoded_bytes() {
+        let png = responses::green_background_png();
+        let body = png.clone();
+        let server = MockServer::start(move |_| responses::image(&body)).await;
+
+        let generated = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_FAST)
+            .await
+            .unwrap();
+        assert_eq!(generated.data, png);
+        // The mock reports 12 prompt and 1290 image tokens
+        assert_eq!(
+            generated.usage,
+            Usage {
+                requests: 1,
+                input_tokens: 12,
+                output_tokens: 1290,
+                images: 1
+            }
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, format!("/models/{}:generateContent", MODEL_FAST));
+        assert_eq!(requests[0].header("x-goog-api-key"), Some("test-key"));
+        let body = requests[0].body_json();
+        assert_eq!(body["contents"][0]["parts"][0]["text"], "a prompt");
+        assert_eq!(body["generationConfig"]["responseModalities"][0], "IMAGE");
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_sends_input_images_before_text() {
+        let png = responses::green_background_png();
+        let body = png.clone();
+        let server = MockServer::start(move |_| responses::image(&body)).await;
+
+        let input = InputImage::from_bytes(png.clone()).unwrap();
+        let prompt = Prompt::text("make the hat red").with_images(vec![input]);
+        generate_emoji_image(&client(&server), &prompt, MODEL_FAST)
+            .await
+            .unwrap();
+
+        let body = server.requests()[0].body_json();
+        let parts = body["contents"][0]["parts"].as_array().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(parts[0]["inlineData"]["data"], STANDARD.encode(&png));
+        assert!(parts[0].get("text").is_none());
+        assert_eq!(parts[1]["text"], "make the hat red");
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_http_error_includes_status() {
+        let server = MockServer::start(|_| responses::error(500, "backend exploded")).await;
+
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("500"), "{}", err);
+        assert!(err.message.contains("backend exploded"), "{}", err);
+        assert_eq!(err.kind, ErrorKind::ServerError);
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_rate_limit_is_retryable() {
+        let server = MockServer::start(|_| responses::error(429, "slow down").with_header("Retry-After", "3")).await;
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::RateLimited);
+        assert_eq!(err.retry_after(), Some(Duration::from_secs(3)));
+
+        // Without a header, fall back to the RetryInfo in the body
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                429,
+                serde_json::json!({ "error": { "code": 429, "details": [{ "retryDelay": "1.5s" }] } }),
+            )
+        })
+        .await;
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.retry_after(), Some(Duration::from_millis(1500)));
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_error_kinds() {
+        let server = MockServer::start(|_| responses::error(400, "bad request")).await;
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::ApiError);
+        assert!(!err.is_retryable());
+
+        let server = MockServer::start(|_| responses::error(401, "API key not valid")).await;
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidApiKey);
+
+        // What Gemini actually sends for an unknown key
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                400,
+                serde_json::json!({
+                    "error": { "code": 400, "message": "API key not valid.", "details": [{ "reason": "API_KEY_INVALID" }] }
+                }),
+            )
+        })
+        .await;
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidApiKey);
+        assert!(!err.is_retryable());
+        let err = generate_filename(&client(&server), &["🤠".to_string()], None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidApiKey);
+
+        // Matches what validate_api_key reports for the same status
+        let server = MockServer::start(|_| responses::error(403, "PERMISSION_DENIED")).await;
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::PermissionDenied);
+
+        // Nothing listening on the port
+        let err = generate_emoji_image(
+            &GeminiClient::with_base_url("k", "http://127.0.0.1:1"),
+            &Prompt::text("a prompt"),
+            MODEL_PRO,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Network);
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_text_only_response_is_an_error() {
+        let server = MockServer::start(|_| responses::text("I can't draw that")).await;
+
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "No image found in response");
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_empty_candidates() {
+        let server = MockServer::start(|_| responses::empty_candidates()).await;
+
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "No image found in response");
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_safety_block() {
+        let server = MockServer::start(|_| responses::safety_block()).await;
+
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert_eq!(err.message, "Prompt blocked: SAFETY");
+        assert_eq!(err.kind, ErrorKind::SafetyBlocked);
+        // Blocked prompts are still billed
+        let usage = err.usage.unwrap();
+        assert_eq!((usage.requests, usage.images), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_generate_image_malformed_json() {
+        let server = MockServer::start(|_| responses::malformed()).await;
+
+        let err = generate_emoji_image(&client(&server), &Prompt::text("a prompt"), MODEL_PRO)
+            .await
+            .unwrap_err();
+        assert!(err.message.starts_with("Failed to parse response"), "{}", err);
+        assert_eq!(err.kind, ErrorKind::InvalidResponse);
+        assert_eq!(err.usage.unwrap().requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_generate_filename_trims_text() {
+        let server = MockServer::start(|_| responses::text("  cowboy_scream\n")).await;
+        let emojis = vec!["🤠".to_string(), "😱".to_string()];
+
+        let name = generate_filename(&client(&server), &emojis, Some("pixel art"))
+            .await
+            .unwrap();
+        assert_eq!(name.name, "cowboy_scream");
+        assert_eq!(name.usage.requests, 1);
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, format!("/models/{}:generateContent", MODEL_TEXT));
+        let prompt = requests[0].body_json()["contents"][0]["parts"][0]["text"].to_string();
+        assert!(prompt.contains("🤠 😱"), "{}", prompt);
+        assert!(prompt.contains("pixel art"), "{}", prompt);
+    }
+
+    #[tokio::test]
+    async fn test_generate_filename_errors() {
+        let emojis = vec!["🤠".to_string()];
+
+        let server = MockServer::start(|_| responses::error(403, "denied")).await;
+        let err = generate_filename(&client(&server), &emojis, None).await.unwrap_err();
+        assert!(err.message.contains("403"), "{}", err);
+        assert_eq!(err.kind, ErrorKind::PermissionDenied);
+        assert!(err.usage.is_none());
+
+        let server = MockServer::start(|_| responses::empty_candidates()).await;
+        let err = generate_filename(&client(&server), &emojis, None).await.unwrap_err();
+        assert_eq!(err.message, "No text in response");
+
+        let server = MockServer::start(|_| responses::malformed()).await;
+        let err = generate_filename(&client(&server), &emojis, None).await.unwrap_err();
+        assert!(err.message.starts_with("Failed to parse response"), "{}", err);
+    }
+
+    fn model(name: &str, methods: &[&str]) -> serde_json::Value {
+        serde_json::json!({ "name": format!("models/{}", name), "supportedGenerationMethods": methods })
+    }
+
+    #[tokio::test]
+    async fn test_validate_api_key_lists_image_models() {
+        let server = MockServer::start(|request| {
+            // Two pages, to check the page token is followed
+            if request.path.contains("pageToken=next") {
+                MockResponse::json(
+                    200,
+                    serde_json::json!({ "models": [model(MODEL_PRO, &["generateContent"])] }),
+                )
+            } else {
+                MockResponse::json(
+                    200,
+                    serde_json::json!({
+                        "models": [
+                            model(MODEL_FAST, &["generateContent", "countTokens"]),
+                            model(MODEL_TEXT, &["generateContent"]),
+                            model("imagen-4.0-generate-001", &["predict"]),
+                        ],
+                        "nextPageToken": "next"
+                    }),
+                )
+            }
+        })
+        .await;
+
+        let validation = validate_api_key(&client(&server)).await.unwrap();
+        assert_eq!(validation.status, KeyStatus::Valid);
+        let ids: Vec<&str> = validation.image_models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, [MODEL_FAST, MODEL_PRO]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "GET");
+        assert!(requests[0].path.starts_with("/models?"), "{}", requests[0].path);
+        assert_eq!(requests[0].header("x-goog-api-key"), Some("test-key"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_api_key_redacts_message() {
+        const KEY: &str = "validate-test-key-0123456789";
+        let server = MockServer::start(|_| {
+            responses::error(
+                403,
+                &format!("Key {} is not allowed, see https://x.test/models?key={}", KEY, KEY),
+            )
+        })
+        .await;
+        // Registered by `api_key` when the key is read, or by the command when it's passed in
+        redact::register_secret(KEY);
+        let validation = validate_api_key(&GeminiClient::with_base_url(KEY, &server.base_url))
+            .await
+            .unwrap();
+        assert_eq!(validation.status, KeyStatus::PermissionDenied);
+        let message = validation.message.unwrap();
+        assert!(!message.contains(KEY), "{}", message);
+        assert!(message.contains("key=[REDACTED]"), "{}", message);
+    }
 
-    #[test]
-    fn test_resize_inconsistent_background_returns_warning() {
-        // This image has a green background but one corner has a hand (skin tone)
-        // Previously this would fail; now it should succeed with a warning
-        let image_bytes = include_bytes!("../tests/fixtures/cowboy_friends.png");
-        let result = resize_to_emoji(image_bytes);
-
-        assert!(result.is_ok(), "Should succeed with warning, not fail: {:?}", result.err());
-        let processed = result.unwrap();
-        assert!(processed.warning.is_some(), "Should have a warning about excluded corners");
-        assert!(!processed.flood_fill.is_empty(), "Should have flood_fill output");
-        assert!(!processed.color_key.is_empty(), "Should have color_key output");
-
-        // Verify the warning mentions excluded corners
-        let warning = processed.warning.unwrap();
-        assert!(warning.contains("corner"), "Warning should mention corners: {}", warning);
+    #[tokio::test]
+    async fn test_validate_api_key_statuses() {
+        let invalid = serde_json::json!({
+            "error": { "code": 400, "message": "API key not valid.", "details": [{ "reason": "API_KEY_INVALID" }] }
+        });
+        let server = MockServer::start(move |_| MockResponse::json(400, invalid.clone())).await;
+        let validation = validate_api_key(&client(&server)).await.unwrap();
+        assert_eq!(validation.status, KeyStatus::Invalid);
+        assert!(validation.image_models.is_empty());
+
+        let server = MockServer::start(|_| responses::error(403, "PERMISSION_DENIED")).await;
+        assert_eq!(
+            validate_api_key(&client(&server)).await.unwrap().status,
+            KeyStatus::PermissionDenied
+        );
+
+        // Listing works, but no image model is available to the key
+        let server = MockServer::start(|_| {
+            MockResponse::json(
+                200,
+                serde_json::json!({ "models": [model(MODEL_TEXT, &["generateContent"])] }),
+            )
+        })
+        .await;
+        let validation = validate_api_key(&client(&server)).await.unwrap();
+        assert_eq!(validation.status, KeyStatus::PermissionDenied);
+        assert!(validation.message.unwrap().contains("no image generation models"));
+
+        // Nothing is listening on port 1
+        let unreachable = GeminiClient::with_base_url("test-key", "http://127.0.0.1:1");
+        assert_eq!(
+            validate_api_key(&unreachable).await.unwrap().status,
+            KeyStatus::NetworkUnreachable
+        );
+
+        // A server error says nothing about the key
+        let server = MockServer::start(|_| responses::error(503, "unavailable")).await;
+        assert_eq!(
+            validate_api_key(&client(&server)).await.unwrap_err().kind,
+            ErrorKind::ServerError
+        );
     }
 }
@@ -0,0 +1,976 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+use crate::background::{self, BackgroundOptions, KeyColor, OutputOptions, ProcessedEmoji};
+use crate::cancel::CancelToken;
+use crate::error::{Error, ErrorKind, Result};
+use crate::provider::{GeneratedImage, ImageProvider, InputImage, Prompt};
+use crate::redact;
+use crate::retry::{RetryBudget, RetryPolicy};
+use crate::usage::Usage;
+
+#[derive(serde::Serialize)]
+pub struct ImageResult {
+    pub index: usize,
+    pub flood_fill: Option<String>,
+    pub color_key: Option<String>,
+    pub soft_matte: Option<String>,
+    /// Variants at the run's additional output sizes, if any
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_sizes: Vec<SizedImages>,
+    pub warning: Option<String>,
+    pub error: Option<Error>,
+}
+
+#[derive(serde::Serialize)]
+pub struct GenerationResult {
+    pub generation_id: String,
+    pub results: Vec<ImageResult>,
+    pub mime_type: String,
+    /// Summed over every slot whose request completed
+    pub usage: Usage,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotStatus {
+    /// Sent once before any slot work begins, so the caller can cancel right
+    /// away. Carries the generation id and no slot `index`.
+    Started,
+    /// Sent for every slot when the generation starts, carrying its id
+    Pending,
+    /// A transient failure happened and the slot will be retried
+    Retrying,
+    Success,
+    Error,
+    /// The generation was cancelled before this slot finished
+    Cancelled,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct GenerationProgress {
+    pub generation_id: String,
+    /// Slot the update is about; `None` only for the generation-wide `Started` event
+    pub index: Option<usize>,
+    pub status: SlotStatus,
+    /// Attempt that just failed, for `Retrying` updates
+    pub attempt: Option<u32>,
+    /// Wait before the next attempt, for `Retrying` updates
+    pub retry_in_ms: Option<u64>,
+    pub flood_fill: Option<String>,
+    pub color_key: Option<String>,
+    pub soft_matte: Option<String>,
+    pub raw_image: Option<String>, // Original image before processing (for debugging failed items)
+    /// Variants at the run's additional output sizes, if any
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_sizes: Vec<SizedImages>,
+    pub warning: Option<String>,
+    pub error: Option<Error>,
+    /// What the slot's request consumed, once the provider has answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// Base64 PNGs of every variant at one additional output size
+#[derive(Clone, serde::Serialize)]
+pub struct SizedImages {
+    pub size: u32,
+    pub flood_fill: String,
+    pub color_key: String,
+    pub soft_matte: String,
+}
+
+impl GenerationProgress {
+    /// The `Started` event of a generation
+    pub(crate) fn started(generation_id: &str) -> Self {
+        Self {
+            index: None,
+            ..Self::update(generation_id, 0, SlotStatus::Started)
+        }
+    }
+
+    /// A status-only update with no image data
+    pub(crate) fn update(generation_id: &str, index: usize, status: SlotStatus) -> Self {
+        Self {
+            generation_id: generation_id.to_string(),
+            index: Some(index),
+            status,
+            attempt: None,
+            retry_in_ms: None,
+            flood_fill: None,
+            color_key: None,
+            soft_matte: None,
+            raw_image: None,
+            extra_sizes: Vec::new(),
+            warning: None,
+            error: None,
+            usage: None,
+        }
+    }
+}
+
+/// Settings shared by every slot of one generation
+pub struct GenerationRun {
+    pub id: String,
+    pub policy: RetryPolicy,
+    pub cancel: Arc<CancelToken>,
+    pub background: BackgroundOptions,
+    pub output: OutputOptions,
+}
+
+/// Build the generation prompt. `references` is the number of reference
+/// images sent ahead of the text, which the prompt refers to as image 1, 2, ...
+pub fn build_prompt(emojis: &[String], modifier: Option<&str>, references: usize) -> String {
+    let mut ingredients = Vec::new();
+    if !emojis.is_empty() {
+        ingredients.push(emojis.join(" "));
+    }
+    match references {
+        0 => {}
+        1 => ingredients.push("the custom emoji in image 1".to_string()),
+        n => {
+            let positions: Vec<String> = (1..=n).map(|i| i.to_string()).collect();
+            ingredients.push(format!("the custom emojis in images {}", positions.join(", ")));
+        }
+    }
+    let emoji_str = ingredients.join(" and ");
+
+    let base_prompt = format!(
+        "Create a single emoji combining: {}. \
+         CRITICAL: Keep it SIMPLE and MINIMAL like standard Unicode emojis. \
+         Copy the exact visual style of Google Noto Color Emoji - simple shapes, minimal details, clean lines. \
+         Do NOT add extra details, textures, or creative interpretations. \
+         The result should look like it belongs in the standard emoji keyboard. \
+         Soft 3D with subtle gradients, bright saturated colors. \
+         NO shadows, NO glow, NO complex textures. \
+         Solid {} background, no variations.",
+        emoji_str,
+        KeyColor::for_subject(emojis, modifier).prompt_text()
+    );
+    let base_prompt = if references > 0 {
+        format!(
+            "{} The attached images are custom emojis, not style references: \
+             keep their recognizable shapes, colors and features.",
+            base_prompt
+        )
+    } else {
+        base_prompt
+    };
+
+    match modifier {
+        Some(mod_text) if !mod_text.trim().is_empty() => {
+            format!("{} Additional modification: {}", base_prompt, mod_text.trim())
+        }
+        _ => base_prompt,
+    }
+}
+
+/// The prompt for a new generation: Unicode emojis plus local reference images
+/// (e.g. custom Slack emojis), which are sent ahead of the text
+pub fn prepare_prompt(
+    emojis: &[String],
+    modifier: Option<&str>,
+    reference_paths: &[impl AsRef<Path>],
+) -> Result<Prompt> {
+    let references = reference_paths
+        .iter()
+        .map(|path| load_reference_image(path.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    if emojis.is_empty() && references.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Select at least one emoji or reference image",
+        ));
+    }
+    Ok(Prompt::text(build_prompt(emojis, modifier, references.len())).with_images(references))
+}
+
+/// Reference images larger than this are rejected rather than uploaded
+const MAX_REFERENCE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Load a local PNG or GIF (e.g. an exported Slack emoji) to send as a
+/// reference image. GIFs are sent as a PNG of their first frame, since the
+/// image models don't accept GIF input.
+pub fn load_reference_image(path: &Path) -> Result<InputImage> {
+    let context = format!("Failed to read reference image {}", path.display());
+    let size = fs::metadata(path).map_err(|e| Error::from(e).context(&context))?.len();
+    if size > MAX_REFERENCE_BYTES {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Reference image {} is too large ({} KB, max {} KB)",
+                path.display(),
+                size / 1024,
+                MAX_REFERENCE_BYTES / 1024
+            ),
+        ));
+    }
+
+    let data = fs::read(path).map_err(|e| Error::from(e).context(&context))?;
+    match image::guess_format(&data) {
+        Ok(image::ImageFormat::Png) => InputImage::from_bytes(data),
+        Ok(image::ImageFormat::Gif) => {
+            let frame = image::load_from_memory_with_format(&data, image::ImageFormat::Gif)
+                .map_err(|e| Error::from(e).context(&context))?;
+            let mut png = Cursor::new(Vec::new());
+            frame.write_to(&mut png, image::ImageFormat::Png)?;
+            InputImage::from_bytes(png.into_inner())
+        }
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Reference image {} is not a PNG or GIF", path.display()),
+        )),
+    }
+}
+
+/// Prompt for editing an earlier result, which is sent along as the only input image.
+/// `emojis` are the result's source emojis, used to pick the background color.
+pub fn build_refine_prompt(instruction: &str, emojis: &[String]) -> String {
+    format!(
+        "Edit the attached emoji: {}. \
+         Change only what is asked and keep everything else the same: \
+         the subject, composition and the simple Google Noto Color Emoji style. \
+         NO shadows, NO glow, NO complex textures. \
+         Solid {} background, no variations.",
+        instruction.trim(),
+        KeyColor::for_subject(emojis, Some(instruction)).prompt_text()
+    )
+}
+
+/// Request one image, retrying transient failures while the policy and the
+/// generation's shared budget allow, and reporting each retry through `on_progress`
+pub(crate) async fn generate_with_retry(
+    provider: &dyn ImageProvider,
+    prompt: &Prompt,
+    model: &str,
+    index: usize,
+    run: &GenerationRun,
+    budget: &RetryBudget,
+    on_progress: &(dyn Fn(GenerationProgress) + Sync),
+) -> Result<GeneratedImage> {
+    let policy = &run.policy;
+    let mut attempt = 1;
+    loop {
+        let err = match provider.generate(prompt, model).await {
+            Ok(image) => return Ok(image),
+            Err(e) => e,
+        };
+        if !err.is_retryable() || attempt >= policy.max_attempts {
+            return Err(err);
+        }
+        // A server asking for a longer wait than the policy allows gets its answer surfaced instead
+        let Some(delay) = policy.delay(attempt, err.retry_after()) else {
+            let wait = err.retry_after().unwrap_or_default().as_secs_f64().ceil();
+            let context = match err.kind {
+                ErrorKind::RateLimited => format!("Rate limited, try again in {} s", wait),
+                _ => format!("Server asked to retry in {} s", wait),
+            };
+            return Err(err.context(&context));
+        };
+        if !budget.try_take() {
+            return Err(err);
+        }
+        on_progress(GenerationProgress {
+            attempt: Some(attempt),
+            retry_in_ms: Some(delay.as_millis() as u64),
+            error: Some(err),
+            ..GenerationProgress::update(&run.id, index, SlotStatus::Retrying)
+        });
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Cut out an image on the blocking pool, so flood fills and large outputs
+/// don't stall the runtime driving the other slots' requests and cancellation
+async fn process_image(
+    image_bytes: Vec<u8>,
+    options: BackgroundOptions,
+    output: OutputOptions,
+) -> Result<ProcessedEmoji> {
+    tokio::task::spawn_blocking(move || background::resize_to_emoji(&image_bytes, &options, &output))
+        .await
+        .map_err(|e| Error::new(ErrorKind::ImageProcessing, format!("Processing task failed: {}", e)))?
+}
+
+/// Generate the given slots in parallel and report each one through
+/// `on_progress` as soon as it finishes. Only fails if every slot failed.
+///
+/// Slot indices are usually `0..count`, or a single index to regenerate one
+/// image of an earlier generation under the same id.
+///
+/// Cancelling `run.cancel` drops the outstanding requests and reports the
+/// unfinished slots as cancelled.
+pub async fn generate_slots(
+    provider: &dyn ImageProvider,
+    prompt: &Prompt,
+    model: &str,
+    slots: &[usize],
+    run: &GenerationRun,
+    on_progress: &(dyn Fn(GenerationProgress) + Sync),
+) -> Result<GenerationResult> {
+    let budget = RetryBudget::new(run.policy.budget);
+    let budget = &budget;
+
+    // Announce every slot up front so the caller learns the generation id
+    for &index in slots {
+        on_progress(GenerationProgress::update(&run.id, index, SlotStatus::Pending));
+    }
+
+    // Generate images in parallel, streaming results as they complete
+    let futures: Vec<_> = slots
+        .iter()
+        .map(|&index| async move {
+            // First, try to get the raw image
+            let raw_result = tokio::select! {
+                result = generate_with_retry(provider, prompt, model, index, run, budget, on_progress) => result,
+                _ = run.cancel.cancelled() => Err(Error::new(ErrorKind::Cancelled, "Generation cancelled")),
+            };
+
+            // Billed responses without a usable image report their usage on the error
+            let usage = match &raw_result {
+                Ok(image) => Some(image.usage),
+                Err(e) => e.usage,
+            };
+            let (result, raw_image, warning) = match raw_result {
+                Ok(GeneratedImage { data: image_bytes, .. }) => {
+                    let raw_b64 = STANDARD.encode(&image_bytes);
+                    // Try to process it
+                    match process_image(image_bytes, run.background, run.output.clone()).await {
+                        Ok(processed) => (
+                            Ok((
+                                STANDARD.encode(&processed.flood_fill),
+                                STANDARD.encode(&processed.color_key),
+                                STANDARD.encode(&processed.soft_matte),
+                                processed
+                                    .extra_sizes
+                                    .iter()
+                                    .map(|sized| SizedImages {
+                                        size: sized.size,
+                                        flood_fill: STANDARD.encode(&sized.flood_fill),
+                                        color_key: STANDARD.encode(&sized.color_key),
+                                        soft_matte: STANDARD.encode(&sized.soft_matte),
+                                    })
+                                    .collect::<Vec<_>>(),
+                            )),
+                            Some(raw_b64),
+                            processed.warning.map(|warning| redact::redact(&warning)),
+                        ),
+                        Err(e) => (Err(e), Some(raw_b64), None), // Processing failed, but we have raw image
+                    }
+                }
+                Err(e) => (Err(e), None, None), // Generation failed, no raw image
+            };
+
+            // Send progress immediately when this image completes
+            let progress = match &result {
+                Ok((ff, ck, sm, extra_sizes)) => GenerationProgress {
+                    flood_fill: Some(ff.clone()),
+                    color_key: Some(ck.clone()),
+                    soft_matte: Some(sm.clone()),
+                    extra_sizes: extra_sizes.clone(),
+                    raw_image: raw_image.clone(), // Include raw for all items
+                    warning: warning.clone(),
+                    usage,
+                    ..GenerationProgress::update(&run.id, index, SlotStatus::Success)
+                },
+                Err(e) => {
+                    let status = if e.kind == ErrorKind::Cancelled {
+                        SlotStatus::Cancelled
+                    } else {
+                        SlotStatus::Error
+                    };
+                    GenerationProgress {
+                        raw_image,
+                        error: Some(e.clone()),
+                        usage,
+                        ..GenerationProgress::update(&run.id, index, status)
+                    }
+                }
+            };
+            on_progress(progress);
+
+            (index, result, warning, usage)
+        })
+        .collect();
+
+    let mut indexed_results = futures::future::join_all(futures).await;
+
+    // Sort by index to maintain order in final result
+    indexed_results.sort_by_key(|(idx, _, _, _)| *idx);
+    let usage = indexed_results.iter().filter_map(|(_, _, _, usage)| *usage).sum();
+
+    let results: Vec<ImageResult> = indexed_results
+        .into_iter()
+        .map(|(index, r, warning, _)| match r {
+            Ok((ff, ck, sm, extra_sizes)) => ImageResult {
+                index,
+                flood_fill: Some(ff),
+                color_key: Some(ck),
+                soft_matte: Some(sm),
+                extra_sizes,
+                warning,
+                error: None,
+            },
+            Err(e) => ImageResult {
+                index,
+                flood_fill: None,
+                color_key: None,
+                soft_matte: None,
+                extra_sizes: Vec::new(),
+                warning: None,
+                error: Some(e),
+            },
+        })
+        .collect();
+
+    // Check if all failed
+    let has_any_success = results.iter().any(|r| r.flood_fill.is_some());
+    if !has_any_success {
+        let first_error = results
+            .iter()
+            .find_map(|r| r.error.clone())
+            .unwrap_or_else(|| Error::new(ErrorKind::InvalidInput, "No images requested"));
+        return Err(first_error.context("Failed to generate images"));
+    }
+
+    Ok(GenerationResult {
+        generation_id: run.id.clone(),
+        results,
+        mime_type: "image/png".to_string(),
+        usage,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemini::{GeminiClient, GeminiProvider};
+    use crate::mock_server::{MockServer, gemini as responses};
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn provider(server: &MockServer) -> GeminiProvider {
+        GeminiProvider::new(GeminiClient::with_base_url("test-key", &server.base_url))
+    }
+
+    fn run(policy: RetryPolicy) -> GenerationRun {
+        GenerationRun {
+            id: "test-generation".to_string(),
+            policy,
+            cancel: Arc::new(CancelToken::default()),
+            background: BackgroundOptions::default(),
+            output: OutputOptions::default(),
+        }
+    }
+
+    fn no_retry() -> GenerationRun {
+        run(RetryPolicy {
+            max_attempts: 1,
+            ..fast_retry_policy()
+        })
+    }
+
+    fn fast_retry() -> GenerationRun {
+        run(fast_retry_policy())
+    }
+
+    fn fast_retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Progress updates other than the initial pending ones
+    fn finished(progress: Mutex<Vec<GenerationProgress>>) -> Vec<GenerationProgress> {
+        let progress = progress.into_inner().unwrap();
+        progress
+            .into_iter()
+            .filter(|p| p.status != SlotStatus::Pending)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_partial_failure() {
+        // Every other request fails
+        let png = responses::green_background_png();
+        let calls = AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            if calls.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+                responses::image(&png)
+            } else {
+                responses::error(500, "internal")
+            }
+        })
+        .await;
+
+        let progress = Mutex::new(Vec::new());
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0, 1, 2, 3],
+            &no_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.results.len(), 4);
+        assert_eq!(result.results.iter().filter(|r| r.flood_fill.is_some()).count(), 2);
+        assert_eq!(result.results.iter().filter(|r| r.error.is_some()).count(), 2);
+        // Only the two answered requests are counted
+        assert_eq!(result.usage.requests, 2);
+        assert_eq!(result.usage.output_tokens, 2 * 1290);
+
+        let progress = finished(progress);
+        let mut indices: Vec<_> = progress.iter().filter_map(|p| p.index).collect();
+        indices.sort();
+        assert_eq!(indices, vec![0, 1, 2, 3]);
+        for p in &progress {
+            if p.error.is_some() {
+                assert!(p.flood_fill.is_none() && p.raw_image.is_none());
+            } else {
+                assert!(
+                    p.flood_fill.is_some() && p.color_key.is_some() && p.soft_matte.is_some() && p.raw_image.is_some()
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keys_in_error_bodies_never_reach_progress() {
+        // A Google-shaped key is caught by pattern; any other stored key once it has been registered
+        const GOOGLE_KEY: &str = "AIzaSyD-progress-test-0123456789abcdefghij";
+        const STORED_KEY: &str = "stored-key-with-no-recognisable-shape";
+        redact::register_secret(STORED_KEY);
+        let calls = AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            // A retryable failure first, so Retrying updates are checked too
+            let status = if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                503
+            } else {
+                400
+            };
+            responses::error(
+                status,
+                &format!(
+                    "Bad request for url https://x.test/models?key={0}: key {0}, alt {1}",
+                    GOOGLE_KEY, STORED_KEY
+                ),
+            )
+        })
+        .await;
+
+        let progress = Mutex::new(Vec::new());
+        let err = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &fast_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        let progress = finished(progress);
+        assert_eq!(
+            progress.iter().map(|p| p.status).collect::<Vec<_>>(),
+            [SlotStatus::Retrying, SlotStatus::Error]
+        );
+        for update in &progress {
+            let json = serde_json::to_string(update).unwrap();
+            assert!(!json.contains(GOOGLE_KEY) && !json.contains(STORED_KEY), "{}", json);
+            assert!(json.contains(redact::REDACTED), "{}", json);
+        }
+        assert!(
+            !err.message.contains(GOOGLE_KEY) && !err.message.contains(STORED_KEY),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_all_failed() {
+        let server = MockServer::start(|_| responses::safety_block()).await;
+
+        let progress = Mutex::new(Vec::new());
+        let err = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0, 1],
+            &fast_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert_eq!(err.kind, ErrorKind::SafetyBlocked);
+        assert_eq!(err.message, "Failed to generate images: Prompt blocked: SAFETY");
+        // Failed slots are still streamed
+        assert_eq!(finished(progress).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_processing_failure_keeps_raw_image() {
+        // Diagonal stripes of unrelated colors leave no background to detect
+        let palette = [[255, 0, 0, 255], [0, 0, 255, 255], [255, 255, 0, 255], [0, 0, 0, 255]];
+        let img = image::RgbaImage::from_fn(8, 8, |x, y| image::Rgba(palette[((x + y) % 4) as usize]));
+        let mut png = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let png = png.into_inner();
+        let server = MockServer::start(move |_| responses::image(&png)).await;
+
+        let progress = Mutex::new(Vec::new());
+        let err = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &fast_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert_eq!(err.kind, ErrorKind::NoBackground);
+        let progress = finished(progress);
+        assert!(progress[0].raw_image.is_some());
+        assert!(progress[0].flood_fill.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_retries_transient_errors() {
+        // First two calls are rate limited, then the backend recovers
+        let png = responses::green_background_png();
+        let calls = AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                responses::error(429, "quota")
+            } else {
+                responses::image(&png)
+            }
+        })
+        .await;
+
+        let progress = Mutex::new(Vec::new());
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &fast_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .unwrap();
+
+        assert!(result.results[0].flood_fill.is_some());
+        assert_eq!(server.requests().len(), 3);
+        let statuses: Vec<_> = finished(progress).iter().map(|p| (p.status, p.attempt)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (SlotStatus::Retrying, Some(1)),
+                (SlotStatus::Retrying, Some(2)),
+                (SlotStatus::Success, None)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_retry_budget_is_shared() {
+        let server = MockServer::start(|_| responses::error(503, "unavailable")).await;
+        let run = run(RetryPolicy {
+            max_attempts: 10,
+            budget: 3,
+            ..fast_retry_policy()
+        });
+
+        let err = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0, 1, 2, 3],
+            &run,
+            &|_| {},
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert_eq!(err.kind, ErrorKind::ServerError);
+        // 4 first attempts plus the 3 retries in the budget
+        assert_eq!(server.requests().len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_does_not_retry_client_errors() {
+        let server = MockServer::start(|_| responses::error(400, "bad")).await;
+
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &fast_retry(),
+            &|_| {},
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_permission_denied_reaches_frontend() {
+        // The frontend sends the user back to the key screen on this kind
+        let server = MockServer::start(|_| responses::error(403, "PERMISSION_DENIED")).await;
+
+        let progress = Mutex::new(Vec::new());
+        let err = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &fast_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(serde_json::to_value(&err).unwrap()["kind"], "permission_denied");
+        let progress = finished(progress);
+        let slot_error = serde_json::to_value(&progress[0].error).unwrap();
+        assert_eq!(slot_error["kind"], "permission_denied");
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_surfaces_long_retry_after() {
+        let server = MockServer::start(|_| responses::error(429, "quota").with_header("Retry-After", "60")).await;
+
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &fast_retry(),
+            &|_| {},
+        )
+        .await;
+
+        let Err(err) = result else {
+            panic!("expected a rate limit error")
+        };
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(err.kind, ErrorKind::RateLimited);
+        assert_eq!(err.retry_after_ms, Some(60_000));
+        assert!(err.message.contains("try again in 60 s"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_long_retry_after_on_server_error() {
+        let server = MockServer::start(|_| responses::error(503, "unavailable").with_header("Retry-After", "60")).await;
+
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &fast_retry(),
+            &|_| {},
+        )
+        .await;
+
+        let Err(err) = result else {
+            panic!("expected a server error")
+        };
+        assert_eq!(err.kind, ErrorKind::ServerError);
+        assert!(err.message.contains("Server asked to retry in 60 s"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_announces_pending_slots() {
+        let png = responses::green_background_png();
+        let server = MockServer::start(move |_| responses::image(&png)).await;
+
+        let progress = Mutex::new(Vec::new());
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0, 1, 2],
+            &no_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.generation_id, "test-generation");
+        let progress = progress.into_inner().unwrap();
+        assert!(progress[..3].iter().all(|p| p.status == SlotStatus::Pending));
+        assert!(progress.iter().all(|p| p.generation_id == "test-generation"));
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_cancel_stops_unfinished_slots() {
+        // The backend never recovers, so slots sit in retry backoff until cancelled
+        let server = MockServer::start(|_| responses::error(503, "unavailable")).await;
+        let run = run(RetryPolicy {
+            max_attempts: 100,
+            budget: 100,
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(60),
+        });
+
+        let progress = Mutex::new(Vec::new());
+        let provider = provider(&server);
+        let on_progress = |p: GenerationProgress| {
+            if p.status == SlotStatus::Retrying {
+                run.cancel.cancel();
+            }
+            progress.lock().unwrap().push(p)
+        };
+        let prompt = Prompt::text("prompt");
+        let batch = generate_slots(&provider, &prompt, "model", &[0, 1], &run, &on_progress);
+        let err = tokio::time::timeout(Duration::from_secs(5), batch)
+            .await
+            .expect("cancel should end the generation")
+            .err()
+            .unwrap();
+
+        assert_eq!(err.kind, ErrorKind::Cancelled);
+        let progress = finished(progress);
+        let cancelled = progress.iter().filter(|p| p.status == SlotStatus::Cancelled).count();
+        assert_eq!(cancelled, 2);
+    }
+
+    #[tokio::test]
+    async fn test_generate_slots_only_runs_requested_indices() {
+        let png = responses::green_background_png();
+        let server = MockServer::start(move |_| responses::image(&png)).await;
+
+        let progress = Mutex::new(Vec::new());
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[2],
+            &no_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(result.results.len(), 1);
+        assert_eq!(result.results[0].index, 2);
+        assert!(finished(progress).iter().all(|p| p.index == Some(2)));
+    }
+
+    #[tokio::test]
+    async fn test_billed_failures_report_usage() {
+        let server = MockServer::start(|_| responses::safety_block()).await;
+
+        let progress = Mutex::new(Vec::new());
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &no_retry(),
+            &|p| progress.lock().unwrap().push(p),
+        )
+        .await;
+        assert!(result.is_err());
+
+        let finished = finished(progress);
+        assert_eq!(finished[0].status, SlotStatus::Error);
+        assert_eq!(finished[0].usage.map(|u| (u.requests, u.images)), Some((1, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_generate_slots_returns_extra_sizes() {
+        let png = responses::green_background_png();
+        let server = MockServer::start(move |_| responses::image(&png)).await;
+        let run = GenerationRun {
+            output: OutputOptions::new(Some(vec![128, 64]), None, None).unwrap(),
+            ..no_retry()
+        };
+
+        let result = generate_slots(
+            &provider(&server),
+            &Prompt::text("prompt"),
+            "model",
+            &[0],
+            &run,
+            &|_| {},
+        )
+        .await
+        .unwrap();
+
+        let sizes: Vec<u32> = result.results[0].extra_sizes.iter().map(|s| s.size).collect();
+        assert_eq!(sizes, [64]);
+    }
+
+    #[test]
+    fn test_build_prompt_refers_to_references_by_position() {
+        let emojis = vec!["🤠".to_string()];
+        assert!(build_prompt(&emojis, None, 1).contains("combining: 🤠 and the custom emoji in image 1."));
+        assert!(build_prompt(&[], None, 2).contains("combining: the custom emojis in images 1, 2."));
+        assert!(!build_prompt(&emojis, None, 0).contains("attached images"));
+    }
+
+    #[test]
+    fn test_load_reference_image_converts_gif_to_png() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let gif_path = dir.join("logo.gif");
+        let img = image::RgbaImage::from_pixel(8, 8, image::Rgba([200, 0, 0, 255]));
+        img.save_with_format(&gif_path, image::ImageFormat::Gif).unwrap();
+        let reference = load_reference_image(&gif_path).unwrap();
+        assert_eq!(reference.mime_type, "image/png");
+        assert_eq!(image::guess_format(&reference.data).unwrap(), image::ImageFormat::Png);
+
+        let png_path = dir.join("mascot.png");
+        fs::write(&png_path, responses::green_background_png()).unwrap();
+        assert_eq!(load_reference_image(&png_path).unwrap().mime_type, "image/png");
+
+        let text_path = dir.join("notes.txt");
+        fs::write(&text_path, "not an image").unwrap();
+        assert_eq!(
+            load_reference_image(&text_path).unwrap_err().kind,
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_build_prompt_modifier() {
+        let emojis = vec!["🤠".to_string(), "😱".to_string()];
+        assert!(build_prompt(&emojis, None, 0).contains("combining: 🤠 😱."));
+        assert!(!build_prompt(&emojis, Some("  "), 0).contains("Additional modification"));
+        assert!(build_prompt(&emojis, Some(" wearing a hat "), 0).ends_with("Additional modification: wearing a hat"));
+    }
+
+    #[test]
+    fn test_build_prompt_avoids_green_background_for_green_emojis() {
+        let prompt = build_prompt(&["🐸".to_string(), "🤠".to_string()], None, 0);
+        assert!(prompt.contains("Solid bright magenta (#FF00FF) background"));
+        assert!(!prompt.contains("#00FF00"));
+    }
+}
@@ -0,0 +1,706 @@
+//! On-disk record of every generation.
+//!
+//! Each generation gets its own directory under the data dir:
+//!
+//! ```text
+//! ~/.local/share/mojimix/history/<generation_id>/
+//!     entry.json              prompt, emojis, model, timestamps, per-slot status
+//!     input_0                 input images sent with the prompt, if any
+//!     slot_0_raw.png          image as returned by the provider
+//!     slot_0_flood_fill.png   processed variants
+//!     slot_0_color_key.png
+//!     slot_0_soft_matte.png
+//!     slot_0_flood_fill_512.png   variants at each additional output size
+//!     slot_0_color_key_512.png
+//!     slot_0_soft_matte_512.png
+//!     animation.gif           encoded result of an animated generation
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{Animation, AnimationFormat};
+use crate::effects::Effect;
+use crate::error::{Error, ErrorKind, Result};
+use crate::generation::{GenerationProgress, SizedImages, SlotStatus};
+use crate::output;
+use crate::provider::{InputImage, Prompt};
+
+const ENTRY_FILE: &str = "entry.json";
+
+/// Milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// One generation and the parameters it was run with
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub prompt: String,
+    pub emojis: Vec<String>,
+    pub modifier: Option<String>,
+    pub provider: String,
+    pub model: String,
+    pub count: usize,
+    /// MIME types of the input images sent with the prompt, stored as `input_<n>`
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// Background tolerance the caller asked for; `None` means the default
+    #[serde(default)]
+    pub tolerance: Option<f32>,
+    /// Output sizes, margin and padding the caller asked for; `None` means the default
+    #[serde(default)]
+    pub sizes: Option<Vec<u32>>,
+    #[serde(default)]
+    pub margin: Option<u32>,
+    #[serde(default)]
+    pub padding: Option<u32>,
+    /// Set for animated generations, whose slots are the generated frames
+    #[serde(default)]
+    pub animation: Option<HistoryAnimation>,
+    pub slots: Vec<HistorySlot>,
+}
+
+/// What an animated generation asked for
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryAnimation {
+    pub motion: Option<String>,
+    pub effect: Option<Effect>,
+    pub format: AnimationFormat,
+    pub frames: Option<usize>,
+    /// Whether the encoded animation was written next to entry.json
+    #[serde(default)]
+    pub saved: bool,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        id: &str,
+        prompt: &str,
+        emojis: &[String],
+        modifier: Option<&str>,
+        provider: &str,
+        model: &str,
+        count: usize,
+    ) -> Self {
+        let now = now_millis();
+        Self {
+            id: id.to_string(),
+            created_at: now,
+            updated_at: now,
+            prompt: prompt.to_string(),
+            emojis: emojis.to_vec(),
+            modifier: modifier.map(str::to_string),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            count,
+            inputs: Vec::new(),
+            tolerance: None,
+            sizes: None,
+            margin: None,
+            padding: None,
+            animation: None,
+            slots: Vec::new(),
+        }
+    }
+}
+
+/// Outcome of one slot. The images themselves live next to entry.json.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistorySlot {
+    pub index: usize,
+    pub status: SlotStatus,
+    pub completed_at: u64,
+    pub warning: Option<String>,
+    pub error: Option<Error>,
+    pub has_raw_image: bool,
+    pub has_flood_fill: bool,
+    pub has_color_key: bool,
+    #[serde(default)]
+    pub has_soft_matte: bool,
+    /// Additional output sizes whose variants were written
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_sizes: Vec<u32>,
+}
+
+/// A slot with its images loaded as base64
+#[derive(Serialize)]
+pub struct HistorySlotImages {
+    #[serde(flatten)]
+    pub slot: HistorySlot,
+    pub raw_image: Option<String>,
+    pub flood_fill: Option<String>,
+    pub color_key: Option<String>,
+    pub soft_matte: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_sizes: Vec<SizedImages>,
+}
+
+/// A full entry as returned by `load_history_entry`
+#[derive(Serialize)]
+pub struct HistoryEntryDetail {
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+    pub images: Vec<HistorySlotImages>,
+    /// The encoded animation as base64, for animated entries
+    pub animation_data: Option<String>,
+}
+
+/// One page of entries, newest first
+#[derive(Serialize)]
+pub struct HistoryPage {
+    pub entries: Vec<HistoryEntry>,
+    pub total: usize,
+}
+
+pub struct HistoryStore {
+    root: PathBuf,
+    // Serializes read-modify-write of entry.json files
+    lock: Mutex<()>,
+}
+
+impl HistoryStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Store under the platform data dir (~/.local/share/mojimix/history on Linux)
+    pub fn open_default() -> Self {
+        let data_dir = dirs::data_dir().unwrap_or_else(std::env::temp_dir);
+        Self::new(data_dir.join("mojimix").join("history"))
+    }
+
+    fn entry_dir(&self, id: &str) -> Result<PathBuf> {
+        // Ids come from the frontend; don't let them escape the store
+        let valid = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid history id: {}", id),
+            ));
+        }
+        Ok(self.root.join(id))
+    }
+
+    fn read_entry(&self, id: &str) -> Result<HistoryEntry> {
+        let path = self.entry_dir(id)?.join(ENTRY_FILE);
+        let contents = fs::read_to_string(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Error::new(ErrorKind::InvalidInput, format!("No history entry: {}", id)),
+            _ => Error::from(e).context("Failed to read history entry"),
+        })?;
+        serde_json::from_str(&contents)
+            .map_err(|e| Error::new(ErrorKind::Storage, format!("Corrupt history entry {}: {}", id, e)))
+    }
+
+    fn write_entry(&self, entry: &HistoryEntry) -> Result<()> {
+        let dir = self.entry_dir(&entry.id)?;
+        fs::create_dir_all(&dir).map_err(|e| Error::from(e).context("Failed to create history directory"))?;
+        let json = serde_json::to_string_pretty(entry)
+            .map_err(|e| Error::new(ErrorKind::Storage, format!("Failed to serialize history entry: {}", e)))?;
+        output::write_atomic(&dir.join(ENTRY_FILE), json.as_bytes())
+            .map_err(|e| Error::from(e).context("Failed to write history entry"))
+    }
+
+    /// Record a new generation before any of its slots finish, along with
+    /// the input images its prompt was sent with
+    pub fn create(&self, entry: &HistoryEntry, inputs: &[InputImage]) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let dir = self.entry_dir(&entry.id)?;
+        fs::create_dir_all(&dir).map_err(|e| Error::from(e).context("Failed to create history directory"))?;
+        for (i, input) in inputs.iter().enumerate() {
+            fs::write(dir.join(input_file(i)), &input.data)
+                .map_err(|e| Error::from(e).context("Failed to write history input image"))?;
+        }
+
+        let mut entry = entry.clone();
+        entry.inputs = inputs.iter().map(|i| i.mime_type.clone()).collect();
+        self.write_entry(&entry)
+    }
+
+    /// Record a finished slot (success, error or cancelled) and its images.
+    /// A slot that already succeeded is only replaced by another success, so
+    /// a failed or cancelled regeneration keeps the earlier image.
+    pub fn record_slot(&self, progress: &GenerationProgress) -> Result<()> {
+        let index = progress
+            .index
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Only slot updates can be recorded"))?;
+        let _guard = self.lock.lock().unwrap();
+        let mut entry = self.read_entry(&progress.generation_id)?;
+        let dir = self.entry_dir(&entry.id)?;
+
+        let previous = entry.slots.iter().find(|s| s.index == index);
+        if previous.is_some_and(|s| s.status == SlotStatus::Success) && progress.status != SlotStatus::Success {
+            return Ok(());
+        }
+        let previous_sizes = previous.map(|s| s.extra_sizes.clone()).unwrap_or_default();
+
+        // Images the new outcome lacks are removed, so no stale file outlives its flag
+        let write_image = |name: &str, data: &Option<String>| -> Result<bool> {
+            let path = dir.join(slot_file(index, name));
+            let Some(data) = data else {
+                return match fs::remove_file(&path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        Err(Error::from(e).context("Failed to remove history image"))
+                    }
+                    _ => Ok(false),
+                };
+            };
+            let bytes = STANDARD
+                .decode(data)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid image data: {}", e)))?;
+            fs::write(path, bytes).map_err(|e| Error::from(e).context("Failed to write history image"))?;
+            Ok(true)
+        };
+
+        for size in previous_sizes
+            .iter()
+            .filter(|size| !progress.extra_sizes.iter().any(|s| s.size == **size))
+        {
+            for variant in SIZED_VARIANTS {
+                write_image(&sized_variant(variant, *size), &None)?;
+            }
+        }
+        for sized in &progress.extra_sizes {
+            write_image(
+                &sized_variant("flood_fill", sized.size),
+                &Some(sized.flood_fill.clone()),
+            )?;
+            write_image(&sized_variant("color_key", sized.size), &Some(sized.color_key.clone()))?;
+            write_image(
+                &sized_variant("soft_matte", sized.size),
+                &Some(sized.soft_matte.clone()),
+            )?;
+        }
+
+        let slot = HistorySlot {
+            index,
+            status: progress.status,
+            completed_at: now_millis(),
+            warning: progress.warning.clone(),
+            error: progress.error.clone(),
+            has_raw_image: write_image("raw", &progress.raw_image)?,
+            has_flood_fill: write_image("flood_fill", &progress.flood_fill)?,
+            has_color_key: write_image("color_key", &progress.color_key)?,
+            has_soft_matte: write_image("soft_matte", &progress.soft_matte)?,
+            extra_sizes: progress.extra_sizes.iter().map(|s| s.size).collect(),
+        };
+
+        entry.slots.retain(|s| s.index != slot.index);
+        entry.slots.push(slot);
+        entry.slots.sort_by_key(|s| s.index);
+        entry.updated_at = now_millis();
+        self.write_entry(&entry)
+    }
+
+    /// Store the encoded result of an animated generation
+    pub fn record_animation(&self, id: &str, animation: &Animation) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut entry = self.read_entry(id)?;
+        let Some(settings) = entry.animation.as_mut() else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Generation {} is not an animation", id),
+            ));
+        };
+        let path = self.entry_dir(id)?.join(animation_file(animation.format));
+        fs::write(path, &animation.data).map_err(|e| Error::from(e).context("Failed to write history animation"))?;
+        settings.format = animation.format;
+        settings.saved = true;
+        entry.updated_at = now_millis();
+        self.write_entry(&entry)
+    }
+
+    /// An entry's metadata, without images
+    pub fn get(&self, id: &str) -> Result<HistoryEntry> {
+        let _guard = self.lock.lock().unwrap();
+        self.read_entry(id)
+    }
+
+    /// The prompt an entry was generated with, including its input images
+    pub fn prompt(&self, entry: &HistoryEntry) -> Result<Prompt> {
+        let dir = self.entry_dir(&entry.id)?;
+        let images = entry
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, mime_type)| {
+                let data = fs::read(dir.join(input_file(i)))
+                    .map_err(|e| Error::from(e).context("Failed to read history input image"))?;
+                Ok(InputImage {
+                    mime_type: mime_type.clone(),
+                    data,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Prompt::text(&entry.prompt).with_images(images))
+    }
+
+    /// Entries sorted newest first, skipping `offset` and returning at most `limit`
+    pub fn list(&self, offset: usize, limit: usize) -> Result<HistoryPage> {
+        let _guard = self.lock.lock().unwrap();
+        let dirs = match fs::read_dir(&self.root) {
+            Ok(dirs) => dirs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(HistoryPage {
+                    entries: Vec::new(),
+                    total: 0,
+                });
+            }
+            Err(e) => return Err(Error::from(e).context("Failed to read history")),
+        };
+
+        // Unreadable or foreign directories are skipped rather than failing the whole list
+        let mut entries: Vec<HistoryEntry> = dirs
+            .filter_map(|d| d.ok())
+            .filter_map(|d| d.file_name().into_string().ok())
+            .filter_map(|id| self.read_entry(&id).ok())
+            .collect();
+        entries.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| b.id.cmp(&a.id)));
+
+        let total = entries.len();
+        let entries = entries.into_iter().skip(offset).take(limit).collect();
+        Ok(HistoryPage { entries, total })
+    }
+
+    /// An entry with all of its images
+    pub fn load(&self, id: &str) -> Result<HistoryEntryDetail> {
+        let _guard = self.lock.lock().unwrap();
+        let entry = self.read_entry(id)?;
+        let dir = self.entry_dir(id)?;
+
+        let read_image = |index: usize, name: &str, present: bool| -> Option<String> {
+            if !present {
+                return None;
+            }
+            fs::read(dir.join(slot_file(index, name)))
+                .ok()
+                .map(|b| STANDARD.encode(b))
+        };
+
+        let images = entry
+            .slots
+            .iter()
+            .map(|slot| HistorySlotImages {
+                raw_image: read_image(slot.index, "raw", slot.has_raw_image),
+                flood_fill: read_image(slot.index, "flood_fill", slot.has_flood_fill),
+                color_key: read_image(slot.index, "color_key", slot.has_color_key),
+                soft_matte: read_image(slot.index, "soft_matte", slot.has_soft_matte),
+                // A size missing any of its variants is skipped rather than returned half empty
+                extra_sizes: slot
+                    .extra_sizes
+                    .iter()
+                    .filter_map(|&size| {
+                        Some(SizedImages {
+                            size,
+                            flood_fill: read_image(slot.index, &sized_variant("flood_fill", size), true)?,
+                            color_key: read_image(slot.index, &sized_variant("color_key", size), true)?,
+                            soft_matte: read_image(slot.index, &sized_variant("soft_matte", size), true)?,
+                        })
+                    })
+                    .collect(),
+                slot: slot.clone(),
+            })
+            .collect();
+
+        let animation_data = entry
+            .animation
+            .as_ref()
+            .filter(|animation| animation.saved)
+            .and_then(|animation| fs::read(dir.join(animation_file(animation.format))).ok())
+            .map(|b| STANDARD.encode(b));
+
+        Ok(HistoryEntryDetail {
+            entry,
+            images,
+            animation_data,
+        })
+    }
+
+    pub fn delete(&self, id: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let dir = self.entry_dir(id)?;
+        if !dir.join(ENTRY_FILE).exists() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("No history entry: {}", id)));
+        }
+        fs::remove_dir_all(&dir).map_err(|e| Error::from(e).context("Failed to delete history entry"))
+    }
+}
+
+/// Variants written for each additional output size
+const SIZED_VARIANTS: [&str; 3] = ["flood_fill", "color_key", "soft_matte"];
+
+fn sized_variant(variant: &str, size: u32) -> String {
+    format!("{}_{}", variant, size)
+}
+
+fn slot_file(index: usize, name: &str) -> String {
+    format!("slot_{}_{}.png", index, name)
+}
+
+fn animation_file(format: AnimationFormat) -> String {
+    format!("animation.{}", format.extension())
+}
+
+fn input_file(index: usize) -> String {
+    format!("input_{}", index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store() -> (tempfile::TempDir, HistoryStore) {
+        let root = tempfile::tempdir().unwrap();
+        let store = HistoryStore::new(root.path().to_path_buf());
+        (root, store)
+    }
+
+    fn entry(id: &str, created_at: u64) -> HistoryEntry {
+        HistoryEntry {
+            created_at,
+            ..HistoryEntry::new(id, "prompt", &["🤠".to_string()], Some("hat"), "gemini", "model", 2)
+        }
+    }
+
+    fn progress(id: &str, index: usize, status: SlotStatus) -> GenerationProgress {
+        GenerationProgress {
+            generation_id: id.to_string(),
+            index: Some(index),
+            status,
+            attempt: None,
+            retry_in_ms: None,
+            flood_fill: None,
+            color_key: None,
+            soft_matte: None,
+            raw_image: None,
+            extra_sizes: Vec::new(),
+            warning: None,
+            error: None,
+            usage: None,
+        }
+    }
+
+    #[test]
+    fn test_record_and_load_slots() {
+        let (_root, store) = temp_store();
+        store.create(&entry("gen-1", 1), &[]).unwrap();
+
+        store
+            .record_slot(&GenerationProgress {
+                flood_fill: Some(STANDARD.encode(b"ff")),
+                color_key: Some(STANDARD.encode(b"ck")),
+                raw_image: Some(STANDARD.encode(b"raw")),
+                warning: Some("corner excluded".to_string()),
+                ..progress("gen-1", 1, SlotStatus::Success)
+            })
+            .unwrap();
+        store
+            .record_slot(&GenerationProgress {
+                error: Some(Error::new(ErrorKind::SafetyBlocked, "blocked")),
+                ..progress("gen-1", 0, SlotStatus::Error)
+            })
+            .unwrap();
+
+        let detail = store.load("gen-1").unwrap();
+        assert_eq!(detail.entry.modifier.as_deref(), Some("hat"));
+        assert_eq!(detail.images.len(), 2);
+        assert_eq!(detail.images[0].slot.index, 0);
+        assert_eq!(
+            detail.images[0].slot.error.as_ref().unwrap().kind,
+            ErrorKind::SafetyBlocked
+        );
+        assert!(detail.images[0].flood_fill.is_none());
+        assert_eq!(
+            detail.images[1].raw_image.as_deref(),
+            Some(STANDARD.encode(b"raw").as_str())
+        );
+        assert_eq!(
+            detail.images[1].flood_fill.as_deref(),
+            Some(STANDARD.encode(b"ff").as_str())
+        );
+        assert_eq!(detail.images[1].slot.warning.as_deref(), Some("corner excluded"));
+    }
+
+    #[test]
+    fn test_record_and_load_extra_sizes() {
+        let (_root, store) = temp_store();
+        store.create(&entry("gen-1", 1), &[]).unwrap();
+        let dir = store.entry_dir("gen-1").unwrap();
+        let sized = |size: u32| SizedImages {
+            size,
+            flood_fill: STANDARD.encode(format!("ff{}", size)),
+            color_key: STANDARD.encode(format!("ck{}", size)),
+            soft_matte: STANDARD.encode(format!("sm{}", size)),
+        };
+
+        store
+            .record_slot(&GenerationProgress {
+                flood_fill: Some(STANDARD.encode(b"ff")),
+                extra_sizes: vec![sized(64), sized(512)],
+                ..progress("gen-1", 0, SlotStatus::Success)
+            })
+            .unwrap();
+        assert!(dir.join(slot_file(0, "soft_matte_512")).exists());
+
+        let detail = store.load("gen-1").unwrap();
+        let loaded = &detail.images[0].extra_sizes;
+        assert_eq!(loaded.iter().map(|s| s.size).collect::<Vec<_>>(), [64, 512]);
+        assert_eq!(loaded[1].flood_fill, STANDARD.encode(b"ff512"));
+        assert_eq!(loaded[1].color_key, STANDARD.encode(b"ck512"));
+        assert_eq!(loaded[1].soft_matte, STANDARD.encode(b"sm512"));
+
+        // A regeneration at fewer sizes removes the sizes it no longer has
+        store
+            .record_slot(&GenerationProgress {
+                flood_fill: Some(STANDARD.encode(b"ff")),
+                extra_sizes: vec![sized(64)],
+                ..progress("gen-1", 0, SlotStatus::Success)
+            })
+            .unwrap();
+        assert!(!dir.join(slot_file(0, "flood_fill_512")).exists());
+        let detail = store.load("gen-1").unwrap();
+        assert_eq!(detail.images[0].slot.extra_sizes, [64]);
+        assert_eq!(detail.images[0].extra_sizes.len(), 1);
+    }
+
+    #[test]
+    fn test_failed_regeneration_keeps_earlier_success() {
+        let (_root, store) = temp_store();
+        store.create(&entry("gen-1", 1), &[]).unwrap();
+        let dir = store.entry_dir("gen-1").unwrap();
+
+        // A processing failure keeps its raw image, a later failure has none
+        store
+            .record_slot(&GenerationProgress {
+                raw_image: Some(STANDARD.encode(b"raw")),
+                ..progress("gen-1", 0, SlotStatus::Error)
+            })
+            .unwrap();
+        store.record_slot(&progress("gen-1", 0, SlotStatus::Error)).unwrap();
+        assert!(!dir.join(slot_file(0, "raw")).exists());
+
+        store
+            .record_slot(&GenerationProgress {
+                flood_fill: Some(STANDARD.encode(b"ff")),
+                ..progress("gen-1", 0, SlotStatus::Success)
+            })
+            .unwrap();
+        store.record_slot(&progress("gen-1", 0, SlotStatus::Cancelled)).unwrap();
+        store.record_slot(&progress("gen-1", 0, SlotStatus::Error)).unwrap();
+
+        let detail = store.load("gen-1").unwrap();
+        assert_eq!(detail.images[0].slot.status, SlotStatus::Success);
+        assert_eq!(
+            detail.images[0].flood_fill.as_deref(),
+            Some(STANDARD.encode(b"ff").as_str())
+        );
+    }
+
+    #[test]
+    fn test_list_pages_newest_first() {
+        let (_root, store) = temp_store();
+        assert_eq!(store.list(0, 10).unwrap().total, 0);
+
+        for (id, created_at) in [("a", 1), ("b", 3), ("c", 2)] {
+            store.create(&entry(id, created_at), &[]).unwrap();
+        }
+
+        let page = store.list(0, 2).unwrap();
+        assert_eq!(page.total, 3);
+        let ids: Vec<_> = page.entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "c"]);
+
+        let page = store.list(2, 2).unwrap();
+        let ids: Vec<_> = page.entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["a"]);
+    }
+
+    #[test]
+    fn test_prompt_round_trips_input_images() {
+        let (_root, store) = temp_store();
+        let input = InputImage {
+            mime_type: "image/gif".to_string(),
+            data: b"GIF89a".to_vec(),
+        };
+        store.create(&entry("refined", 1), &[input]).unwrap();
+
+        let entry = store.get("refined").unwrap();
+        assert_eq!(entry.inputs, vec!["image/gif"]);
+        let prompt = store.prompt(&entry).unwrap();
+        assert_eq!(prompt.text, "prompt");
+        assert_eq!(prompt.images.len(), 1);
+        assert_eq!(prompt.images[0].mime_type, "image/gif");
+        assert_eq!(prompt.images[0].data, b"GIF89a");
+    }
+
+    #[test]
+    fn test_record_animation() {
+        let (_root, store) = temp_store();
+        store.create(&entry("still", 1), &[]).unwrap();
+        let animated = HistoryEntry {
+            animation: Some(HistoryAnimation {
+                motion: Some("waving".to_string()),
+                effect: None,
+                format: AnimationFormat::Gif,
+                frames: Some(2),
+                saved: false,
+            }),
+            ..entry("animated", 2)
+        };
+        store.create(&animated, &[]).unwrap();
+        assert!(store.load("animated").unwrap().animation_data.is_none());
+
+        let animation = Animation {
+            data: b"GIF89a".to_vec(),
+            format: AnimationFormat::Gif,
+            mime_type: AnimationFormat::Gif.mime_type(),
+            frames: 2,
+            size: 128,
+            delay_ms: 80,
+            warning: None,
+        };
+        assert_eq!(
+            store.record_animation("still", &animation).unwrap_err().kind,
+            ErrorKind::InvalidInput
+        );
+        store.record_animation("animated", &animation).unwrap();
+
+        let detail = store.load("animated").unwrap();
+        assert!(detail.entry.animation.unwrap().saved);
+        assert_eq!(
+            detail.animation_data.as_deref(),
+            Some(STANDARD.encode(b"GIF89a").as_str())
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        let (_root, store) = temp_store();
+        store.create(&entry("gone", 1), &[]).unwrap();
+        store.delete("gone").unwrap();
+
+        assert!(store.load("gone").is_err());
+        assert_eq!(store.delete("gone").unwrap_err().kind, ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_rejects_path_traversal() {
+        let (_root, store) = temp_store();
+        assert_eq!(store.load("../etc").err().unwrap().kind, ErrorKind::InvalidInput);
+        assert_eq!(store.delete("..").unwrap_err().kind, ErrorKind::InvalidInput);
+    }
+}
@@ -1,294 +1,905 @@
-mod gemini;
-
-use base64::{engine::general_purpose::STANDARD, Engine};
-use std::env;
-use std::fs;
-use std::path::PathBuf;
+//! MojiMix core, shared by the desktop app (`run`) and the `mojimix-cli` binary.
+
+pub mod animation;
+pub mod api_key;
+pub mod background;
+pub mod cancel;
+pub mod config;
+pub mod discord;
+pub mod effects;
+pub mod error;
+pub mod gemini;
+pub mod generation;
+mod history;
+#[cfg(test)]
+mod mock_server;
+pub mod output;
+pub mod process;
+pub mod profiles;
+pub mod provider;
+pub mod redact;
+pub mod retry;
+pub mod slack;
+pub mod usage;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use tauri::State;
 use tauri::ipc::Channel;
 
-#[derive(serde::Serialize)]
-struct ImageResult {
-    flood_fill: Option<String>,
-    color_key: Option<String>,
-    warning: Option<String>,
-    error: Option<String>,
+use crate::animation::{Animation, AnimationFormat, AnimationOptions};
+use crate::background::{BackgroundOptions, OutputOptions};
+use crate::cancel::ActiveGenerations;
+use crate::effects::Effect;
+use crate::error::{Error, ErrorKind, Result};
+use crate::generation::{GenerationProgress, GenerationResult, SlotStatus};
+use crate::history::{HistoryAnimation, HistoryEntry, HistoryEntryDetail, HistoryPage, HistoryStore};
+use crate::profiles::ProfileStore;
+use crate::provider::{InputImage, Prompt};
+use crate::usage::{UsageRecord, UsageStore};
+
+/// Whether the active profile, or the default key without one, has a key
+#[tauri::command]
+fn check_api_key(profiles: State<'_, ProfileStore>) -> bool {
+    profiles::active_secret(&profiles).is_ok_and(|secret| api_key::get(&secret).is_some())
 }
 
-#[derive(serde::Serialize)]
-struct GenerationResult {
-    results: Vec<ImageResult>,
-    mime_type: String,
+#[tauri::command]
+fn save_api_key(key: String) -> Result<()> {
+    api_key::save_api_key(&key)
 }
 
-#[derive(Clone, serde::Serialize)]
-struct GenerationProgress {
-    index: usize,
-    flood_fill: Option<String>,
-    color_key: Option<String>,
-    raw_image: Option<String>, // Original image before processing (for debugging failed items)
-    warning: Option<String>,
-    error: Option<String>,
+#[tauri::command]
+fn clear_api_key() -> Result<()> {
+    api_key::clear_api_key()
 }
 
-/// Get the path to the API key file
-fn api_key_path() -> Option<PathBuf> {
-    dirs::config_dir().map(|p| p.join("mojimix").join("api_key"))
+/// Check a Gemini key with a test call and list the image models it can use.
+/// Without `api_key`, the active profile's key, or the default key, is checked.
+#[tauri::command]
+async fn validate_api_key(api_key: Option<String>, profiles: State<'_, ProfileStore>) -> Result<gemini::KeyValidation> {
+    let api_key = match api_key.filter(|key| !key.trim().is_empty()) {
+        Some(key) => {
+            let key = key.trim().to_string();
+            redact::register_secret(&key);
+            key
+        }
+        None => profiles::credentials(&profiles)?.api_key,
+    };
+    gemini::validate_api_key(&gemini::GeminiClient::new(&api_key)).await
 }
 
-/// Get API key from env var first, then from config file
-fn get_api_key_internal() -> Option<String> {
-    // First check environment variable
-    if let Ok(key) = env::var("GEMINI_API_KEY") {
-        if !key.trim().is_empty() {
-            return Some(key);
-        }
-    }
+/// Where the active Gemini key is stored, and whether it needs the passphrase
+#[tauri::command]
+fn api_key_storage(profiles: State<'_, ProfileStore>) -> Result<api_key::StorageStatus> {
+    Ok(api_key::status(&profiles::active_secret(&profiles)?))
+}
 
-    // Then check config file
-    if let Some(path) = api_key_path() {
-        if let Ok(contents) = fs::read_to_string(&path) {
-            let key = contents.trim().to_string();
-            if !key.is_empty() {
-                return Some(key);
-            }
-        }
-    }
+#[tauri::command]
+fn list_profiles(profiles: State<'_, ProfileStore>) -> Result<Vec<profiles::ProfileInfo>> {
+    profiles.list()
+}
 
-    None
+#[tauri::command]
+fn add_profile(
+    name: String,
+    api_key: String,
+    default_model: Option<String>,
+    daily_budget: Option<u32>,
+    profiles: State<'_, ProfileStore>,
+) -> Result<()> {
+    let profile = profiles::Profile {
+        name,
+        default_model: default_model.filter(|m| !m.trim().is_empty()),
+        daily_budget,
+    };
+    profiles::add_profile(&profiles, profile, &api_key)
 }
 
 #[tauri::command]
-fn check_api_key() -> bool {
-    get_api_key_internal().is_some()
+fn remove_profile(name: String, profiles: State<'_, ProfileStore>) -> Result<()> {
+    profiles::remove_profile(&profiles, &name)
 }
 
+/// Make a profile active, or go back to the default key with `null`
 #[tauri::command]
-fn save_api_key(key: String) -> Result<(), String> {
-    let key = key.trim().to_string();
-    if key.is_empty() {
-        return Err("API key cannot be empty".to_string());
-    }
+fn select_profile(name: Option<String>, profiles: State<'_, ProfileStore>) -> Result<()> {
+    profiles.select(name.as_deref())
+}
 
-    let path = api_key_path().ok_or("Could not determine config directory")?;
+#[tauri::command]
+fn unlock_api_keys(passphrase: String) -> Result<()> {
+    api_key::unlock(&passphrase)
+}
 
-    // Create parent directory if needed
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).map_err(|e| format!("Failed to create config directory: {}", e))?;
-    }
+/// Encrypt keys kept in files (when there's no OS secret store) with a
+/// passphrase, or decrypt them with `null`
+#[tauri::command]
+fn set_api_key_passphrase(passphrase: Option<String>) -> Result<()> {
+    api_key::set_passphrase(passphrase.as_deref())
+}
 
-    fs::write(&path, &key).map_err(|e| format!("Failed to save API key: {}", e))?;
+#[tauri::command]
+fn check_slack_token() -> bool {
+    api_key::get(&api_key::Secret::SlackToken).is_some()
+}
 
-    Ok(())
+#[tauri::command]
+fn save_slack_token(token: String) -> Result<()> {
+    slack::validate_token(&token)?;
+    api_key::save(&api_key::Secret::SlackToken, &token).map(|_| ())
 }
 
 #[tauri::command]
-fn clear_api_key() -> Result<(), String> {
-    if let Some(path) = api_key_path() {
-        if path.exists() {
-            fs::remove_file(&path).map_err(|e| format!("Failed to remove API key: {}", e))?;
-        }
-    }
-    Ok(())
+fn clear_slack_token() -> Result<()> {
+    api_key::clear(&api_key::Secret::SlackToken)
+}
+
+#[tauri::command]
+fn check_discord_token() -> bool {
+    api_key::get(&api_key::Secret::DiscordToken).is_some()
+}
+
+#[tauri::command]
+fn save_discord_token(token: String) -> Result<()> {
+    api_key::save(&api_key::Secret::DiscordToken, &token).map(|_| ())
+}
+
+#[tauri::command]
+fn clear_discord_token() -> Result<()> {
+    api_key::clear(&api_key::Secret::DiscordToken)
+}
+
+#[tauri::command]
+fn list_providers() -> Vec<provider::ProviderInfo> {
+    provider::list_providers()
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn generate_emoji(
     emojis: Vec<String>,
     modifier: Option<String>,
+    reference_images: Option<Vec<String>>,
+    provider: Option<String>,
+    model: Option<String>,
     fast_model: Option<bool>,
     count: Option<usize>,
+    tolerance: Option<f32>,
+    sizes: Option<Vec<u32>>,
+    margin: Option<u32>,
+    padding: Option<u32>,
     on_progress: Channel<GenerationProgress>,
-) -> Result<GenerationResult, String> {
-    let api_key = get_api_key_internal().ok_or("No API key configured")?;
-    let use_fast = fast_model.unwrap_or(false);
-    let num_images = count.unwrap_or(4).min(8); // Default 4, max 8
-
-    let prompt = build_prompt(&emojis, modifier.as_deref());
-
-    // Generate images in parallel, streaming results as they complete
-    let futures: Vec<_> = (0..num_images)
-        .map(|index| {
-            let prompt = prompt.clone();
-            let api_key = api_key.clone();
-            let channel = on_progress.clone();
-            async move {
-                // First, try to get the raw image
-                let raw_result = gemini::generate_emoji_image(&prompt, &api_key, use_fast).await;
-
-                let (result, raw_image, warning) = match raw_result {
-                    Ok(image_bytes) => {
-                        let raw_b64 = STANDARD.encode(&image_bytes);
-                        // Try to process it
-                        match gemini::resize_to_emoji(&image_bytes) {
-                            Ok(processed) => (
-                                Ok((
-                                    STANDARD.encode(&processed.flood_fill),
-                                    STANDARD.encode(&processed.color_key),
-                                )),
-                                Some(raw_b64),
-                                processed.warning,
-                            ),
-                            Err(e) => (Err(e), Some(raw_b64), None), // Processing failed, but we have raw image
-                        }
-                    }
-                    Err(e) => (Err(e), None, None), // Generation failed, no raw image
-                };
-
-                // Send progress immediately when this image completes
-                let progress = match &result {
-                    Ok((ff, ck)) => GenerationProgress {
-                        index,
-                        flood_fill: Some(ff.clone()),
-                        color_key: Some(ck.clone()),
-                        raw_image: raw_image.clone(), // Include raw for all items
-                        warning: warning.clone(),
-                        error: None,
-                    },
-                    Err(e) => GenerationProgress {
-                        index,
-                        flood_fill: None,
-                        color_key: None,
-                        raw_image,
-                        warning: None,
-                        error: Some(e.clone()),
-                    },
-                };
-                let _ = channel.send(progress);
-
-                (index, result, warning)
-            }
-        })
-        .collect();
-
-    let mut indexed_results = futures::future::join_all(futures).await;
-
-    // Sort by index to maintain order in final result
-    indexed_results.sort_by_key(|(idx, _, _)| *idx);
-
-    let results: Vec<ImageResult> = indexed_results
-        .into_iter()
-        .map(|(_, r, warning)| match r {
-            Ok((ff, ck)) => ImageResult {
-                flood_fill: Some(ff),
-                color_key: Some(ck),
-                warning,
-                error: None,
-            },
-            Err(e) => ImageResult {
-                flood_fill: None,
-                color_key: None,
-                warning: None,
-                error: Some(e),
-            },
-        })
-        .collect();
-
-    // Check if all failed
-    let has_any_success = results.iter().any(|r| r.flood_fill.is_some());
-    if !has_any_success {
-        let first_error = results
-            .iter()
-            .find_map(|r| r.error.as_ref())
-            .map(|e| e.as_str())
-            .unwrap_or("Unknown error");
-        return Err(format!("Failed to generate images: {}", first_error));
+    generations: State<'_, ActiveGenerations>,
+    history: State<'_, HistoryStore>,
+    profiles: State<'_, ProfileStore>,
+    usage: State<'_, UsageStore>,
+) -> Result<GenerationResult> {
+    // Checked up front so a bad value fails before any request is made
+    let background = BackgroundOptions::with_tolerance(tolerance)?;
+    let output = OutputOptions::new(sizes.clone(), margin, padding)?;
+    let prompt = generation::prepare_prompt(&emojis, modifier.as_deref(), &reference_images.unwrap_or_default())?;
+
+    let stores = Stores::new(&generations, &history, &profiles, &usage);
+    let source = Source::New {
+        provider: provider.as_deref(),
+        model: model.as_deref(),
+        fast: fast_model.unwrap_or(false),
+    };
+    // Default 4, capped by provider
+    let images = |max_images: usize| count.unwrap_or(4).min(max_images);
+    let started = start_run(stores, &on_progress, source, &prompt, images, background, output)?;
+    let entry = HistoryEntry {
+        tolerance,
+        sizes,
+        margin,
+        padding,
+        ..started.entry(&prompt.text, &emojis, modifier.as_deref())
+    };
+    started.run.record(&entry, &prompt.images);
+
+    let slots: Vec<usize> = (0..started.images).collect();
+    run_slots(started, &prompt, &slots).await
+}
+
+/// Edit an earlier result: send its raw image back to the provider with an
+/// instruction ("make the hat red") and process the new variants as usual.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn refine_emoji(
+    image_base64: String,
+    instruction: String,
+    emojis: Vec<String>,
+    provider: Option<String>,
+    model: Option<String>,
+    fast_model: Option<bool>,
+    count: Option<usize>,
+    tolerance: Option<f32>,
+    sizes: Option<Vec<u32>>,
+    margin: Option<u32>,
+    padding: Option<u32>,
+    on_progress: Channel<GenerationProgress>,
+    generations: State<'_, ActiveGenerations>,
+    history: State<'_, HistoryStore>,
+    profiles: State<'_, ProfileStore>,
+    usage: State<'_, UsageStore>,
+) -> Result<GenerationResult> {
+    if instruction.trim().is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Describe what to change"));
     }
+    let background = BackgroundOptions::with_tolerance(tolerance)?;
+    let output = OutputOptions::new(sizes.clone(), margin, padding)?;
+    let image_bytes = STANDARD
+        .decode(&image_base64)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Failed to decode image: {}", e)))?;
+    let image = InputImage::from_bytes(image_bytes)?;
+    let prompt = Prompt::text(generation::build_refine_prompt(&instruction, &emojis)).with_images(vec![image]);
+
+    let stores = Stores::new(&generations, &history, &profiles, &usage);
+    let source = Source::New {
+        provider: provider.as_deref(),
+        model: model.as_deref(),
+        fast: fast_model.unwrap_or(false),
+    };
+    let images = |max_images: usize| count.unwrap_or(4).min(max_images);
+    let started = start_run(stores, &on_progress, source, &prompt, images, background, output)?;
+    let entry = HistoryEntry {
+        tolerance,
+        sizes,
+        margin,
+        padding,
+        ..started.entry(&prompt.text, &emojis, Some(instruction.trim()))
+    };
+    started.run.record(&entry, &prompt.images);
+
+    let slots: Vec<usize> = (0..started.images).collect();
+    run_slots(started, &prompt, &slots).await
+}
 
-    Ok(GenerationResult {
-        results,
-        mime_type: "image/png".to_string(),
-    })
+/// Generate one slot of a stored generation again, with the same prompt and
+/// model. Progress uses the original generation id, and a new image replaces
+/// that slot in history; if the attempt fails, an earlier image is kept.
+#[tauri::command]
+async fn regenerate_slot(
+    generation_id: String,
+    index: usize,
+    on_progress: Channel<GenerationProgress>,
+    generations: State<'_, ActiveGenerations>,
+    history: State<'_, HistoryStore>,
+    profiles: State<'_, ProfileStore>,
+    usage: State<'_, UsageStore>,
+) -> Result<GenerationResult> {
+    let entry = history.get(&generation_id)?;
+    require_still(&entry)?;
+    if index >= entry.count {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Generation {} has no slot {}", generation_id, index),
+        ));
+    }
+
+    let prompt = history.prompt(&entry)?;
+    let background = BackgroundOptions::with_tolerance(entry.tolerance)?;
+    let output = OutputOptions::new(entry.sizes.clone(), entry.margin, entry.padding)?;
+
+    let stores = Stores::new(&generations, &history, &profiles, &usage);
+    let started = start_run(
+        stores,
+        &on_progress,
+        Source::Resume(&entry),
+        &prompt,
+        |_| 1,
+        background,
+        output,
+    )?;
+    run_slots(started, &prompt, &[index]).await
+}
+
+/// Animated entries are generated as a whole with `generate_animation`, not slot by slot
+fn require_still(entry: &HistoryEntry) -> Result<()> {
+    if entry.animation.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Generation {} is an animation; generate it again instead", entry.id),
+        ));
+    }
+    Ok(())
 }
 
-fn build_prompt(emojis: &[String], modifier: Option<&str>) -> String {
-    let emoji_str = emojis.join(" ");
-
-    let base_prompt = format!(
-        "Create a single emoji combining: {}. \
-         CRITICAL: Keep it SIMPLE and MINIMAL like standard Unicode emojis. \
-         Copy the exact visual style of Google Noto Color Emoji - simple shapes, minimal details, clean lines. \
-         Do NOT add extra details, textures, or creative interpretations. \
-         The result should look like it belongs in the standard emoji keyboard. \
-         Soft 3D with subtle gradients, bright saturated colors. \
-         NO shadows, NO glow, NO complex textures. \
-         Solid bright green (#00FF00) background, no variations.",
-        emoji_str
-    );
-
-    match modifier {
-        Some(mod_text) if !mod_text.trim().is_empty() => {
-            format!(
-                "{} Additional modification: {}",
-                base_prompt,
-                mod_text.trim()
-            )
+/// Run a stored generation's parameters again as a new generation.
+/// `count` defaults to the original number of images.
+#[tauri::command]
+async fn replay_generation(
+    generation_id: String,
+    count: Option<usize>,
+    on_progress: Channel<GenerationProgress>,
+    generations: State<'_, ActiveGenerations>,
+    history: State<'_, HistoryStore>,
+    profiles: State<'_, ProfileStore>,
+    usage: State<'_, UsageStore>,
+) -> Result<GenerationResult> {
+    let original = history.get(&generation_id)?;
+    require_still(&original)?;
+    let prompt = history.prompt(&original)?;
+    let background = BackgroundOptions::with_tolerance(original.tolerance)?;
+    let output = OutputOptions::new(original.sizes.clone(), original.margin, original.padding)?;
+
+    let stores = Stores::new(&generations, &history, &profiles, &usage);
+    let images = |max_images: usize| count.unwrap_or(original.count).min(max_images);
+    let started = start_run(
+        stores,
+        &on_progress,
+        Source::Replay(&original),
+        &prompt,
+        images,
+        background,
+        output,
+    )?;
+    let entry = HistoryEntry {
+        tolerance: original.tolerance,
+        sizes: original.sizes.clone(),
+        margin: original.margin,
+        padding: original.padding,
+        ..started.entry(&original.prompt, &original.emojis, original.modifier.as_deref())
+    };
+    started.run.record(&entry, &prompt.images);
+
+    let slots: Vec<usize> = (0..started.images).collect();
+    run_slots(started, &prompt, &slots).await
+}
+
+/// The app state a generating command reads and records to
+#[derive(Clone, Copy)]
+struct Stores<'a> {
+    generations: &'a ActiveGenerations,
+    history: &'a HistoryStore,
+    profiles: &'a ProfileStore,
+    usage: &'a UsageStore,
+}
+
+impl<'a> Stores<'a> {
+    fn new(
+        generations: &'a ActiveGenerations,
+        history: &'a HistoryStore,
+        profiles: &'a ProfileStore,
+        usage: &'a UsageStore,
+    ) -> Self {
+        Self {
+            generations,
+            history,
+            profiles,
+            usage,
         }
-        _ => base_prompt,
     }
 }
 
+/// Which provider and model a generation uses, and the id it runs under
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    /// A new generation. An explicit model wins over the profile's default,
+    /// which wins over the fast flag.
+    New {
+        provider: Option<&'a str>,
+        model: Option<&'a str>,
+        fast: bool,
+    },
+    /// A new generation with a stored generation's provider and model
+    Replay(&'a HistoryEntry),
+    /// More slots of a stored generation, under its id
+    Resume(&'a HistoryEntry),
+}
+
+/// A generation set up by `start_run`
+struct Started<'a> {
+    provider: Box<dyn provider::ImageProvider>,
+    /// Images held against the profile's budget
+    images: usize,
+    run: SlotRun<'a>,
+}
+
+impl Started<'_> {
+    /// A history entry with this generation's id, provider, model and image count
+    fn entry(&self, prompt: &str, emojis: &[String], modifier: Option<&str>) -> HistoryEntry {
+        let run = &self.run;
+        HistoryEntry::new(
+            &run.active.id,
+            prompt,
+            emojis,
+            modifier,
+            self.provider.id(),
+            &run.model,
+            self.images,
+        )
+    }
+}
+
+/// Set up a generation the same way for every generating command: take the
+/// active profile's key, create the provider, pick the model, check `prompt`'s
+/// images and hold `images(max_images)` against the profile's budget, all
+/// before anything is recorded. Then register the generation, so it can be
+/// cancelled until the run is finished, and report it as started.
+fn start_run<'a>(
+    stores: Stores<'a>,
+    on_progress: &'a Channel<GenerationProgress>,
+    source: Source<'_>,
+    prompt: &Prompt,
+    images: impl FnOnce(usize) -> usize,
+    background: BackgroundOptions,
+    output: OutputOptions,
+) -> Result<Started<'a>> {
+    let credentials = profiles::credentials(stores.profiles)?;
+    let provider_id = match source {
+        Source::New { provider, .. } => provider.unwrap_or(provider::DEFAULT_PROVIDER),
+        Source::Replay(entry) | Source::Resume(entry) => &entry.provider,
+    };
+    let provider = provider::create_provider(provider_id, &credentials.api_key)?;
+    let model = match source {
+        Source::New { model, fast, .. } => {
+            provider.resolve_model(model.or(credentials.default_model.as_deref()), fast)?
+        }
+        Source::Replay(entry) | Source::Resume(entry) => entry.model.clone(),
+    };
+    check_image_input(provider.as_ref(), prompt)?;
+    let images = images(provider.capabilities().max_images);
+    let reservation = credentials
+        .profile
+        .as_deref()
+        .map(|profile| stores.profiles.reserve(profile, images))
+        .transpose()?;
+
+    let active = match source {
+        Source::Resume(entry) => stores.generations.resume(&entry.id)?,
+        Source::New { .. } | Source::Replay(_) => stores.generations.start(),
+    };
+    let _ = on_progress.send(GenerationProgress::started(&active.id));
+    let run = SlotRun {
+        active,
+        on_progress,
+        history: stores.history,
+        usage: stores.usage,
+        model,
+        profile: credentials.profile,
+        reservation,
+        generated: AtomicUsize::new(0),
+        background,
+        output,
+    };
+    Ok(Started { provider, images, run })
+}
+
+/// A registered generation: forwards its progress, records finished slots in
+/// history and usage, and counts generated images against the profile's budget
+struct SlotRun<'a> {
+    active: cancel::ActiveGeneration<'a>,
+    on_progress: &'a Channel<GenerationProgress>,
+    history: &'a HistoryStore,
+    usage: &'a UsageStore,
+    model: String,
+    /// Active profile, if any
+    profile: Option<String>,
+    reservation: Option<profiles::Reservation<'a>>,
+    /// The provider billed for an image even when background removal failed
+    generated: AtomicUsize,
+    /// Checked by the caller before the generation is recorded
+    background: BackgroundOptions,
+    output: OutputOptions,
+}
+
+impl SlotRun<'_> {
+    /// Record a new generation in history. Best effort: a disk problem
+    /// shouldn't lose the images on screen.
+    fn record(&self, entry: &HistoryEntry, inputs: &[InputImage]) {
+        log_failure("record generation in history", self.history.create(entry, inputs));
+    }
+
+    fn generation_run(&self) -> generation::GenerationRun {
+        generation::GenerationRun {
+            id: self.active.id.clone(),
+            policy: retry::RetryPolicy::default(),
+            cancel: self.active.token.clone(),
+            background: self.background,
+            output: self.output.clone(),
+        }
+    }
+
+    /// Count, record and forward a slot's (or an animation frame's) progress
+    fn report(&self, progress: GenerationProgress) {
+        if progress.status == SlotStatus::Success || progress.raw_image.is_some() {
+            self.generated.fetch_add(1, Ordering::Relaxed);
+        }
+        let finished = matches!(
+            progress.status,
+            SlotStatus::Success | SlotStatus::Error | SlotStatus::Cancelled
+        );
+        if finished {
+            log_failure("record slot in history", self.history.record_slot(&progress));
+        }
+        if let Some(usage) = progress.usage {
+            let record = UsageRecord::new(&progress.generation_id, &self.model, self.profile.as_deref(), usage);
+            log_failure("record usage", self.usage.record(&record));
+        }
+        let _ = self.on_progress.send(progress);
+    }
+
+    /// Count the generated images against the profile's budget and release the
+    /// rest, and unregister the generation
+    fn finish(self) {
+        if let Some(reservation) = self.reservation {
+            log_failure("record profile usage", reservation.finish(self.generated.into_inner()));
+        }
+    }
+}
+
+/// Log a failed step that shouldn't fail the command, such as recording
+/// history or usage: the images on screen matter more than the bookkeeping
+fn log_failure(what: &str, result: Result<()>) {
+    if let Err(e) = result {
+        log::warn!("Failed to {}: {}", what, e);
+    }
+}
+
+/// Fail before anything is recorded if `prompt` has images `provider` can't take
+fn check_image_input(provider: &dyn provider::ImageProvider, prompt: &Prompt) -> Result<()> {
+    if !prompt.images.is_empty() && !provider.capabilities().image_input {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Provider '{}' does not accept input images", provider.id()),
+        ));
+    }
+    Ok(())
+}
+
+/// Generate `slots` of a started generation, reporting each one through its run
+async fn run_slots(started: Started<'_>, prompt: &Prompt, slots: &[usize]) -> Result<GenerationResult> {
+    let Started { provider, run, .. } = started;
+    let result = generation::generate_slots(
+        provider.as_ref(),
+        prompt,
+        &run.model,
+        slots,
+        &run.generation_run(),
+        &|progress| run.report(progress),
+    )
+    .await;
+    run.finish();
+    result
+}
+
+/// Tokens, images and cost per generation, day, profile and model over the
+/// last `days` days (UTC, including today), or all recorded usage
+#[tauri::command]
+fn get_usage_report(days: Option<u32>, usage: State<'_, UsageStore>) -> Result<usage::UsageReport> {
+    usage.report(days, &usage::price_table())
+}
+
+/// Stored generations, newest first. Defaults to the first 20.
+#[tauri::command]
+fn list_history(offset: Option<usize>, limit: Option<usize>, history: State<'_, HistoryStore>) -> Result<HistoryPage> {
+    history.list(offset.unwrap_or(0), limit.unwrap_or(20))
+}
+
+#[tauri::command]
+fn load_history_entry(id: String, history: State<'_, HistoryStore>) -> Result<HistoryEntryDetail> {
+    history.load(&id)
+}
+
+#[tauri::command]
+fn delete_history_entry(id: String, history: State<'_, HistoryStore>) -> Result<()> {
+    history.delete(&id)
+}
+
+/// Stop a running generation. Returns false if it already finished.
+#[tauri::command]
+fn cancel_generation(generation_id: String, generations: State<'_, ActiveGenerations>) -> bool {
+    generations.cancel(&generation_id)
+}
+
 #[tauri::command]
 async fn save_emoji_image(
     image_base64: String,
     emojis: Vec<String>,
     modifier: Option<String>,
-) -> Result<String, String> {
-    let api_key = get_api_key_internal().ok_or("No API key configured")?;
-
+    extension: Option<String>,
+    generation_id: Option<String>,
+    profiles: State<'_, ProfileStore>,
+    usage: State<'_, UsageStore>,
+) -> Result<String> {
+    // Animations are saved as .gif or .webp (APNG keeps .png)
+    let extension = extension.unwrap_or_else(|| "png".to_string());
+    if !["png", "gif", "webp"].contains(&extension.as_str()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unsupported file type: {}", extension),
+        ));
+    }
     let image_bytes = STANDARD
         .decode(&image_base64)
-        .map_err(|e| format!("Failed to decode image: {}", e))?;
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Failed to decode image: {}", e)))?;
+
+    // Name the file with Gemini using the active profile's key; without one, or if naming fails, it's just "emoji"
+    // The request counts toward the image's generation, or a new id when there is none
+    let name = match profiles::credentials(&profiles) {
+        Ok(credentials) => {
+            let client = gemini::GeminiClient::new(&credentials.api_key);
+            let result = gemini::generate_filename(&client, &emojis, modifier.as_deref()).await;
+            let billed = match &result {
+                Ok(suggested) => Some(suggested.usage),
+                Err(e) => e.usage,
+            };
+            if let Some(billed) = billed {
+                let generation_id = generation_id.unwrap_or_else(cancel::new_generation_id);
+                let record = UsageRecord::new(
+                    &generation_id,
+                    gemini::MODEL_TEXT,
+                    credentials.profile.as_deref(),
+                    billed,
+                );
+                log_failure("record usage", usage.record(&record));
+            }
+            result.ok().map(|suggested| suggested.name)
+        }
+        Err(_) => None,
+    };
+    let name = name.unwrap_or_else(|| "emoji".to_string());
 
-    // Generate filename using Gemini
-    let name = gemini::generate_filename(&emojis, modifier.as_deref(), &api_key)
-        .await
-        .unwrap_or_else(|_| "emoji".to_string());
+    let downloads =
+        dirs::download_dir().ok_or_else(|| Error::new(ErrorKind::Storage, "Could not find Downloads directory"))?;
+
+    let file_path = output::write_unique(&downloads, &output::sanitize_filename(&name), &extension, &image_bytes)?;
 
-    let downloads = dirs::download_dir()
-        .ok_or_else(|| "Could not find Downloads directory".to_string())?;
+    Ok(file_path.to_string_lossy().to_string())
+}
 
-    // Find a unique filename
-    let base_name = sanitize_filename(&name);
-    let mut file_path = downloads.join(format!("{}.png", base_name));
-    let mut counter = 2;
+/// Animate an image with a local effect, e.g. a finished result's raw image.
+/// Works without an API key.
+#[tauri::command]
+async fn animate_image(
+    image_base64: String,
+    effect: Effect,
+    frames: Option<usize>,
+    format: Option<AnimationFormat>,
+    max_bytes: Option<usize>,
+    tolerance: Option<f32>,
+) -> Result<Animation> {
+    let image_bytes = STANDARD
+        .decode(&image_base64)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Failed to decode image: {}", e)))?;
+    let background = BackgroundOptions::with_tolerance(tolerance)?;
+    let options = animation_options(frames, format, max_bytes);
+    tokio::task::spawn_blocking(move || animation::animate_image(&image_bytes, effect, &options, &background))
+        .await
+        .map_err(|e| Error::new(ErrorKind::ImageProcessing, format!("Animation task failed: {}", e)))?
+}
 
-    while file_path.exists() {
-        file_path = downloads.join(format!("{}_{}.png", base_name, counter));
-        counter += 1;
+/// Generate an animated emoji. With an `effect`, one image is generated and
+/// animated locally; otherwise the provider draws every frame, following `motion`.
+/// Progress reports each frame as a slot, and the result is kept in history.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn generate_animation(
+    emojis: Vec<String>,
+    modifier: Option<String>,
+    motion: Option<String>,
+    effect: Option<Effect>,
+    frames: Option<usize>,
+    format: Option<AnimationFormat>,
+    max_bytes: Option<usize>,
+    provider: Option<String>,
+    model: Option<String>,
+    fast_model: Option<bool>,
+    tolerance: Option<f32>,
+    sizes: Option<Vec<u32>>,
+    margin: Option<u32>,
+    padding: Option<u32>,
+    on_progress: Channel<GenerationProgress>,
+    generations: State<'_, ActiveGenerations>,
+    history: State<'_, HistoryStore>,
+    profiles: State<'_, ProfileStore>,
+    usage: State<'_, UsageStore>,
+) -> Result<Animation> {
+    let background = BackgroundOptions::with_tolerance(tolerance)?;
+    let output = OutputOptions::new(sizes.clone(), margin, padding)?;
+    let prompt = generation::prepare_prompt(&emojis, modifier.as_deref(), &[] as &[String])?;
+    let options = AnimationOptions {
+        output,
+        ..animation_options(frames, format, max_bytes)
+    };
+    let images = options.generated_images(effect)?;
+
+    let stores = Stores::new(&generations, &history, &profiles, &usage);
+    let source = Source::New {
+        provider: provider.as_deref(),
+        model: model.as_deref(),
+        fast: fast_model.unwrap_or(false),
+    };
+    let output = options.output.clone();
+    let started = start_run(stores, &on_progress, source, &prompt, |_| images, background, output)?;
+    let entry = HistoryEntry {
+        tolerance,
+        sizes,
+        margin,
+        padding,
+        animation: Some(HistoryAnimation {
+            motion: motion.clone(),
+            effect,
+            format: options.format,
+            frames,
+            saved: false,
+        }),
+        ..started.entry(&prompt.text, &emojis, modifier.as_deref())
+    };
+    started.run.record(&entry, &prompt.images);
+
+    let Started { provider, run, .. } = started;
+    let animation = animation::generate_animation(
+        provider.as_ref(),
+        &prompt,
+        &run.model,
+        motion.as_deref(),
+        effect,
+        &options,
+        &run.generation_run(),
+        &|progress| run.report(progress),
+    )
+    .await;
+    if let Ok(animation) = &animation {
+        log_failure(
+            "record animation in history",
+            history.record_animation(&run.active.id, animation),
+        );
     }
+    run.finish();
+    animation
+}
 
-    fs::write(&file_path, &image_bytes).map_err(|e| format!("Failed to save file: {}", e))?;
+/// Animation settings from command arguments; the size limit defaults to Slack's
+fn animation_options(
+    frames: Option<usize>,
+    format: Option<AnimationFormat>,
+    max_bytes: Option<usize>,
+) -> AnimationOptions {
+    let defaults = AnimationOptions::default();
+    AnimationOptions {
+        format: format.unwrap_or(defaults.format),
+        frames,
+        max_bytes: max_bytes.or(defaults.max_bytes),
+        ..defaults
+    }
+}
 
-    Ok(file_path.to_string_lossy().to_string())
+/// Remove the background of an existing image, or of every image in a
+/// directory. Works without an API key. `sizes` lists canvas sizes to write,
+/// 128 by default.
+#[tauri::command]
+async fn process_image(
+    path: String,
+    out_dir: Option<String>,
+    tolerance: Option<f32>,
+    sizes: Option<Vec<u32>>,
+    margin: Option<u32>,
+    padding: Option<u32>,
+) -> Result<Vec<process::ProcessedFile>> {
+    let options = BackgroundOptions::with_tolerance(tolerance)?;
+    let output = OutputOptions::new(sizes, margin, padding)?;
+    tokio::task::spawn_blocking(move || {
+        let out_dir = out_dir.as_deref().map(std::path::Path::new);
+        process::process_path(std::path::Path::new(&path), out_dir, &options, &output)
+    })
+    .await
+    .map_err(|e| Error::new(ErrorKind::ImageProcessing, format!("Processing task failed: {}", e)))?
 }
 
-fn sanitize_filename(name: &str) -> String {
-    name.chars()
-        .filter_map(|c| {
-            if c.is_ascii_alphanumeric() {
-                Some(c.to_ascii_lowercase())
-            } else if c == '_' || c == '-' || c == ' ' {
-                Some('_')
-            } else {
-                None
-            }
-        })
-        .collect::<String>()
-        .trim_matches('_')
-        .to_string()
+/// Add a processed image (PNG, or an animated GIF) to the Slack workspace as
+/// `:name:`. Returns the name as uploaded.
+#[tauri::command]
+async fn upload_to_slack(image_base64: String, name: String) -> Result<String> {
+    let token = api_key::require(&api_key::Secret::SlackToken)?;
+    let image_bytes = STANDARD
+        .decode(&image_base64)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Failed to decode image: {}", e)))?;
+    slack::upload_emoji(&slack::SlackClient::new(&token), &name, &image_bytes).await
+}
+
+/// Add a processed image or animation to a Discord server as `:name:`. The
+/// server defaults to `discord_guild_id` from config.json.
+#[tauri::command]
+async fn upload_to_discord(
+    image_base64: String,
+    name: String,
+    guild_id: Option<String>,
+) -> Result<discord::DiscordEmoji> {
+    let token = api_key::require(&api_key::Secret::DiscordToken)?;
+    let guild_id = discord_guild_id(guild_id)?;
+    let image_bytes = STANDARD
+        .decode(&image_base64)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Failed to decode image: {}", e)))?;
+    discord::upload_emoji(&discord::DiscordClient::new(&token), &guild_id, &name, &image_bytes).await
+}
+
+/// Add a processed image or animation to a Discord server as a sticker.
+/// `tags` are autocomplete keywords and default to the name.
+#[tauri::command]
+async fn upload_sticker_to_discord(
+    image_base64: String,
+    name: String,
+    tags: Option<String>,
+    guild_id: Option<String>,
+) -> Result<discord::DiscordSticker> {
+    let token = api_key::require(&api_key::Secret::DiscordToken)?;
+    let guild_id = discord_guild_id(guild_id)?;
+    let image_bytes = STANDARD
+        .decode(&image_base64)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Failed to decode image: {}", e)))?;
+    discord::upload_sticker(
+        &discord::DiscordClient::new(&token),
+        &guild_id,
+        &name,
+        tags.as_deref(),
+        &image_bytes,
+    )
+    .await
+}
+
+/// The given server ID, or `discord_guild_id` from config.json
+fn discord_guild_id(guild_id: Option<String>) -> Result<String> {
+    guild_id
+        .filter(|id| !id.trim().is_empty())
+        .or_else(|| config::Settings::load().discord_guild_id)
+        .map(|id| id.trim().to_string())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No Discord server ID given"))
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
+        .plugin(tauri_plugin_log::Builder::new().level(log::LevelFilter::Info).build())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
+        // Runs once the log plugin is set up, before any command can read a key
+        .setup(|_| {
+            log_failure("migrate stored API keys", api_key::migrate());
+            Ok(())
+        })
+        .manage(ActiveGenerations::default())
+        .manage(HistoryStore::open_default())
+        .manage(ProfileStore::open_default())
+        .manage(UsageStore::open_default())
         .invoke_handler(tauri::generate_handler![
             generate_emoji,
+            refine_emoji,
+            regenerate_slot,
+            replay_generation,
+            cancel_generation,
+            list_history,
+            load_history_entry,
+            delete_history_entry,
             save_emoji_image,
+            process_image,
+            animate_image,
+            generate_animation,
+            list_providers,
             check_api_key,
+            validate_api_key,
             save_api_key,
-            clear_api_key
+            clear_api_key,
+            api_key_storage,
+            unlock_api_keys,
+            set_api_key_passphrase,
+            list_profiles,
+            add_profile,
+            remove_profile,
+            select_profile,
+            get_usage_report,
+            upload_to_slack,
+            check_slack_token,
+            save_slack_token,
+            clear_slack_token,
+            upload_to_discord,
+            upload_sticker_to_discord,
+            check_discord_token,
+            save_discord_token,
+            clear_discord_token
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,238 @@
+//! In-process HTTP server that stands in for remote APIs in tests.
+//!
+//! Each request is recorded and answered by a handler closure, so tests can
+//! script success, failure and malformed responses without network access.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A request received by the mock server
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    /// Look up a header by case-insensitive name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn body_json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).expect("request body should be JSON")
+    }
+}
+
+/// A canned response returned by a handler
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&RecordedRequest) -> MockResponse + Send + Sync;
+
+pub struct MockServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockServer {
+    /// Start a server on a random local port, answering every request with `handler`
+    pub async fn start(handler: impl Fn(&RecordedRequest) -> MockResponse + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock server");
+        let addr = listener.local_addr().expect("mock server address");
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let _ = serve_connection(stream, handler, recorded).await;
+                });
+            }
+        });
+
+        Self {
+            base_url: format!("http://{}", addr),
+            requests,
+        }
+    }
+
+    /// All requests received so far, in arrival order
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    handler: Arc<Handler>,
+    recorded: Arc<Mutex<Vec<RecordedRequest>>>,
+) -> std::io::Result<()> {
+    // Read until the end of the headers
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let header_end = loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect();
+
+    let content_length = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    let mut body = buf[header_end..].to_vec();
+    while body.len() < content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+
+    let request = RecordedRequest {
+        method,
+        path,
+        headers,
+        body,
+    };
+    recorded.lock().unwrap().push(request.clone());
+    let response = handler(&request);
+
+    let mut out = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (name, value) in &response.headers {
+        out.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    out.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+    out.push_str(&response.body);
+    stream.write_all(out.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Canned `generateContent` responses
+pub mod gemini {
+    use super::MockResponse;
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde_json::json;
+
+    /// A small PNG: red square on a solid green background
+    pub fn green_background_png() -> Vec<u8> {
+        let mut img = image::RgbaImage::from_pixel(64, 64, image::Rgba([0, 255, 0, 255]));
+        for y in 16..48 {
+            for x in 16..48 {
+                img.put_pixel(x, y, image::Rgba([220, 30, 30, 255]));
+            }
+        }
+        let mut out = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    /// A response with one inline image part
+    pub fn image(png: &[u8]) -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "candidates": [{
+                    "content": {
+                        "parts": [{ "inlineData": { "mimeType": "image/png", "data": STANDARD.encode(png) } }]
+                    },
+                    "finishReason": "STOP"
+                }],
+                "usageMetadata": { "promptTokenCount": 12, "candidatesTokenCount": 1290, "totalTokenCount": 1302 }
+            }),
+        )
+    }
+
+    /// A response with one text part
+    pub fn text(text: &str) -> MockResponse {
+        MockResponse::json(
+            200,
+            json!({
+                "candidates": [{
+                    "content": { "parts": [{ "text": text }] },
+                    "finishReason": "STOP"
+                }]
+            }),
+        )
+    }
+
+    /// A successful response with no candidates at all
+    pub fn empty_candidates() -> MockResponse {
+        MockResponse::json(200, json!({ "candidates": [] }))
+    }
+
+    /// A prompt rejected by the safety filters: no candidates, only feedback
+    pub fn safety_block() -> MockResponse {
+        MockResponse::json(200, json!({ "promptFeedback": { "blockReason": "SAFETY" } }))
+    }
+
+    /// A Gemini-style error body with the given HTTP status
+    pub fn error(status: u16, message: &str) -> MockResponse {
+        MockResponse::json(
+            status,
+            json!({ "error": { "code": status, "message": message, "status": "ERROR" } }),
+        )
+    }
+
+    /// A 200 response whose body is not valid JSON
+    pub fn malformed() -> MockResponse {
+        MockResponse::text(200, "{\"candidates\": [")
+    }
+}
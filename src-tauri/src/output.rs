@@ -0,0 +1,84 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+/// Turn a generated name into a safe snake_case file stem
+pub fn sanitize_filename(name: &str) -> String {
+    name.chars()
+        .filter_map(|c| {
+            if c.is_ascii_alphanumeric() {
+                Some(c.to_ascii_lowercase())
+            } else if c == '_' || c == '-' || c == ' ' {
+                Some('_')
+            } else {
+                None
+            }
+        })
+        .collect::<String>()
+        .trim_matches('_')
+        .to_string()
+}
+
+/// `dir/<stem>.<ext>`, or `dir/<stem>_2.<ext>`, `_3`, ... if that already exists
+pub fn unique_path(dir: &Path, stem: &str, ext: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", stem, ext));
+    let mut counter = 2;
+
+    while path.exists() {
+        path = dir.join(format!("{}_{}.{}", stem, counter, ext));
+        counter += 1;
+    }
+
+    path
+}
+
+/// Write `bytes` to a new file in `dir` without overwriting anything
+pub fn write_unique(dir: &Path, stem: &str, ext: &str, bytes: &[u8]) -> Result<PathBuf> {
+    let path = unique_path(dir, stem, ext);
+    fs::write(&path, bytes).map_err(|e| Error::from(e).context("Failed to save file"))?;
+    Ok(path)
+}
+
+/// Replace `path` with `bytes` by writing a sibling `.tmp` file and renaming
+/// it, so a crash never leaves a half-written file
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("Cowboy Scream!"), "cowboy_scream");
+        assert_eq!(sanitize_filename("  _hat-cat_ "), "hat_cat");
+        assert_eq!(sanitize_filename("🤠"), "");
+    }
+
+    #[test]
+    fn test_write_unique_never_overwrites() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let first = write_unique(dir, "emoji", "png", b"one").unwrap();
+        let second = write_unique(dir, "emoji", "png", b"two").unwrap();
+        assert_eq!(first.file_name().unwrap(), "emoji.png");
+        assert_eq!(second.file_name().unwrap(), "emoji_2.png");
+        assert_eq!(fs::read(&first).unwrap(), b"one");
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_the_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("entry.json");
+        write_atomic(&path, b"one").unwrap();
+        write_atomic(&path, b"two").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"two");
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 1);
+    }
+}
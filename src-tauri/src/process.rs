@@ -0,0 +1,216 @@
+//! Background removal for existing images, outside of generation. Needs no API key.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::background::{self, BackgroundOptions, OutputOptions};
+use crate::error::{Error, ErrorKind, Result};
+use crate::output;
+
+/// Extensions picked up when processing a directory
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+
+/// Outputs of a directory batch go here, so a second run doesn't pick them up as inputs
+const BATCH_OUT_DIR: &str = "processed";
+
+/// Outcome for one input file. A failed file doesn't stop the rest of a batch.
+#[derive(Debug, Serialize)]
+pub struct ProcessedFile {
+    pub input: PathBuf,
+    pub flood_fill: Option<PathBuf>,
+    pub color_key: Option<PathBuf>,
+    pub soft_matte: Option<PathBuf>,
+    /// Every variant at the additional output sizes
+    pub extra_sizes: Vec<PathBuf>,
+    pub warning: Option<String>,
+    pub error: Option<Error>,
+}
+
+/// Cut out and resize a single image file, or every image in a directory.
+///
+/// Results are written as `<stem>_flood_fill.png`, `<stem>_color_key.png` and `<stem>_soft_matte.png`,
+/// next to a single input or into `<dir>/processed/` for a directory, unless
+/// `out_dir` is given. Additional sizes get a suffix, e.g. `<stem>_soft_matte_512.png`.
+pub fn process_path(
+    input: &Path,
+    out_dir: Option<&Path>,
+    options: &BackgroundOptions,
+    output: &OutputOptions,
+) -> Result<Vec<ProcessedFile>> {
+    let metadata =
+        fs::metadata(input).map_err(|e| Error::from(e).context(&format!("Failed to read {}", input.display())))?;
+
+    let (files, default_out) = if metadata.is_dir() {
+        (image_files(input)?, input.join(BATCH_OUT_DIR))
+    } else {
+        let parent = input.parent().unwrap_or(Path::new(".")).to_path_buf();
+        (vec![input.to_path_buf()], parent)
+    };
+    if files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("No images found in {}", input.display()),
+        ));
+    }
+
+    let out_dir = out_dir.map(Path::to_path_buf).unwrap_or(default_out);
+    fs::create_dir_all(&out_dir).map_err(|e| Error::from(e).context("Failed to create output directory"))?;
+
+    Ok(files
+        .iter()
+        .map(|file| process_file(file, &out_dir, options, output))
+        .collect())
+}
+
+fn process_file(input: &Path, out_dir: &Path, options: &BackgroundOptions, output: &OutputOptions) -> ProcessedFile {
+    let mut result = ProcessedFile {
+        input: input.to_path_buf(),
+        flood_fill: None,
+        color_key: None,
+        soft_matte: None,
+        extra_sizes: Vec::new(),
+        warning: None,
+        error: None,
+    };
+
+    let processed = fs::read(input)
+        .map_err(|e| Error::from(e).context(&format!("Failed to read {}", input.display())))
+        .and_then(|bytes| background::resize_to_emoji(&bytes, options, output));
+    let processed = match processed {
+        Ok(processed) => processed,
+        Err(e) => {
+            result.error = Some(e);
+            return result;
+        }
+    };
+    result.warning = processed.warning;
+
+    let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or("emoji");
+    let write =
+        |variant: &str, bytes: &[u8]| output::write_unique(out_dir, &format!("{}_{}", stem, variant), "png", bytes);
+    let written = write("flood_fill", &processed.flood_fill).and_then(|flood_fill| {
+        let main = (
+            flood_fill,
+            write("color_key", &processed.color_key)?,
+            write("soft_matte", &processed.soft_matte)?,
+        );
+        let mut extra = Vec::new();
+        for sized in &processed.extra_sizes {
+            extra.push(write(&format!("flood_fill_{}", sized.size), &sized.flood_fill)?);
+            extra.push(write(&format!("color_key_{}", sized.size), &sized.color_key)?);
+            extra.push(write(&format!("soft_matte_{}", sized.size), &sized.soft_matte)?);
+        }
+        Ok((main, extra))
+    });
+    match written {
+        Ok(((flood_fill, color_key, soft_matte), extra_sizes)) => {
+            result.flood_fill = Some(flood_fill);
+            result.color_key = Some(color_key);
+            result.soft_matte = Some(soft_matte);
+            result.extra_sizes = extra_sizes;
+        }
+        Err(e) => result.error = Some(e),
+    }
+    result
+}
+
+/// Image files directly inside `dir`, sorted by name
+fn image_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| Error::from(e).context(&format!("Failed to read {}", dir.display())))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::gemini::green_background_png;
+
+    #[test]
+    fn test_process_single_file_writes_next_to_input() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let input = dir.join("logo.png");
+        fs::write(&input, green_background_png()).unwrap();
+
+        let results = process_path(&input, None, &BackgroundOptions::default(), &OutputOptions::default()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].error.is_none());
+        assert_eq!(
+            results[0].flood_fill.as_deref(),
+            Some(dir.join("logo_flood_fill.png").as_path())
+        );
+        assert_eq!(
+            results[0].color_key.as_deref(),
+            Some(dir.join("logo_color_key.png").as_path())
+        );
+        assert!(results[0].extra_sizes.is_empty());
+    }
+
+    #[test]
+    fn test_process_directory_keeps_going_after_failures() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("a.png"), green_background_png()).unwrap();
+        fs::write(dir.join("b.png"), b"not really a png").unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+        let results = process_path(dir, None, &BackgroundOptions::default(), &OutputOptions::default()).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].error.is_none());
+        assert!(
+            results[0]
+                .flood_fill
+                .as_ref()
+                .unwrap()
+                .starts_with(dir.join("processed"))
+        );
+        assert_eq!(results[1].error.as_ref().unwrap().kind, ErrorKind::ImageProcessing);
+
+        // Outputs live in processed/, so running again sees the same inputs
+        assert_eq!(
+            process_path(dir, None, &BackgroundOptions::default(), &OutputOptions::default())
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_process_writes_additional_sizes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let input = dir.join("logo.png");
+        fs::write(&input, green_background_png()).unwrap();
+
+        let output = OutputOptions::new(Some(vec![128, 512]), None, None).unwrap();
+        let results = process_path(&input, None, &BackgroundOptions::default(), &output).unwrap();
+        assert_eq!(results[0].extra_sizes.len(), 3);
+        assert!(results[0].extra_sizes.contains(&dir.join("logo_soft_matte_512.png")));
+    }
+
+    #[test]
+    fn test_process_empty_directory_is_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        assert_eq!(
+            process_path(dir, None, &BackgroundOptions::default(), &OutputOptions::default())
+                .unwrap_err()
+                .kind,
+            ErrorKind::InvalidInput
+        );
+    }
+}
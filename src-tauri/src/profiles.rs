@@ -0,0 +1,421 @@
+//! Named API key profiles, e.g. personal, team and billing-project keys.
+//!
+//! Each profile has its own Gemini key (kept by `api_key` like the default
+//! key), an optional default model and an optional daily image budget.
+//! Profile settings and today's usage live in `~/.config/mojimix/profiles.json`.
+//! Without an active profile the default key is used.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::api_key::{self, Secret};
+use crate::config;
+use crate::error::{Error, ErrorKind, Result};
+use crate::history::now_millis;
+use crate::output;
+
+const PROFILES_FILE: &str = "profiles.json";
+
+const MAX_NAME_LEN: usize = 32;
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    /// Model used when a generation doesn't ask for one
+    #[serde(default)]
+    pub default_model: Option<String>,
+    /// Most images generated per day (UTC) with this profile
+    #[serde(default)]
+    pub daily_budget: Option<u32>,
+}
+
+/// A profile as listed to the frontend
+#[derive(Clone, Debug, Serialize)]
+pub struct ProfileInfo {
+    #[serde(flatten)]
+    pub profile: Profile,
+    pub active: bool,
+    pub used_today: u32,
+}
+
+/// Images generated by a profile on one day
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+struct DailyUsage {
+    /// Days since the Unix epoch (UTC)
+    day: u64,
+    images: u32,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ProfilesFile {
+    active: Option<String>,
+    profiles: Vec<Profile>,
+    usage: BTreeMap<String, DailyUsage>,
+}
+
+impl ProfilesFile {
+    fn find(&self, name: &str) -> Result<&Profile> {
+        self.profiles
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("No profile named '{}'", name)))
+    }
+
+    fn used_today(&self, name: &str) -> u32 {
+        self.usage
+            .get(name)
+            .filter(|usage| usage.day == today())
+            .map_or(0, |usage| usage.images)
+    }
+}
+
+/// The key and settings to generate with
+pub struct Credentials {
+    pub api_key: String,
+    /// Active profile, if any
+    pub profile: Option<String>,
+    pub default_model: Option<String>,
+}
+
+fn today() -> u64 {
+    now_millis() / MILLIS_PER_DAY
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid profile name '{}': use up to {} letters, numbers, '-' and '_'",
+                name, MAX_NAME_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub struct ProfileStore {
+    path: PathBuf,
+    // Serializes read-modify-write of profiles.json, and holds the images
+    // reserved by running generations per profile
+    lock: Mutex<BTreeMap<String, usize>>,
+}
+
+/// Images held against a profile's daily budget while a generation runs.
+/// Dropping it releases them; `finish` releases them and counts the ones generated.
+pub struct Reservation<'a> {
+    store: &'a ProfileStore,
+    name: String,
+    images: usize,
+}
+
+impl Reservation<'_> {
+    /// Count `generated` images against today's usage and release the rest
+    pub fn finish(mut self, generated: usize) -> Result<()> {
+        let mut reserved = self.store.lock.lock().unwrap();
+        release(&mut reserved, &self.name, std::mem::take(&mut self.images));
+        if generated == 0 {
+            return Ok(());
+        }
+        let mut file = self.store.read()?;
+        let images = file.used_today(&self.name) + generated as u32;
+        file.usage
+            .insert(self.name.clone(), DailyUsage { day: today(), images });
+        self.store.write(&file)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut reserved = self.store.lock.lock().unwrap_or_else(|e| e.into_inner());
+        release(&mut reserved, &self.name, self.images);
+    }
+}
+
+fn release(reserved: &mut BTreeMap<String, usize>, name: &str, images: usize) {
+    if let Some(count) = reserved.get_mut(name) {
+        *count = count.saturating_sub(images);
+        if *count == 0 {
+            reserved.remove(name);
+        }
+    }
+}
+
+impl ProfileStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Store in the config dir (~/.config/mojimix/profiles.json on Linux)
+    pub fn open_default() -> Self {
+        let dir = config::config_dir().unwrap_or_else(std::env::temp_dir);
+        Self::new(dir.join(PROFILES_FILE))
+    }
+
+    fn read(&self) -> Result<ProfilesFile> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| Error::new(ErrorKind::Storage, format!("Corrupt {}: {}", PROFILES_FILE, e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ProfilesFile::default()),
+            Err(e) => Err(Error::from(e).context("Failed to read profiles")),
+        }
+    }
+
+    fn write(&self, file: &ProfilesFile) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::from(e).context("Failed to create config directory"))?;
+        }
+        let json = serde_json::to_string_pretty(file)
+            .map_err(|e| Error::new(ErrorKind::Storage, format!("Failed to serialize profiles: {}", e)))?;
+        output::write_atomic(&self.path, json.as_bytes())
+            .map_err(|e| Error::from(e).context("Failed to write profiles"))
+    }
+
+    pub fn list(&self) -> Result<Vec<ProfileInfo>> {
+        let file = self.read()?;
+        Ok(file
+            .profiles
+            .iter()
+            .map(|profile| ProfileInfo {
+                profile: profile.clone(),
+                active: file.active.as_deref() == Some(profile.name.as_str()),
+                used_today: file.used_today(&profile.name),
+            })
+            .collect())
+    }
+
+    /// Add a profile's settings; its key is saved separately by `add_profile`
+    fn add(&self, profile: Profile) -> Result<()> {
+        validate_name(&profile.name)?;
+        if profile.daily_budget == Some(0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Daily budget must be at least 1 image",
+            ));
+        }
+        let _guard = self.lock.lock().unwrap();
+        let mut file = self.read()?;
+        if file.profiles.iter().any(|p| p.name == profile.name) {
+            return Err(Error::new(
+                ErrorKind::NameTaken,
+                format!("A profile named '{}' already exists", profile.name),
+            ));
+        }
+        file.profiles.push(profile);
+        self.write(&file)
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut file = self.read()?;
+        file.find(name)?;
+        file.profiles.retain(|p| p.name != name);
+        file.usage.remove(name);
+        if file.active.as_deref() == Some(name) {
+            file.active = None;
+        }
+        self.write(&file)
+    }
+
+    /// Make `name` the active profile, or go back to the default key with `None`
+    pub fn select(&self, name: Option<&str>) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut file = self.read()?;
+        if let Some(name) = name {
+            file.find(name)?;
+        }
+        file.active = name.map(str::to_string);
+        self.write(&file)
+    }
+
+    pub fn active(&self) -> Result<Option<Profile>> {
+        let file = self.read()?;
+        match &file.active {
+            Some(name) => file.find(name).cloned().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Hold `images` against the profile's daily budget, failing if they would
+    /// go over it together with today's usage and other running generations
+    pub fn reserve(&self, name: &str, images: usize) -> Result<Reservation<'_>> {
+        let mut reserved = self.lock.lock().unwrap();
+        let file = self.read()?;
+        if let Some(budget) = file.find(name)?.daily_budget {
+            let used = file.used_today(name) as usize + reserved.get(name).copied().unwrap_or(0);
+            if used + images > budget as usize {
+                return Err(Error::new(
+                    ErrorKind::LimitReached,
+                    format!(
+                        "Profile '{}' has used {} of its {} images today; {} more requested",
+                        name, used, budget, images
+                    ),
+                ));
+            }
+        }
+        *reserved.entry(name.to_string()).or_default() += images;
+        Ok(Reservation {
+            store: self,
+            name: name.to_string(),
+            images,
+        })
+    }
+}
+
+/// Add a profile and store its key
+pub fn add_profile(store: &ProfileStore, profile: Profile, key: &str) -> Result<()> {
+    if key.trim().is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "API key cannot be empty"));
+    }
+    let name = profile.name.clone();
+    store.add(profile)?;
+    if let Err(e) = api_key::save(&Secret::Profile(name.clone()), key) {
+        // Don't leave a profile behind that has no key
+        let _ = store.remove(&name);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Remove a profile and its key
+pub fn remove_profile(store: &ProfileStore, name: &str) -> Result<()> {
+    store.remove(name)?;
+    api_key::clear(&Secret::Profile(name.to_string()))
+}
+
+/// The secret holding the key generations use right now
+pub fn active_secret(store: &ProfileStore) -> Result<Secret> {
+    Ok(match store.active()? {
+        Some(profile) => Secret::Profile(profile.name),
+        None => Secret::GeminiApiKey,
+    })
+}
+
+/// The active profile's key and settings, or the default key without one
+pub fn credentials(store: &ProfileStore) -> Result<Credentials> {
+    match store.active()? {
+        Some(profile) => Ok(Credentials {
+            api_key: api_key::require(&Secret::Profile(profile.name.clone()))?,
+            profile: Some(profile.name),
+            default_model: profile.default_model,
+        }),
+        None => Ok(Credentials {
+            api_key: api_key::require_api_key()?,
+            profile: None,
+            default_model: None,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (tempfile::TempDir, ProfileStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ProfileStore::new(dir.path().join(PROFILES_FILE));
+        (dir, store)
+    }
+
+    fn profile(name: &str, daily_budget: Option<u32>) -> Profile {
+        Profile {
+            name: name.to_string(),
+            default_model: Some("gemini-2.5-flash-image".to_string()),
+            daily_budget,
+        }
+    }
+
+    #[test]
+    fn test_add_select_remove() {
+        let (_dir, store) = store();
+        assert!(store.list().unwrap().is_empty());
+        assert!(store.active().unwrap().is_none());
+
+        store.add(profile("personal", None)).unwrap();
+        store.add(profile("team", Some(20))).unwrap();
+        assert_eq!(store.add(profile("team", None)).unwrap_err().kind, ErrorKind::NameTaken);
+        assert_eq!(
+            store.add(profile("../team", None)).unwrap_err().kind,
+            ErrorKind::InvalidInput
+        );
+
+        store.select(Some("team")).unwrap();
+        assert_eq!(store.active().unwrap(), Some(profile("team", Some(20))));
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().any(|p| p.active && p.profile.name == "team"));
+        assert_eq!(store.select(Some("billing")).unwrap_err().kind, ErrorKind::InvalidInput);
+
+        // Removing the active profile falls back to the default key
+        store.remove("team").unwrap();
+        assert!(store.active().unwrap().is_none());
+        assert_eq!(store.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_daily_budget() {
+        let (_dir, store) = store();
+        store.add(profile("team", Some(5))).unwrap();
+        store.add(profile("personal", None)).unwrap();
+
+        store.reserve("team", 4).unwrap().finish(4).unwrap();
+        assert_eq!(store.list().unwrap()[0].used_today, 4);
+        let reservation = store.reserve("team", 1).unwrap();
+        drop(reservation);
+        let err = store.reserve("team", 2).err().unwrap();
+        assert_eq!(err.kind, ErrorKind::LimitReached);
+        assert!(err.message.contains("4 of its 5"));
+
+        // No budget, no limit
+        store.reserve("personal", 1000).unwrap().finish(1000).unwrap();
+        store.reserve("personal", 8).unwrap();
+    }
+
+    #[test]
+    fn test_running_generations_share_the_budget() {
+        let (_dir, store) = store();
+        store.add(profile("team", Some(5))).unwrap();
+
+        let first = store.reserve("team", 4).unwrap();
+        assert_eq!(store.reserve("team", 2).err().unwrap().kind, ErrorKind::LimitReached);
+        let second = store.reserve("team", 1).unwrap();
+
+        // Images that weren't generated go back to the budget
+        first.finish(2).unwrap();
+        second.finish(1).unwrap();
+        assert_eq!(store.list().unwrap()[0].used_today, 3);
+        store.reserve("team", 2).unwrap();
+        assert!(store.reserve("team", 3).is_err());
+    }
+
+    #[test]
+    fn test_usage_resets_on_a_new_day() {
+        let (_dir, store) = store();
+        store.add(profile("team", Some(5))).unwrap();
+        let mut file = store.read().unwrap();
+        file.usage.insert(
+            "team".to_string(),
+            DailyUsage {
+                day: today() - 1,
+                images: 5,
+            },
+        );
+        store.write(&file).unwrap();
+
+        store.reserve("team", 5).unwrap().finish(2).unwrap();
+        assert_eq!(store.list().unwrap()[0].used_today, 2);
+    }
+}
@@ -0,0 +1,166 @@
+use futures::future::BoxFuture;
+
+use crate::error::{Error, ErrorKind, Result};
+use crate::gemini::{GeminiClient, GeminiProvider};
+use crate::usage::Usage;
+
+/// Provider used when a request doesn't name one
+pub const DEFAULT_PROVIDER: &str = "gemini";
+
+/// A model offered by an image provider
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ModelInfo {
+    pub id: String,
+    pub name: String,
+    /// Cheaper/faster model, used when the caller asks for the fast option
+    pub fast: bool,
+}
+
+/// What a provider supports, so the UI can hide options that won't work
+#[derive(Clone, serde::Serialize)]
+pub struct Capabilities {
+    /// Accepts images as input alongside the prompt
+    pub image_input: bool,
+    /// Maximum number of images that can be requested in one generation
+    pub max_images: usize,
+}
+
+/// Summary of a provider for the frontend
+#[derive(serde::Serialize)]
+pub struct ProviderInfo {
+    pub id: String,
+    pub name: String,
+    pub models: Vec<ModelInfo>,
+    pub capabilities: Capabilities,
+}
+
+/// An image sent to the provider alongside the prompt text
+#[derive(Clone, Debug)]
+pub struct InputImage {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl InputImage {
+    /// Wrap encoded image bytes, detecting the format from their contents
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let mime_type = match image::guess_format(&data) {
+            Ok(image::ImageFormat::Png) => "image/png",
+            Ok(image::ImageFormat::Gif) => "image/gif",
+            Ok(image::ImageFormat::Jpeg) => "image/jpeg",
+            Ok(image::ImageFormat::WebP) => "image/webp",
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Unsupported input image: expected PNG, GIF, JPEG or WebP",
+                ));
+            }
+        };
+        Ok(Self {
+            mime_type: mime_type.to_string(),
+            data,
+        })
+    }
+}
+
+/// A raw generated image and what the request consumed
+#[derive(Clone, Debug)]
+pub struct GeneratedImage {
+    pub data: Vec<u8>,
+    pub usage: Usage,
+}
+
+/// What to generate: instructions plus any input images they refer to
+#[derive(Clone, Debug, Default)]
+pub struct Prompt {
+    pub text: String,
+    pub images: Vec<InputImage>,
+}
+
+impl Prompt {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn with_images(mut self, images: Vec<InputImage>) -> Self {
+        self.images = images;
+        self
+    }
+}
+
+/// A backend that turns a prompt into a raw image.
+///
+/// Implementations only produce the raw image bytes; background removal and
+/// resizing happen afterwards in `background::resize_to_emoji`, the same for every provider.
+pub trait ImageProvider: Send + Sync {
+    fn id(&self) -> &'static str;
+
+    fn name(&self) -> &'static str;
+
+    fn models(&self) -> Vec<ModelInfo>;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Generate a single image for the prompt using the given model id.
+    /// Only called with input images if `capabilities().image_input` is set.
+    fn generate<'a>(&'a self, prompt: &'a Prompt, model: &'a str) -> BoxFuture<'a, Result<GeneratedImage>>;
+
+    /// Pick a model: the requested one if offered, otherwise the first fast/quality match
+    fn resolve_model(&self, model: Option<&str>, fast: bool) -> Result<String> {
+        let models = self.models();
+        if let Some(requested) = model {
+            return models
+                .iter()
+                .find(|m| m.id == requested)
+                .map(|m| m.id.clone())
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Model '{}' is not offered by provider '{}'", requested, self.id()),
+                    )
+                });
+        }
+        models
+            .iter()
+            .find(|m| m.fast == fast)
+            .or_else(|| models.first())
+            .map(|m| m.id.clone())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Provider '{}' has no models", self.id()),
+                )
+            })
+    }
+}
+
+/// Ids of all registered providers
+pub const PROVIDER_IDS: &[&str] = &["gemini"];
+
+/// Create the provider registered under `id`
+pub fn create_provider(id: &str, api_key: &str) -> Result<Box<dyn ImageProvider>> {
+    match id {
+        "gemini" => Ok(Box::new(GeminiProvider::new(GeminiClient::new(api_key)))),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unknown image provider: {}", id),
+        )),
+    }
+}
+
+/// Describe every registered provider
+pub fn list_providers() -> Vec<ProviderInfo> {
+    PROVIDER_IDS
+        .iter()
+        .filter_map(|id| create_provider(id, "").ok())
+        .map(|p| ProviderInfo {
+            id: p.id().to_string(),
+            name: p.name().to_string(),
+            models: p.models(),
+            capabilities: p.capabilities(),
+        })
+        .collect()
+}
@@ -0,0 +1,119 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// How failed requests are retried
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts per image, including the first one
+    pub max_attempts: u32,
+    /// Retries shared by all images in one generation, so a dead backend
+    /// doesn't multiply every slot's wait
+    pub budget: u32,
+    /// Backoff before the first retry; doubles with each attempt
+    pub base_delay: Duration,
+    /// Upper bound for a single wait. A longer `Retry-After` isn't waited out.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            budget: 12,
+            base_delay: Duration::from_millis(1000),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after `attempt` (1-based) failed.
+    ///
+    /// Uses "full jitter": a random wait between zero and the exponential
+    /// backoff, so parallel slots that failed together don't retry together.
+    /// A server-provided `retry_after` is treated as the minimum wait; `None`
+    /// means it's longer than `max_delay` and the request shouldn't be retried.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let jittered = exp.mul_f64(random_fraction());
+        match retry_after {
+            Some(min) if min > self.max_delay => None,
+            Some(min) => Some(min.max(jittered)),
+            None => Some(jittered),
+        }
+    }
+}
+
+/// Retries remaining for one generation
+pub struct RetryBudget(AtomicU32);
+
+impl RetryBudget {
+    pub fn new(retries: u32) -> Self {
+        Self(AtomicU32::new(retries))
+    }
+
+    /// Take one retry from the budget, returning false if it's used up
+    pub fn try_take(&self) -> bool {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+    }
+}
+
+/// Random number in [0, 1) from std's randomly seeded hasher
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Parse a `Retry-After` header given in seconds. HTTP dates are ignored.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_is_bounded_by_backoff() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+            ..RetryPolicy::default()
+        };
+        for _ in 0..50 {
+            assert!(policy.delay(1, None).unwrap() <= Duration::from_millis(100));
+            assert!(policy.delay(2, None).unwrap() <= Duration::from_millis(200));
+            assert!(policy.delay(5, None).unwrap() <= Duration::from_millis(350));
+        }
+    }
+
+    #[test]
+    fn test_delay_respects_retry_after() {
+        let policy = RetryPolicy::default();
+        assert!(policy.delay(1, Some(Duration::from_secs(5))).unwrap() >= Duration::from_secs(5));
+        assert_eq!(policy.delay(1, Some(policy.max_delay)), Some(policy.max_delay));
+        // Not waited out, so a huge Retry-After can't stall the UI
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(600))), None);
+    }
+
+    #[test]
+    fn test_budget_runs_out() {
+        let budget = RetryBudget::new(2);
+        assert!(budget.try_take());
+        assert!(budget.try_take());
+        assert!(!budget.try_take());
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 7 "), Some(Duration::from_secs(7)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+    }
+}
@@ -0,0 +1,333 @@
+//! Upload finished emojis to a Slack workspace through the `emoji.add` API.
+//!
+//! `emoji.add` is the endpoint Slack's own clients use. It takes the image
+//! itself, unlike `admin.emoji.add`, which only takes a public URL and needs an
+//! Enterprise Grid org admin. It refuses bot and app-level tokens, so it needs
+//! a user token (`xoxp-`, or a legacy `xoxs-`) of a member allowed to add emojis.
+
+use reqwest::Client;
+use reqwest::multipart::{Form, Part};
+use serde::Deserialize;
+
+use crate::animation::SLACK_MAX_BYTES;
+use crate::config::{self, Settings};
+use crate::error::{Error, ErrorKind, Result};
+use crate::retry;
+
+const DEFAULT_BASE_URL: &str = "https://slack.com/api";
+
+/// Environment variable that overrides the API base URL
+const BASE_URL_ENV: &str = "MOJIMIX_SLACK_BASE_URL";
+
+/// Longest emoji name Slack accepts
+const MAX_NAME_LEN: usize = 100;
+
+/// Prefixes of the user tokens `emoji.add` accepts
+const USER_TOKEN_PREFIXES: &[&str] = &["xoxp-", "xoxs-"];
+
+/// Resolve the API base URL: env var first, then config.json, then the public endpoint
+pub fn base_url() -> String {
+    config::env_override(BASE_URL_ENV)
+        .or_else(|| Settings::load().slack_base_url)
+        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string())
+}
+
+pub struct SlackClient {
+    http: Client,
+    base_url: String,
+    token: String,
+}
+
+impl SlackClient {
+    /// Create a client using the configured base URL
+    pub fn new(token: &str) -> Self {
+        Self::with_base_url(token, &base_url())
+    }
+
+    pub fn with_base_url(token: &str, base_url: &str) -> Self {
+        Self {
+            http: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+}
+
+/// Every Slack Web API response; `error` is set when `ok` is false
+#[derive(Deserialize)]
+struct SlackResponse {
+    ok: bool,
+    error: Option<String>,
+}
+
+/// Check that a token is a user token, since `emoji.add` refuses bot and app tokens
+pub fn validate_token(token: &str) -> Result<()> {
+    let token = token.trim();
+    if USER_TOKEN_PREFIXES.iter().any(|p| token.starts_with(p)) {
+        return Ok(());
+    }
+    let kind = if token.starts_with("xoxb-") {
+        "a bot token"
+    } else if token.starts_with("xapp-") {
+        "an app-level token"
+    } else {
+        "not a Slack user token"
+    };
+    Err(Error::new(
+        ErrorKind::InvalidApiKey,
+        format!(
+            "Slack emoji uploads need a user token (xoxp-...) with permission to add emojis; this is {}",
+            kind
+        ),
+    ))
+}
+
+/// Check an emoji name against Slack's rules and return it without surrounding colons
+pub fn validate_name(name: &str) -> Result<String> {
+    let name = name.trim().trim_matches(':');
+    if name.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Emoji name cannot be empty"));
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Emoji name is longer than {} characters", MAX_NAME_LEN),
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid emoji name '{}': use lowercase letters, numbers, underscores and hyphens",
+                name
+            ),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// Check that an image is a format and size Slack accepts, returning its MIME type
+fn validate_image(image: &[u8]) -> Result<&'static str> {
+    if image.len() > SLACK_MAX_BYTES {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Image is {} KB, Slack's limit is {} KB",
+                image.len().div_ceil(1024),
+                SLACK_MAX_BYTES / 1024
+            ),
+        ));
+    }
+    match image::guess_format(image) {
+        Ok(image::ImageFormat::Png) => Ok("image/png"),
+        Ok(image::ImageFormat::Gif) => Ok("image/gif"),
+        Ok(image::ImageFormat::Jpeg) => Ok("image/jpeg"),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Slack only accepts PNG, GIF and JPEG emojis",
+        )),
+    }
+}
+
+/// Add `image` to the workspace as `:name:`. Returns the name as uploaded.
+pub async fn upload_emoji(client: &SlackClient, name: &str, image: &[u8]) -> Result<String> {
+    validate_token(&client.token)?;
+    let name = validate_name(name)?;
+    let mime_type = validate_image(image)?;
+
+    let file = Part::bytes(image.to_vec())
+        .file_name(name.clone())
+        .mime_str(mime_type)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+    let form = Form::new()
+        .text("mode", "data")
+        .text("name", name.clone())
+        .part("image", file);
+
+    let response = client
+        .http
+        .post(format!("{}/emoji.add", client.base_url))
+        .bearer_auth(&client.token)
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| Error::from(e).context("Failed to reach Slack"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(retry::parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+        return Err(
+            Error::from_status(status.as_u16(), format!("Slack error {}: {}", status, body))
+                .with_retry_after(retry_after),
+        );
+    }
+
+    let slack_response: SlackResponse = response.json().await.map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidResponse,
+            format!("Failed to parse Slack response: {}", e),
+        )
+    })?;
+    if slack_response.ok {
+        return Ok(name);
+    }
+    Err(slack_error(
+        &name,
+        slack_response.error.as_deref().unwrap_or("unknown_error"),
+    ))
+}
+
+/// Map a Slack error code to an error the frontend can act on
+fn slack_error(name: &str, code: &str) -> Error {
+    match code {
+        "error_name_taken" | "error_name_taken_i18n" => Error::new(
+            ErrorKind::NameTaken,
+            format!("The workspace already has an emoji named :{}:", name),
+        ),
+        "not_authed" | "invalid_auth" | "token_revoked" | "token_expired" | "account_inactive" => {
+            Error::new(ErrorKind::InvalidApiKey, format!("Slack rejected the token: {}", code))
+        }
+        "missing_scope" | "not_allowed_token_type" | "no_permission" | "restricted_action" => Error::new(
+            ErrorKind::PermissionDenied,
+            format!("Slack token isn't allowed to add emojis: {}", code),
+        ),
+        "ratelimited" => Error::new(ErrorKind::RateLimited, "Slack rate limit reached"),
+        code if code.starts_with("invalid_name") => Error::new(
+            ErrorKind::InvalidInput,
+            format!("Slack rejected the name :{}:: {}", name, code),
+        ),
+        "too_large" | "error_too_big" | "resized_but_still_too_large" | "error_bad_format" | "error_bad_upload" => {
+            Error::new(ErrorKind::InvalidInput, format!("Slack rejected the image: {}", code))
+        }
+        code => Error::new(ErrorKind::ApiError, format!("Slack error: {}", code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer, gemini::green_background_png};
+    use serde_json::json;
+
+    fn client(server: &MockServer) -> SlackClient {
+        SlackClient::with_base_url("xoxp-test", &server.base_url)
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert_eq!(validate_name(":cowboy_scream:").unwrap(), "cowboy_scream");
+        assert_eq!(validate_name("party-parrot2").unwrap(), "party-parrot2");
+        for bad in ["", "::", "Cowboy", "cowboy scream", "cowboy.scream", "🤠"] {
+            assert_eq!(
+                validate_name(bad).unwrap_err().kind,
+                ErrorKind::InvalidInput,
+                "{:?}",
+                bad
+            );
+        }
+        assert!(validate_name(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload_sends_multipart_form() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({ "ok": true }))).await;
+        let name = upload_emoji(&client(&server), ":cowboy_scream:", &green_background_png())
+            .await
+            .unwrap();
+        assert_eq!(name, "cowboy_scream");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/emoji.add");
+        assert_eq!(request.header("authorization"), Some("Bearer xoxp-test"));
+        assert!(
+            request
+                .header("content-type")
+                .unwrap()
+                .starts_with("multipart/form-data")
+        );
+        let body = String::from_utf8_lossy(&request.body);
+        assert!(body.contains("name=\"name\"\r\n\r\ncowboy_scream\r\n"));
+        assert!(body.contains("name=\"mode\"\r\n\r\ndata\r\n"));
+        assert!(body.contains("Content-Type: image/png"));
+    }
+
+    #[tokio::test]
+    async fn test_upload_reports_name_collision() {
+        let server =
+            MockServer::start(|_| MockResponse::json(200, json!({ "ok": false, "error": "error_name_taken" }))).await;
+        let err = upload_emoji(&client(&server), "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::NameTaken);
+        assert!(err.message.contains(":cowboy:"));
+    }
+
+    #[tokio::test]
+    async fn test_upload_error_kinds() {
+        let server =
+            MockServer::start(|_| MockResponse::json(200, json!({ "ok": false, "error": "invalid_auth" }))).await;
+        let err = upload_emoji(&client(&server), "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidApiKey);
+
+        // A valid token without the scope or workspace permission isn't a bad token
+        let server =
+            MockServer::start(|_| MockResponse::json(200, json!({ "ok": false, "error": "missing_scope" }))).await;
+        let err = upload_emoji(&client(&server), "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::PermissionDenied);
+
+        let server = MockServer::start(|_| {
+            MockResponse::json(429, json!({ "ok": false, "error": "ratelimited" })).with_header("Retry-After", "30")
+        })
+        .await;
+        let err = upload_emoji(&client(&server), "cowboy", &green_background_png())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::RateLimited);
+        assert_eq!(err.retry_after_ms, Some(30_000));
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_bot_and_app_tokens() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({ "ok": true }))).await;
+        for token in ["xoxb-1234-bot", "xapp-1-app", "not-a-token"] {
+            let client = SlackClient::with_base_url(token, &server.base_url);
+            let err = upload_emoji(&client, "cowboy", &green_background_png())
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind, ErrorKind::InvalidApiKey, "{}", token);
+        }
+        assert!(server.requests().is_empty());
+        assert!(validate_token(" xoxs-legacy ").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_upload_checks_image_before_sending() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({ "ok": true }))).await;
+        let mut too_big = green_background_png();
+        too_big.resize(SLACK_MAX_BYTES + 1, 0);
+        let err = upload_emoji(&client(&server), "cowboy", &too_big).await.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidInput);
+        assert!(err.message.contains("128 KB"));
+
+        let err = upload_emoji(&client(&server), "cowboy", b"not an image")
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidInput);
+        assert!(server.requests().is_empty());
+    }
+}
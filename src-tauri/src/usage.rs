@@ -0,0 +1,365 @@
+//! Token and image usage, priced per model.
+//!
+//! Providers report what each request consumed (Gemini's `usageMetadata`).
+//! Every request is appended to `usage.jsonl` in the data directory with its
+//! generation, model and profile, and `UsageStore::report` sums the records
+//! per generation, day, profile and model using a price table that
+//! config.json can override.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::ops::AddAssign;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Settings;
+use crate::error::{Error, ErrorKind, Result};
+use crate::history::now_millis;
+
+const USAGE_FILE: &str = "usage.jsonl";
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// What one or more requests consumed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub requests: u32,
+    pub input_tokens: u64,
+    /// Everything billed as output, including thinking and image tokens
+    pub output_tokens: u64,
+    pub images: u32,
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.requests += other.requests;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.images += other.images;
+    }
+}
+
+impl std::iter::Sum for Usage {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Usage::default(), |mut total, usage| {
+            total += usage;
+            total
+        })
+    }
+}
+
+/// US dollars per million tokens
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPrice {
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input_per_million + usage.output_tokens as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Published Gemini prices, overridden or extended by `prices` in config.json
+pub fn price_table() -> BTreeMap<String, ModelPrice> {
+    let mut prices = BTreeMap::from([
+        (
+            "gemini-3-pro-image-preview".to_string(),
+            ModelPrice {
+                input_per_million: 2.0,
+                output_per_million: 120.0,
+            },
+        ),
+        (
+            "gemini-2.5-flash-image".to_string(),
+            ModelPrice {
+                input_per_million: 0.3,
+                output_per_million: 30.0,
+            },
+        ),
+        // Names saved files
+        (
+            crate::gemini::MODEL_TEXT.to_string(),
+            ModelPrice {
+                input_per_million: 0.1,
+                output_per_million: 0.4,
+            },
+        ),
+    ]);
+    prices.extend(Settings::load().prices);
+    prices
+}
+
+/// One request, as stored in usage.jsonl
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub generation_id: String,
+    /// Milliseconds since the Unix epoch
+    pub at: u64,
+    pub model: String,
+    pub profile: Option<String>,
+    pub usage: Usage,
+}
+
+impl UsageRecord {
+    pub fn new(generation_id: &str, model: &str, profile: Option<&str>, usage: Usage) -> Self {
+        Self {
+            generation_id: generation_id.to_string(),
+            at: now_millis(),
+            model: model.to_string(),
+            profile: profile.map(str::to_string),
+            usage,
+        }
+    }
+}
+
+/// Usage and its cost for one group of requests
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct UsageTotals {
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// In US dollars; requests to models missing from the price table count as free
+    pub cost_usd: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, usage: Usage, price: Option<&ModelPrice>) {
+        self.usage += usage;
+        self.cost_usd += price.map_or(0.0, |p| p.cost(&usage));
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GenerationUsage {
+    pub generation_id: String,
+    pub model: String,
+    pub profile: Option<String>,
+    /// First request, in milliseconds since the Unix epoch
+    pub at: u64,
+    /// UTC date of the first request, "YYYY-MM-DD"
+    pub day: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+/// Result of `get_usage_report`; groups are keyed by date, profile name ("" for the default key) and model
+#[derive(Debug, Default, Serialize)]
+pub struct UsageReport {
+    pub total: UsageTotals,
+    /// Newest first
+    pub generations: Vec<GenerationUsage>,
+    pub days: BTreeMap<String, UsageTotals>,
+    pub profiles: BTreeMap<String, UsageTotals>,
+    pub models: BTreeMap<String, UsageTotals>,
+    /// Models used but missing from the price table
+    pub unpriced_models: Vec<String>,
+}
+
+/// Format days since the Unix epoch as a "YYYY-MM-DD" date
+fn format_day(days: u64) -> String {
+    // Howard Hinnant's civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+pub struct UsageStore {
+    path: PathBuf,
+    // Keeps appended lines whole when slots finish at the same time
+    lock: Mutex<()>,
+}
+
+impl UsageStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Store next to history in the data dir (~/.local/share/mojimix/usage.jsonl on Linux)
+    pub fn open_default() -> Self {
+        let data_dir = dirs::data_dir().unwrap_or_else(std::env::temp_dir);
+        Self::new(data_dir.join("mojimix").join(USAGE_FILE))
+    }
+
+    pub fn record(&self, record: &UsageRecord) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::from(e).context("Failed to create data directory"))?;
+        }
+        let mut line = serde_json::to_string(record)
+            .map_err(|e| Error::new(ErrorKind::Storage, format!("Failed to serialize usage: {}", e)))?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| Error::from(e).context("Failed to record usage"))
+    }
+
+    /// Records from the last `days` days (UTC, including today), or all of them.
+    /// Unreadable lines, e.g. from a crash mid-write, are skipped.
+    fn records(&self, days: Option<u32>) -> Result<Vec<UsageRecord>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::from(e).context("Failed to read usage")),
+        };
+        let today = now_millis() / MILLIS_PER_DAY;
+        let first_day = days.map_or(0, |days| today.saturating_sub(u64::from(days.max(1)) - 1));
+        Ok(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str::<UsageRecord>(line).ok())
+            .filter(|record| record.at / MILLIS_PER_DAY >= first_day)
+            .collect())
+    }
+
+    /// Sum usage from the last `days` days, priced with `prices`
+    pub fn report(&self, days: Option<u32>, prices: &BTreeMap<String, ModelPrice>) -> Result<UsageReport> {
+        let mut report = UsageReport::default();
+        let mut generations: Vec<GenerationUsage> = Vec::new();
+        let mut generation_index: BTreeMap<String, usize> = BTreeMap::new();
+
+        for record in self.records(days)? {
+            let price = prices.get(&record.model);
+            if price.is_none() && !report.unpriced_models.contains(&record.model) {
+                report.unpriced_models.push(record.model.clone());
+            }
+            let day = format_day(record.at / MILLIS_PER_DAY);
+
+            report.total.add(record.usage, price);
+            report.days.entry(day.clone()).or_default().add(record.usage, price);
+            report
+                .profiles
+                .entry(record.profile.clone().unwrap_or_default())
+                .or_default()
+                .add(record.usage, price);
+            report
+                .models
+                .entry(record.model.clone())
+                .or_default()
+                .add(record.usage, price);
+
+            let index = *generation_index.entry(record.generation_id.clone()).or_insert_with(|| {
+                generations.push(GenerationUsage {
+                    generation_id: record.generation_id.clone(),
+                    model: record.model.clone(),
+                    profile: record.profile.clone(),
+                    at: record.at,
+                    day,
+                    totals: UsageTotals::default(),
+                });
+                generations.len() - 1
+            });
+            let generation = &mut generations[index];
+            generation.at = generation.at.min(record.at);
+            generation.totals.add(record.usage, price);
+        }
+
+        // Records are appended in order, so reversing first keeps ties newest first
+        generations.reverse();
+        generations.sort_by_key(|g| std::cmp::Reverse(g.at));
+        report.generations = generations;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> (tempfile::TempDir, UsageStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UsageStore::new(dir.path().join(USAGE_FILE));
+        (dir, store)
+    }
+
+    fn usage(input_tokens: u64, output_tokens: u64) -> Usage {
+        Usage {
+            requests: 1,
+            input_tokens,
+            output_tokens,
+            images: 1,
+        }
+    }
+
+    #[test]
+    fn test_format_day() {
+        assert_eq!(format_day(0), "1970-01-01");
+        assert_eq!(format_day(19_782), "2024-02-29");
+        assert_eq!(format_day(20_743), "2026-10-17");
+    }
+
+    #[test]
+    fn test_report_groups_and_prices() {
+        let (_dir, store) = store();
+        let prices = BTreeMap::from([
+            (
+                "pro".to_string(),
+                ModelPrice {
+                    input_per_million: 2.0,
+                    output_per_million: 120.0,
+                },
+            ),
+            (
+                "fast".to_string(),
+                ModelPrice {
+                    input_per_million: 0.3,
+                    output_per_million: 30.0,
+                },
+            ),
+        ]);
+
+        store
+            .record(&UsageRecord::new("gen-1", "pro", Some("team"), usage(1000, 1120)))
+            .unwrap();
+        store
+            .record(&UsageRecord::new("gen-1", "pro", Some("team"), usage(1000, 1120)))
+            .unwrap();
+        store
+            .record(&UsageRecord::new("gen-2", "fast", None, usage(1000, 1290)))
+            .unwrap();
+        let mut yesterday = UsageRecord::new("gen-0", "other", None, usage(10, 10));
+        yesterday.at -= MILLIS_PER_DAY;
+        store.record(&yesterday).unwrap();
+
+        let report = store.report(None, &prices).unwrap();
+        assert_eq!(report.total.usage.requests, 4);
+        assert_eq!(report.total.usage.images, 4);
+        assert_eq!(report.unpriced_models, ["other"]);
+        assert_eq!(report.days.len(), 2);
+
+        // 2 x (1000 x $2 + 1120 x $120) / 1M
+        let pro = report.models["pro"].cost_usd;
+        assert!((pro - 0.2728).abs() < 1e-9, "{}", pro);
+        let fast = report.models["fast"].cost_usd;
+        assert!((fast - 0.0390).abs() < 1e-9, "{}", fast);
+        assert!((report.profiles["team"].cost_usd - pro).abs() < 1e-9);
+        assert_eq!(report.profiles[""].usage.requests, 2);
+
+        // Newest generation first, one row per generation
+        let ids: Vec<&str> = report.generations.iter().map(|g| g.generation_id.as_str()).collect();
+        assert_eq!(ids, ["gen-2", "gen-1", "gen-0"]);
+        assert_eq!(report.generations[1].totals.usage.requests, 2);
+
+        // Only today
+        let today = store.report(Some(1), &prices).unwrap();
+        assert_eq!(today.total.usage.requests, 3);
+        assert!(today.unpriced_models.is_empty());
+    }
+}
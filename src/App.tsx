@@ -2,15 +2,66 @@ import { useState, useEffect, useCallback } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { EmojiPicker } from "./components/EmojiPicker";
 import { TextModifier } from "./components/TextModifier";
+import { ReferenceImages } from "./components/ReferenceImages";
+import { ProcessImages } from "./components/ProcessImages";
+import { ProfileSelector } from "./components/ProfileSelector";
+import { UsageSummary } from "./components/UsageSummary";
 import { GenerateButton } from "./components/GenerateButton";
 import { HistoryPanel } from "./components/HistoryPanel";
 import { SelectionPanel } from "./components/SelectionPanel";
 import { useGeminiGeneration } from "./hooks/useGeminiGeneration";
-import type { HistoryItem } from "./types";
+import { toAppError, isAuthError } from "./types";
+import type {
+  ApiKeyStorage,
+  HistoryItem,
+  KeyValidation,
+  SavedHistoryDetail,
+  SavedHistoryPage,
+} from "./types";
+
+/** Load the most recent saved generations as history items, oldest first */
+async function loadSavedHistory(): Promise<HistoryItem[]> {
+  const page = await invoke<SavedHistoryPage>("list_history", {
+    offset: 0,
+    limit: 20,
+  });
+  const details = await Promise.all(
+    page.entries.map((entry) =>
+      invoke<SavedHistoryDetail>("load_history_entry", { id: entry.id })
+    )
+  );
+  return details.reverse().flatMap((entry) =>
+    entry.images
+      .filter((slot) => slot.status === "success" || slot.status === "error")
+      .map((slot) => ({
+        id: `${entry.id}-${slot.index}`,
+        generationId: entry.id,
+        slotIndex: slot.index,
+        sourceEmojis: entry.emojis,
+        modifier: entry.modifier ?? "",
+        model: entry.model,
+        status: slot.status as "success" | "error",
+        floodFill: slot.flood_fill,
+        colorKey: slot.color_key,
+        softMatte: slot.soft_matte,
+        rawImage: slot.raw_image,
+        warning: slot.warning,
+        error: slot.error?.message ?? null,
+      }))
+  );
+}
 
-function ApiKeySetup({ onSaved }: { onSaved: () => void }) {
+function ApiKeySetup({
+  onSaved,
+  notice,
+}: {
+  onSaved: () => void;
+  /** Why the stored key was sent back here, e.g. the model isn't enabled for it */
+  notice: string | null;
+}) {
   const [apiKey, setApiKey] = useState("");
-  const [error, setError] = useState<string | null>(null);
+  const [passphrase, setPassphrase] = useState("");
+  const [error, setError] = useState<string | null>(notice);
   const [saving, setSaving] = useState(false);
 
   const handleSubmit = async (e: React.FormEvent) => {
@@ -19,10 +70,26 @@ function ApiKeySetup({ onSaved }: { onSaved: () => void }) {
     setSaving(true);
 
     try {
+      // Catch typos now rather than on the first generation. Offline, save anyway.
+      const validation = await invoke<KeyValidation>("validate_api_key", {
+        apiKey,
+      });
+      if (validation.status === "invalid") {
+        setError("Gemini doesn't recognise this key");
+        return;
+      }
+      if (validation.status === "permission_denied") {
+        setError(validation.message ?? "This key can't use the image models");
+        return;
+      }
+      // Only used when there's no OS keychain and the key goes to a file
+      if (passphrase) {
+        await invoke("set_api_key_passphrase", { passphrase });
+      }
       await invoke("save_api_key", { key: apiKey });
       onSaved();
     } catch (err) {
-      setError(String(err));
+      setError(toAppError(err).message);
     } finally {
       setSaving(false);
     }
@@ -47,6 +114,14 @@ function ApiKeySetup({ onSaved }: { onSaved: () => void }) {
             className="border-retro mb-3 w-full rounded-lg bg-[var(--bg-secondary)] px-4 py-3 text-[var(--text-primary)] placeholder-[var(--text-muted)] focus:border-[var(--electric-blue)] focus:shadow-[0_0_15px_var(--electric-blue-glow)] focus:outline-none"
             autoFocus
           />
+          <input
+            type="password"
+            value={passphrase}
+            onChange={(e) => setPassphrase(e.target.value)}
+            placeholder="Passphrase (optional)"
+            title="Encrypts the key if your system has no keychain"
+            className="border-retro mb-3 w-full rounded-lg bg-[var(--bg-secondary)] px-4 py-2 text-sm text-[var(--text-primary)] placeholder-[var(--text-muted)] focus:border-[var(--electric-blue)] focus:outline-none"
+          />
 
           {error && (
             <div className="font-pixel mb-3 text-sm text-[var(--hot-pink)]">
@@ -78,30 +153,109 @@ function ApiKeySetup({ onSaved }: { onSaved: () => void }) {
   );
 }
 
+/** The stored key is encrypted: ask for the passphrase */
+function ApiKeyUnlock({ onUnlocked }: { onUnlocked: () => void }) {
+  const [passphrase, setPassphrase] = useState("");
+  const [error, setError] = useState<string | null>(null);
+
+  const handleSubmit = async (e: React.FormEvent) => {
+    e.preventDefault();
+    setError(null);
+    try {
+      await invoke("unlock_api_keys", { passphrase });
+      onUnlocked();
+    } catch (err) {
+      setError(toAppError(err).message);
+    }
+  };
+
+  return (
+    <div className="flex h-screen items-center justify-center bg-[var(--bg-primary)] p-6">
+      <div className="border-retro relative z-10 w-full max-w-md rounded-lg bg-[var(--surface)] p-6">
+        <h1 className="font-pixel mb-2 text-2xl text-[var(--electric-blue)]">
+          UNLOCK
+        </h1>
+        <p className="mb-4 text-sm text-[var(--text-secondary)]">
+          Your API key is encrypted. Enter your passphrase to use it.
+        </p>
+
+        <form onSubmit={handleSubmit}>
+          <input
+            type="password"
+            value={passphrase}
+            onChange={(e) => setPassphrase(e.target.value)}
+            className="border-retro mb-3 w-full rounded-lg bg-[var(--bg-secondary)] px-4 py-3 text-[var(--text-primary)] focus:border-[var(--electric-blue)] focus:outline-none"
+            autoFocus
+          />
+
+          {error && (
+            <div className="font-pixel mb-3 text-sm text-[var(--hot-pink)]">
+              ! {error}
+            </div>
+          )}
+
+          <button
+            type="submit"
+            disabled={!passphrase}
+            className={`btn-bevel font-pixel w-full rounded-lg py-3 text-sm font-bold text-white ${
+              !passphrase ? "cursor-not-allowed bg-[var(--border-chunky)] opacity-50" : "bg-[var(--electric-blue)]"
+            }`}
+          >
+            UNLOCK
+          </button>
+        </form>
+      </div>
+    </div>
+  );
+}
+
 const COUNT_OPTIONS = [1, 2, 4, 8] as const;
 
 function App() {
   const [selectedEmojis, setSelectedEmojis] = useState<string[]>([]);
   const [modifier, setModifier] = useState("");
+  const [referenceImages, setReferenceImages] = useState<string[]>([]);
   const [hasApiKey, setHasApiKey] = useState<boolean | null>(null);
+  const [keyLocked, setKeyLocked] = useState(false);
+  const [keyNotice, setKeyNotice] = useState<string | null>(null);
   const [fastModel, setFastModel] = useState(true);
   const [generationCount, setGenerationCount] = useState(4);
   const [history, setHistory] = useState<HistoryItem[]>([]);
   const [selectedId, setSelectedId] = useState<string | null>(null);
 
-  const { isLoading, pendingCount, error, generate } =
+  const { isLoading, pendingCount, error, generate, regenerateSlot, replay, refine, cancel } =
     useGeminiGeneration();
 
   const selectedItem = history.find((h) => h.id === selectedId) ?? null;
 
   const checkApiKey = () => {
-    invoke<boolean>("check_api_key").then(setHasApiKey);
+    invoke<ApiKeyStorage>("api_key_storage").then(({ storage, locked }) => {
+      setKeyLocked(locked);
+      setHasApiKey(storage !== "none");
+    });
   };
 
   useEffect(() => {
     checkApiKey();
+    loadSavedHistory()
+      .then((saved) => setHistory((prev) => [...saved, ...prev]))
+      .catch((err) => console.error("Failed to load history:", err));
   }, []);
 
+  // The key was rejected or removed: go back to the setup screen
+  useEffect(() => {
+    if (isAuthError(error)) {
+      setKeyNotice(null);
+      setHasApiKey(false);
+    } else if (error?.kind === "permission_denied") {
+      // The key works but can't use this model: say so instead of a bare setup screen
+      setKeyNotice(error.message || "This key can't use the image models");
+      setHasApiKey(false);
+    } else if (error?.kind === "locked") {
+      setKeyLocked(true);
+    }
+  }, [error]);
+
   const handleEmojiSelect = (emoji: string) => {
     setSelectedEmojis((prev) => [...prev, emoji]);
   };
@@ -111,7 +265,12 @@ function App() {
   };
 
   const handleItemComplete = useCallback((item: HistoryItem) => {
-    setHistory((prev) => [...prev, item]);
+    // A regenerated slot keeps its id and replaces the old image in place
+    setHistory((prev) =>
+      prev.some((h) => h.id === item.id)
+        ? prev.map((h) => (h.id === item.id ? item : h))
+        : [...prev, item]
+    );
     // Auto-select first successful item if nothing selected
     if (item.status === "success") {
       setSelectedId((current) => current ?? item.id);
@@ -122,6 +281,7 @@ function App() {
     generate(
       selectedEmojis,
       modifier,
+      referenceImages,
       fastModel,
       generationCount,
       handleItemComplete
@@ -131,6 +291,7 @@ function App() {
   const handleClear = () => {
     setSelectedEmojis([]);
     setModifier("");
+    setReferenceImages([]);
   };
 
   const handleClearHistory = () => {
@@ -145,9 +306,13 @@ function App() {
     );
   }
 
+  if (keyLocked) {
+    return <ApiKeyUnlock onUnlocked={checkApiKey} />;
+  }
+
   // No API key - show setup
   if (hasApiKey === false) {
-    return <ApiKeySetup onSaved={checkApiKey} />;
+    return <ApiKeySetup onSaved={checkApiKey} notice={keyNotice} />;
   }
 
   return (
@@ -162,6 +327,8 @@ function App() {
             onEmojiRemove={handleEmojiRemove}
           />
 
+          <ReferenceImages paths={referenceImages} onChange={setReferenceImages} />
+
           <TextModifier value={modifier} onChange={setModifier} />
 
           {/* Model toggle */}
@@ -229,13 +396,27 @@ function App() {
 
           <GenerateButton
             onClick={handleGenerate}
-            disabled={selectedEmojis.length === 0}
+            disabled={selectedEmojis.length === 0 && referenceImages.length === 0}
             isLoading={isLoading}
           />
 
+          <ProcessImages />
+
+          <ProfileSelector onChange={checkApiKey} />
+
+          <UsageSummary refreshKey={history.length} />
+
           {/* Clear buttons */}
           <div className="mt-3 flex items-center justify-center gap-4">
-            {(selectedEmojis.length > 0 || modifier) && (
+            {isLoading && (
+              <button
+                onClick={cancel}
+                className="text-sm text-[var(--text-muted)] transition-colors hover:text-[var(--hot-pink)]"
+              >
+                [ CANCEL ]
+              </button>
+            )}
+            {(selectedEmojis.length > 0 || modifier || referenceImages.length > 0) && (
               <button
                 onClick={handleClear}
                 className="text-sm text-[var(--text-muted)] transition-colors hover:text-[var(--hot-pink)]"
@@ -259,7 +440,7 @@ function App() {
           {error && (
             <div className="border-retro mb-3 rounded-lg border-[var(--hot-pink)] bg-[var(--surface-elevated)] p-3">
               <div className="font-pixel text-sm text-[var(--hot-pink)]">
-                ! ERROR: {error}
+                ! ERROR: {error.message}
               </div>
             </div>
           )}
@@ -277,6 +458,11 @@ function App() {
         <div className="border-retro flex w-[280px] shrink-0 flex-col rounded-lg bg-[var(--surface)] p-4">
           <SelectionPanel
             selectedItem={selectedItem}
+            onRegenerate={(item) => regenerateSlot(item, handleItemComplete)}
+            onReplay={(item) => replay(item, generationCount, handleItemComplete)}
+            onRefine={(item, instruction) =>
+              refine(item, instruction, generationCount, handleItemComplete)
+            }
           />
         </div>
       </div>
@@ -1,7 +1,7 @@
 import { useState } from "react";
 import type { HistoryItem } from "../types";
 
-type ImageVariant = "raw" | "flood" | "colorKey";
+type ImageVariant = "raw" | "flood" | "colorKey" | "softMatte";
 
 interface Props {
   history: HistoryItem[];
@@ -25,6 +25,7 @@ export function HistoryPanel({
       case "raw": return item.rawImage;
       case "flood": return item.floodFill;
       case "colorKey": return item.colorKey;
+      case "softMatte": return item.softMatte;
     }
   };
 
@@ -32,6 +33,7 @@ export function HistoryPanel({
     { key: "raw", label: "RAW" },
     { key: "flood", label: "FLOOD" },
     { key: "colorKey", label: "COLOUR KEY" },
+    { key: "softMatte", label: "SOFT MATTE" },
   ];
 
   return (
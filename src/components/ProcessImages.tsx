@@ -0,0 +1,78 @@
+import { useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { open } from "@tauri-apps/plugin-dialog";
+import { toAppError } from "../types";
+import type { ProcessedFile } from "../types";
+
+export function ProcessImages() {
+  const [results, setResults] = useState<ProcessedFile[] | null>(null);
+  const [error, setError] = useState<string | null>(null);
+  const [busy, setBusy] = useState(false);
+
+  const handleProcess = async (directory: boolean) => {
+    const selected = await open({
+      directory,
+      filters: directory
+        ? undefined
+        : [{ name: "Image", extensions: ["png", "jpg", "jpeg", "gif", "webp", "bmp"] }],
+    });
+    if (!selected || Array.isArray(selected)) return;
+
+    setBusy(true);
+    setError(null);
+    setResults(null);
+    try {
+      setResults(await invoke<ProcessedFile[]>("process_image", { path: selected }));
+    } catch (err) {
+      setError(toAppError(err).message);
+    } finally {
+      setBusy(false);
+    }
+  };
+
+  const fileName = (path: string) => path.split(/[\\/]/).pop();
+
+  return (
+    <div className="mt-4">
+      <div className="font-pixel mb-2 flex items-center justify-between text-sm text-[var(--cyber-yellow)]">
+        <span>&gt; CUT OUT EXISTING:</span>
+        <span className="flex gap-2">
+          <button
+            onClick={() => handleProcess(false)}
+            disabled={busy}
+            className="text-[var(--text-muted)] transition-colors hover:text-[var(--electric-blue)]"
+          >
+            [ FILE ]
+          </button>
+          <button
+            onClick={() => handleProcess(true)}
+            disabled={busy}
+            className="text-[var(--text-muted)] transition-colors hover:text-[var(--electric-blue)]"
+          >
+            [ FOLDER ]
+          </button>
+        </span>
+      </div>
+      {error && <p className="text-xs text-[var(--hot-pink)]">{error}</p>}
+      {results && (
+        <ul className="flex max-h-24 flex-col gap-1 overflow-y-auto text-xs">
+          {results.map((result) => (
+            <li key={result.input} className="text-[var(--text-secondary)]">
+              {fileName(result.input)}:{" "}
+              {result.error ? (
+                <span className="text-[var(--hot-pink)]">{result.error.message}</span>
+              ) : (
+                <span className="text-[var(--lime)]">
+                  {fileName(result.color_key ?? "")}
+                  {result.warning && (
+                    <span className="text-[var(--cyber-yellow)]"> ({result.warning})</span>
+                  )}
+                </span>
+              )}
+            </li>
+          ))}
+        </ul>
+      )}
+    </div>
+  );
+}
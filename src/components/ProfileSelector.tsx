@@ -0,0 +1,144 @@
+import { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import { toAppError } from "../types";
+import type { ProfileInfo } from "../types";
+
+interface Props {
+  /** Called after the active profile changes so the key can be rechecked */
+  onChange: () => void;
+}
+
+const inputClass =
+  "w-full rounded border border-[var(--border-chunky)] bg-[var(--surface-elevated)] px-2 py-1 text-xs text-[var(--text-primary)]";
+
+export function ProfileSelector({ onChange }: Props) {
+  const [profiles, setProfiles] = useState<ProfileInfo[]>([]);
+  const [adding, setAdding] = useState(false);
+  const [name, setName] = useState("");
+  const [apiKey, setApiKey] = useState("");
+  const [defaultModel, setDefaultModel] = useState("");
+  const [budget, setBudget] = useState("");
+  const [error, setError] = useState<string | null>(null);
+
+  const refresh = () =>
+    invoke<ProfileInfo[]>("list_profiles")
+      .then(setProfiles)
+      .catch((err) => setError(toAppError(err).message));
+
+  useEffect(() => {
+    refresh();
+  }, []);
+
+  const run = async (action: () => Promise<unknown>) => {
+    setError(null);
+    try {
+      await action();
+      await refresh();
+      onChange();
+      return true;
+    } catch (err) {
+      setError(toAppError(err).message);
+      return false;
+    }
+  };
+
+  const handleSelect = (value: string) =>
+    run(() => invoke("select_profile", { name: value || null }));
+
+  const handleAdd = async () => {
+    const added = await run(() =>
+      invoke("add_profile", {
+        name: name.trim(),
+        apiKey: apiKey.trim(),
+        defaultModel: defaultModel.trim() || null,
+        dailyBudget: budget ? Number(budget) : null,
+      })
+    );
+    if (added) {
+      setAdding(false);
+      setName("");
+      setApiKey("");
+      setDefaultModel("");
+      setBudget("");
+    }
+  };
+
+  const active = profiles.find((p) => p.active);
+
+  return (
+    <div className="mt-4">
+      <div className="font-pixel mb-2 flex items-center justify-between text-sm text-[var(--cyber-yellow)]">
+        <span>&gt; PROFILE:</span>
+        <span className="flex gap-2">
+          {active && (
+            <button
+              onClick={() => run(() => invoke("remove_profile", { name: active.name }))}
+              className="text-[var(--text-muted)] transition-colors hover:text-[var(--hot-pink)]"
+            >
+              [ REMOVE ]
+            </button>
+          )}
+          <button
+            onClick={() => setAdding(!adding)}
+            className="text-[var(--text-muted)] transition-colors hover:text-[var(--electric-blue)]"
+          >
+            {adding ? "[ CANCEL ]" : "[ ADD ]"}
+          </button>
+        </span>
+      </div>
+      <select
+        value={active?.name ?? ""}
+        onChange={(e) => handleSelect(e.target.value)}
+        className={inputClass}
+      >
+        <option value="">Default key</option>
+        {profiles.map((profile) => (
+          <option key={profile.name} value={profile.name}>
+            {profile.name}
+            {profile.daily_budget !== null &&
+              ` (${profile.used_today}/${profile.daily_budget} today)`}
+          </option>
+        ))}
+      </select>
+      {adding && (
+        <div className="mt-2 flex flex-col gap-1">
+          <input
+            value={name}
+            onChange={(e) => setName(e.target.value)}
+            placeholder="Name, e.g. team"
+            className={inputClass}
+          />
+          <input
+            type="password"
+            value={apiKey}
+            onChange={(e) => setApiKey(e.target.value)}
+            placeholder="Gemini API key"
+            className={inputClass}
+          />
+          <input
+            value={defaultModel}
+            onChange={(e) => setDefaultModel(e.target.value)}
+            placeholder="Default model (optional)"
+            className={inputClass}
+          />
+          <input
+            type="number"
+            min={1}
+            value={budget}
+            onChange={(e) => setBudget(e.target.value)}
+            placeholder="Daily image budget (optional)"
+            className={inputClass}
+          />
+          <button
+            onClick={handleAdd}
+            disabled={!name.trim() || !apiKey.trim()}
+            className="font-pixel rounded bg-[var(--electric-blue)] py-1 text-xs font-bold text-[var(--bg-primary)] disabled:opacity-50"
+          >
+            SAVE PROFILE
+          </button>
+        </div>
+      )}
+      {error && <p className="mt-1 text-xs text-[var(--hot-pink)]">{error}</p>}
+    </div>
+  );
+}
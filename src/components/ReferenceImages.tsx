@@ -0,0 +1,57 @@
+import { open } from "@tauri-apps/plugin-dialog";
+
+interface Props {
+  paths: string[];
+  onChange: (paths: string[]) => void;
+}
+
+export function ReferenceImages({ paths, onChange }: Props) {
+  const handleAdd = async () => {
+    const selected = await open({
+      multiple: true,
+      filters: [{ name: "Custom emoji", extensions: ["png", "gif"] }],
+    });
+    if (!selected) return;
+    const added = (Array.isArray(selected) ? selected : [selected]).filter(
+      (path) => !paths.includes(path)
+    );
+    onChange([...paths, ...added]);
+  };
+
+  return (
+    <div className="mt-4">
+      <div className="font-pixel mb-2 flex items-center justify-between text-sm text-[var(--cyber-yellow)]">
+        <span>&gt; CUSTOM EMOJIS (OPTIONAL):</span>
+        <button
+          onClick={handleAdd}
+          className="text-[var(--text-muted)] transition-colors hover:text-[var(--electric-blue)]"
+        >
+          [ ADD ]
+        </button>
+      </div>
+      {paths.length > 0 && (
+        <ol className="flex flex-col gap-1">
+          {paths.map((path, i) => (
+            <li
+              key={path}
+              className="flex items-center justify-between rounded bg-[var(--bg-secondary)] px-2 py-1 text-xs text-[var(--text-secondary)]"
+            >
+              <span className="truncate">
+                {i + 1}. {path.split(/[\\/]/).pop()}
+              </span>
+              <button
+                onClick={() => onChange(paths.filter((p) => p !== path))}
+                className="ml-2 text-[var(--text-muted)] hover:text-[var(--hot-pink)]"
+              >
+                x
+              </button>
+            </li>
+          ))}
+        </ol>
+      )}
+      <p className="font-pixel mt-2 text-xs text-[var(--text-muted)]">
+        * PNG or GIF files to mix in, e.g. exported Slack emojis
+      </p>
+    </div>
+  );
+}
@@ -1,20 +1,51 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
-import type { HistoryItem } from "../types";
+import { isAuthError, toAppError } from "../types";
+import type { Animation, DiscordEmoji, DiscordSticker, Effect, HistoryItem } from "../types";
 
-type ImageVariant = "raw" | "flood" | "colorKey";
+const EFFECTS: Effect[] = [
+  "shake",
+  "spin",
+  "pulse",
+  "rainbow",
+  "bounce",
+  "zoom_in",
+  "wave",
+  "intensifies",
+  "conveyor",
+];
+
+type ImageVariant = "raw" | "flood" | "colorKey" | "softMatte";
+
+type UploadTarget = "slack" | "discord" | "sticker";
 
 interface Props {
   selectedItem: HistoryItem | null;
+  onRegenerate: (item: HistoryItem) => void;
+  onReplay: (item: HistoryItem) => void;
+  onRefine: (item: HistoryItem, instruction: string) => void;
 }
 
-export function SelectionPanel({ selectedItem }: Props) {
+export function SelectionPanel({ selectedItem, onRegenerate, onReplay, onRefine }: Props) {
   const [savedPath, setSavedPath] = useState<string | null>(null);
+  const [instruction, setInstruction] = useState("");
   const [selectedVariant, setSelectedVariant] = useState<ImageVariant>("colorKey");
+  const [effect, setEffect] = useState<Effect>("shake");
+  const [animation, setAnimation] = useState<Animation | null>(null);
+  const [animating, setAnimating] = useState(false);
+  const [uploadName, setUploadName] = useState("");
+  const [uploadToken, setUploadToken] = useState("");
+  const [guildId, setGuildId] = useState("");
+  // Destination whose token must be entered before the next upload there
+  const [needsToken, setNeedsToken] = useState<UploadTarget | null>(null);
+  const [uploadStatus, setUploadStatus] = useState<{ ok: boolean; message: string } | null>(null);
 
-  // Reset saved path when selecting a different item
+  // Reset saved path, refine instruction, animation and upload state when selecting a different item
   useEffect(() => {
     setSavedPath(null);
+    setInstruction("");
+    setAnimation(null);
+    setUploadStatus(null);
   }, [selectedItem?.id]);
 
   // Get image for a specific variant
@@ -24,6 +55,7 @@ export function SelectionPanel({ selectedItem }: Props) {
       case "raw": return selectedItem.rawImage;
       case "flood": return selectedItem.floodFill;
       case "colorKey": return selectedItem.colorKey;
+      case "softMatte": return selectedItem.softMatte;
     }
   };
 
@@ -38,6 +70,7 @@ export function SelectionPanel({ selectedItem }: Props) {
         imageBase64: selectedImage,
         emojis: selectedItem.sourceEmojis,
         modifier: selectedItem.modifier || null,
+        generationId: selectedItem.generationId,
       });
       setSavedPath(path);
     } catch (error) {
@@ -45,6 +78,79 @@ export function SelectionPanel({ selectedItem }: Props) {
     }
   };
 
+  const handleAnimate = async () => {
+    if (!selectedImage) return;
+    setAnimating(true);
+    try {
+      // Processed variants are already transparent and are animated as they are
+      setAnimation(await invoke<Animation>("animate_image", {
+        imageBase64: selectedImage,
+        effect,
+        frames: null,
+        format: "gif",
+        maxBytes: null,
+        tolerance: null,
+      }));
+    } catch (error) {
+      console.error("Failed to animate:", error);
+    } finally {
+      setAnimating(false);
+    }
+  };
+
+  const handleSaveAnimation = async () => {
+    if (!animation || !selectedItem) return;
+    try {
+      const path = await invoke<string>("save_emoji_image", {
+        imageBase64: animation.data,
+        emojis: selectedItem.sourceEmojis,
+        modifier: selectedItem.modifier || null,
+        extension: animation.format === "apng" ? "png" : animation.format,
+        generationId: selectedItem.generationId,
+      });
+      setSavedPath(path);
+    } catch (error) {
+      console.error("Failed to save:", error);
+    }
+  };
+
+  // Uploads the animation if there is one, otherwise the selected variant
+  const handleUpload = async (target: UploadTarget) => {
+    const image = animation?.data ?? selectedImage;
+    if (!image || !uploadName.trim()) return;
+    // Stickers go to Discord with the same bot token
+    const service = target === "slack" ? "slack" : "discord";
+    try {
+      if (needsToken === service) {
+        await invoke(`save_${service}_token`, { token: uploadToken });
+        setUploadToken("");
+        setNeedsToken(null);
+      }
+      if (target === "slack") {
+        const name = await invoke<string>("upload_to_slack", { imageBase64: image, name: uploadName });
+        setUploadStatus({ ok: true, message: `Added :${name}: to Slack` });
+      } else if (target === "sticker") {
+        const sticker = await invoke<DiscordSticker>("upload_sticker_to_discord", {
+          imageBase64: image,
+          name: uploadName,
+          guildId: guildId || null,
+        });
+        setUploadStatus({ ok: true, message: `Added sticker "${sticker.name}" to Discord` });
+      } else {
+        const emoji = await invoke<DiscordEmoji>("upload_to_discord", {
+          imageBase64: image,
+          name: uploadName,
+          guildId: guildId || null,
+        });
+        setUploadStatus({ ok: true, message: `Added :${emoji.name}: to Discord` });
+      }
+    } catch (err) {
+      const error = toAppError(err);
+      setNeedsToken(isAuthError(error) ? service : null);
+      setUploadStatus({ ok: false, message: error.message });
+    }
+  };
+
   const handleCopyToClipboard = async () => {
     if (!imageSrc) return;
     try {
@@ -76,6 +182,7 @@ export function SelectionPanel({ selectedItem }: Props) {
     { key: "raw", label: "RAW" },
     { key: "flood", label: "FLOOD" },
     { key: "colorKey", label: "COLOUR KEY" },
+    { key: "softMatte", label: "SOFT MATTE" },
   ];
 
   return (
@@ -201,8 +308,151 @@ export function SelectionPanel({ selectedItem }: Props) {
         >
           COPY
         </button>
+        <div className="flex gap-2">
+          <button
+            onClick={() => onRegenerate(selectedItem)}
+            title="Generate this image again with the same prompt"
+            className="btn-bevel font-pixel flex-1 rounded-lg bg-[var(--border-chunky)] py-2 text-xs font-bold text-white"
+          >
+            REGENERATE
+          </button>
+          <button
+            onClick={() => onReplay(selectedItem)}
+            title="Run the whole generation again"
+            className="btn-bevel font-pixel flex-1 rounded-lg bg-[var(--border-chunky)] py-2 text-xs font-bold text-white"
+          >
+            REPLAY
+          </button>
+        </div>
+        <div className="flex gap-2">
+          <select
+            value={effect}
+            onChange={(e) => setEffect(e.target.value as Effect)}
+            className="font-pixel min-w-0 flex-1 rounded-lg border-2 border-[var(--border-chunky)] bg-[var(--surface-elevated)] px-2 text-xs text-white"
+          >
+            {EFFECTS.map((name) => (
+              <option key={name} value={name}>{name.replace("_", " ").toUpperCase()}</option>
+            ))}
+          </select>
+          <button
+            onClick={handleAnimate}
+            disabled={!hasImage || animating}
+            title="Animate the selected image locally as a GIF"
+            className={`btn-bevel font-pixel rounded-lg px-3 py-2 text-xs font-bold ${
+              hasImage && !animating
+                ? "bg-[var(--border-chunky)] text-white"
+                : "cursor-not-allowed bg-[var(--border-chunky)] text-[var(--text-muted)] opacity-40"
+            }`}
+          >
+            ANIMATE
+          </button>
+        </div>
+        {animation && (
+          <div className="flex items-center gap-2">
+            <img
+              src={`data:${animation.mime_type};base64,${animation.data}`}
+              alt="Animation"
+              className="h-16 w-16 object-contain"
+            />
+            <button
+              onClick={handleSaveAnimation}
+              title={animation.warning ?? undefined}
+              className="btn-bevel font-pixel flex-1 rounded-lg bg-[var(--electric-blue)] py-2 text-xs font-bold text-[var(--bg-primary)]"
+            >
+              SAVE {animation.format.toUpperCase()}
+            </button>
+          </div>
+        )}
+        <form
+          className="flex gap-2"
+          onSubmit={(e) => {
+            e.preventDefault();
+            onRefine(selectedItem, instruction);
+          }}
+        >
+          <input
+            type="text"
+            value={instruction}
+            onChange={(e) => setInstruction(e.target.value)}
+            placeholder="make the hat red"
+            className="min-w-0 flex-1 rounded-lg border-2 border-[var(--border-chunky)] bg-[var(--surface-elevated)] px-2 text-xs text-white"
+          />
+          <button
+            type="submit"
+            disabled={!selectedItem.rawImage || !instruction.trim()}
+            className={`btn-bevel font-pixel rounded-lg px-3 py-2 text-xs font-bold ${
+              selectedItem.rawImage && instruction.trim()
+                ? "bg-[var(--border-chunky)] text-white"
+                : "cursor-not-allowed bg-[var(--border-chunky)] text-[var(--text-muted)] opacity-40"
+            }`}
+          >
+            REFINE
+          </button>
+        </form>
       </div>
 
+      {/* Upload to Slack or Discord */}
+      <form
+        className="mt-2 flex flex-col gap-2"
+        onSubmit={(e) => {
+          e.preventDefault();
+          handleUpload("slack");
+        }}
+      >
+        {needsToken && (
+          <input
+            type="password"
+            value={uploadToken}
+            onChange={(e) => setUploadToken(e.target.value)}
+            placeholder={needsToken === "slack" ? "Slack user token (xoxp-...)" : "Discord bot token"}
+            className="rounded-lg border-2 border-[var(--border-chunky)] bg-[var(--surface-elevated)] px-2 py-1 text-xs text-white"
+          />
+        )}
+        <div className="flex gap-2">
+          <input
+            type="text"
+            value={uploadName}
+            onChange={(e) => setUploadName(e.target.value)}
+            placeholder="emoji_name"
+            className="min-w-0 flex-1 rounded-lg border-2 border-[var(--border-chunky)] bg-[var(--surface-elevated)] px-2 text-xs text-white"
+          />
+          <input
+            type="text"
+            value={guildId}
+            onChange={(e) => setGuildId(e.target.value)}
+            placeholder="server ID"
+            title="Discord server ID; defaults to discord_guild_id in config.json"
+            className="w-20 min-w-0 rounded-lg border-2 border-[var(--border-chunky)] bg-[var(--surface-elevated)] px-2 text-xs text-white"
+          />
+        </div>
+        <div className="flex gap-2">
+          {(["slack", "discord", "sticker"] as const).map((target) => (
+            <button
+              key={target}
+              type="button"
+              onClick={() => handleUpload(target)}
+              disabled={!hasImage || !uploadName.trim()}
+              className={`btn-bevel font-pixel flex-1 rounded-lg py-2 text-xs font-bold ${
+                hasImage && uploadName.trim()
+                  ? "bg-[var(--border-chunky)] text-white"
+                  : "cursor-not-allowed bg-[var(--border-chunky)] text-[var(--text-muted)] opacity-40"
+              }`}
+            >
+              {target.toUpperCase()}
+            </button>
+          ))}
+        </div>
+        {uploadStatus && (
+          <div
+            className={`text-xs break-words ${
+              uploadStatus.ok ? "font-pixel text-[var(--lime)]" : "text-[var(--hot-pink)]"
+            }`}
+          >
+            {uploadStatus.message}
+          </div>
+        )}
+      </form>
+
       {/* Saved path */}
       {savedPath && (
         <div className="font-pixel mt-2 text-center text-xs text-[var(--lime)]">
@@ -0,0 +1,45 @@
+import { useEffect, useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
+import type { UsageReport } from "../types";
+
+interface Props {
+  /** Changes whenever a generation finishes, to refresh the totals */
+  refreshKey: number;
+}
+
+const DAYS = 30;
+
+const formatCost = (usd: number) => `$${usd.toFixed(usd < 1 ? 3 : 2)}`;
+
+export function UsageSummary({ refreshKey }: Props) {
+  const [report, setReport] = useState<UsageReport | null>(null);
+
+  useEffect(() => {
+    invoke<UsageReport>("get_usage_report", { days: DAYS })
+      .then(setReport)
+      .catch((err) => console.error("Failed to load usage:", err));
+  }, [refreshKey]);
+
+  if (!report || report.total.requests === 0) return null;
+
+  return (
+    <div className="mt-4">
+      <div className="font-pixel mb-2 flex items-center justify-between text-sm text-[var(--cyber-yellow)]">
+        <span>&gt; USAGE ({DAYS} DAYS):</span>
+        <span className="text-[var(--lime)]">{formatCost(report.total.cost_usd)}</span>
+      </div>
+      <ul className="flex flex-col gap-1 text-xs text-[var(--text-secondary)]">
+        {Object.entries(report.models).map(([model, totals]) => (
+          <li key={model} className="flex justify-between">
+            <span>
+              {model}: {totals.images} images
+            </span>
+            <span>
+              {report.unpriced_models.includes(model) ? "unpriced" : formatCost(totals.cost_usd)}
+            </span>
+          </li>
+        ))}
+      </ul>
+    </div>
+  );
+}
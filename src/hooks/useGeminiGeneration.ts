@@ -1,24 +1,28 @@
-import { useState, useCallback } from "react";
+import { useState, useCallback, useRef } from "react";
 import { invoke, Channel } from "@tauri-apps/api/core";
-import type { GenerationResult, GenerationProgress, HistoryItem } from "../types";
+import { toAppError, isAuthError } from "../types";
+import type {
+  AppError,
+  GenerationResult,
+  GenerationProgress,
+  HistoryItem,
+} from "../types";
 
 export function useGeminiGeneration() {
   const [pendingCount, setPendingCount] = useState(0);
-  const [error, setError] = useState<string | null>(null);
+  const [error, setError] = useState<AppError | null>(null);
+  // Ids of generations that are still running, so they can be cancelled
+  const activeIds = useRef(new Set<string>());
 
-  const generate = useCallback(
+  // Run a generation command, streaming each finished slot to onItemComplete
+  const run = useCallback(
     async (
-      emojis: string[],
-      modifier: string,
-      fastModel: boolean,
+      command: string,
+      args: Record<string, unknown>,
       count: number,
+      source: { emojis: string[]; modifier: string; model: string },
       onItemComplete: (item: HistoryItem) => void
     ) => {
-      if (emojis.length === 0) {
-        setError("Please select at least one emoji");
-        return;
-      }
-
       // Add to pending count (allows concurrent batches)
       setPendingCount((prev) => prev + count);
       setError(null);
@@ -27,20 +31,48 @@ export function useGeminiGeneration() {
       const onProgress = new Channel<GenerationProgress>();
 
       onProgress.onmessage = (progress: GenerationProgress) => {
+        // The generation and its slots are announced up front; remember the id for cancellation
+        if (progress.status === "started" || progress.status === "pending") {
+          activeIds.current.add(progress.generation_id);
+          return;
+        }
+        if (progress.index === null) {
+          return;
+        }
+
+        // Slot hit a transient error and will be retried; it isn't done yet
+        if (progress.status === "retrying") {
+          return;
+        }
+
+        // Cancelled slots are finished, but there's nothing to show
+        if (progress.status === "cancelled") {
+          setPendingCount((prev) => Math.max(0, prev - 1));
+          return;
+        }
+
         // Create history item for this completed generation
         const item: HistoryItem = {
-          id: crypto.randomUUID(),
-          sourceEmojis: [...emojis],
-          modifier: modifier || "",
-          model: fastModel ? "gemini-2.5-flash-image" : "gemini-3-pro-image-preview",
+          id: `${progress.generation_id}-${progress.index}`,
+          generationId: progress.generation_id,
+          slotIndex: progress.index,
+          sourceEmojis: [...source.emojis],
+          modifier: source.modifier,
+          model: source.model,
           status: progress.flood_fill ? "success" : "error",
           floodFill: progress.flood_fill,
           colorKey: progress.color_key,
+          softMatte: progress.soft_matte,
           rawImage: progress.raw_image,
           warning: progress.warning,
-          error: progress.error,
+          error: progress.error?.message ?? null,
         };
 
+        // A rejected key fails every slot; surface it so the app can ask for a new one
+        if (isAuthError(progress.error)) {
+          setError(progress.error);
+        }
+
         // Call the completion callback
         onItemComplete(item);
 
@@ -49,26 +81,139 @@ export function useGeminiGeneration() {
       };
 
       try {
-        await invoke<GenerationResult>("generate_emoji", {
-          emojis,
-          modifier: modifier || null,
-          fastModel,
-          count,
+        const result = await invoke<GenerationResult>(command, {
+          ...args,
           onProgress,
         });
+        activeIds.current.delete(result.generation_id);
       } catch (err) {
         // Decrement all pending from this batch on error
         setPendingCount((prev) => Math.max(0, prev - count));
-        setError(err instanceof Error ? err.message : String(err));
+        const appError = toAppError(err);
+        if (appError.kind !== "cancelled") {
+          setError(appError);
+        }
       }
     },
     []
   );
 
+  const generate = useCallback(
+    async (
+      emojis: string[],
+      modifier: string,
+      referenceImages: string[],
+      fastModel: boolean,
+      count: number,
+      onItemComplete: (item: HistoryItem) => void
+    ) => {
+      if (emojis.length === 0 && referenceImages.length === 0) {
+        setError({
+          kind: "invalid_input",
+          message: "Please select at least one emoji or custom emoji",
+          retry_after_ms: null,
+        });
+        return;
+      }
+
+      await run(
+        "generate_emoji",
+        {
+          emojis,
+          modifier: modifier || null,
+          referenceImages: referenceImages.length > 0 ? referenceImages : null,
+          fastModel,
+          count,
+        },
+        count,
+        {
+          emojis,
+          modifier: modifier || "",
+          model: fastModel ? "gemini-2.5-flash-image" : "gemini-3-pro-image-preview",
+        },
+        onItemComplete
+      );
+    },
+    [run]
+  );
+
+  // Generate one slot again; the result replaces the item with the same id
+  const regenerateSlot = useCallback(
+    async (item: HistoryItem, onItemComplete: (item: HistoryItem) => void) => {
+      await run(
+        "regenerate_slot",
+        { generationId: item.generationId, index: item.slotIndex },
+        1,
+        { emojis: item.sourceEmojis, modifier: item.modifier, model: item.model },
+        onItemComplete
+      );
+    },
+    [run]
+  );
+
+  // Run the item's whole generation again as a new batch
+  const replay = useCallback(
+    async (
+      item: HistoryItem,
+      count: number,
+      onItemComplete: (item: HistoryItem) => void
+    ) => {
+      await run(
+        "replay_generation",
+        { generationId: item.generationId, count },
+        count,
+        { emojis: item.sourceEmojis, modifier: item.modifier, model: item.model },
+        onItemComplete
+      );
+    },
+    [run]
+  );
+
+  // Send an earlier result back with an instruction, e.g. "make the hat red"
+  const refine = useCallback(
+    async (
+      item: HistoryItem,
+      instruction: string,
+      count: number,
+      onItemComplete: (item: HistoryItem) => void
+    ) => {
+      if (!item.rawImage || !instruction.trim()) {
+        return;
+      }
+
+      await run(
+        "refine_emoji",
+        {
+          imageBase64: item.rawImage,
+          instruction,
+          emojis: item.sourceEmojis,
+          model: item.model,
+          count,
+        },
+        count,
+        { emojis: item.sourceEmojis, modifier: instruction.trim(), model: item.model },
+        onItemComplete
+      );
+    },
+    [run]
+  );
+
+  const cancel = useCallback(async () => {
+    const ids = [...activeIds.current];
+    activeIds.current.clear();
+    await Promise.all(
+      ids.map((generationId) => invoke("cancel_generation", { generationId }))
+    );
+  }, []);
+
   return {
     isLoading: pendingCount > 0,
     pendingCount,
     error,
     generate,
+    regenerateSlot,
+    replay,
+    refine,
+    cancel,
   };
 }
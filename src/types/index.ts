@@ -1,22 +1,73 @@
+export type ErrorKind =
+  | "missing_api_key"
+  | "invalid_api_key"
+  | "permission_denied"
+  | "rate_limited"
+  | "safety_blocked"
+  | "server_error"
+  | "network"
+  | "api_error"
+  | "invalid_response"
+  | "no_background"
+  | "image_processing"
+  | "invalid_input"
+  | "locked"
+  | "name_taken"
+  | "limit_reached"
+  | "storage"
+  | "cancelled";
+
+/** Error returned by every backend command */
+export interface AppError {
+  kind: ErrorKind;
+  message: string;
+  retry_after_ms: number | null;
+  /** What the request was billed when the provider answered without a usable result */
+  usage?: Usage;
+}
+
 export interface ImageResult {
   flood_fill: string | null;
   color_key: string | null;
+  soft_matte: string | null;
+  /** Only present when the run asked for more than one output size */
+  extra_sizes?: SizedImages[];
   warning: string | null;
-  error: string | null;
+  error: AppError | null;
 }
 
 export interface GenerationResult {
+  generation_id: string;
   results: ImageResult[];
   mime_type: string;
+  /** Summed over every slot whose request completed */
+  usage: Usage;
 }
 
 export interface GenerationProgress {
-  index: number;
+  generation_id: string;
+  /** Slot the update is about; null only for the "started" event */
+  index: number | null;
+  status: "started" | "pending" | "retrying" | "success" | "error" | "cancelled";
+  attempt: number | null;
+  retry_in_ms: number | null;
   flood_fill: string | null;
   color_key: string | null;
+  soft_matte: string | null;
   raw_image: string | null;
+  /** Only present when the run asked for more than one output size */
+  extra_sizes?: SizedImages[];
   warning: string | null;
-  error: string | null;
+  error: AppError | null;
+  /** What the slot's request consumed, once the provider answered with an image */
+  usage?: Usage;
+}
+
+export interface SizedImages {
+  size: number;
+  flood_fill: string;
+  color_key: string;
+  soft_matte: string;
 }
 
 export interface SlotState {
@@ -28,20 +79,210 @@ export interface SlotState {
 
 export interface GenerationState {
   isLoading: boolean;
-  error: string | null;
+  error: AppError | null;
   result: GenerationResult | null;
   slots: SlotState[];
 }
 
 export interface HistoryItem {
   id: string;
+  generationId: string;
+  slotIndex: number;
   sourceEmojis: string[];
   modifier: string;
   model: string;
   status: "success" | "error";
   floodFill: string | null;
   colorKey: string | null;
+  softMatte: string | null;
   rawImage: string | null;
   warning: string | null;
   error: string | null;
 }
+
+export interface SavedHistorySlot {
+  index: number;
+  status: GenerationProgress["status"];
+  completed_at: number;
+  warning: string | null;
+  error: AppError | null;
+  raw_image: string | null;
+  flood_fill: string | null;
+  color_key: string | null;
+  soft_matte: string | null;
+  /** Only present when the generation asked for more than one output size */
+  extra_sizes?: SizedImages[];
+}
+
+export interface SavedHistoryEntry {
+  id: string;
+  created_at: number;
+  updated_at: number;
+  prompt: string;
+  emojis: string[];
+  modifier: string | null;
+  provider: string;
+  model: string;
+  count: number;
+  /** Background tolerance used; null means the default */
+  tolerance: number | null;
+  /** Output sizes, margin and padding used; null means the default */
+  sizes: number[] | null;
+  margin: number | null;
+  padding: number | null;
+  /** Set for animated generations, whose slots are the generated frames */
+  animation: SavedHistoryAnimation | null;
+}
+
+export interface SavedHistoryAnimation {
+  motion: string | null;
+  effect: Effect | null;
+  format: AnimationFormat;
+  frames: number | null;
+  saved: boolean;
+}
+
+export interface SavedHistoryPage {
+  entries: SavedHistoryEntry[];
+  total: number;
+}
+
+export interface SavedHistoryDetail extends SavedHistoryEntry {
+  images: SavedHistorySlot[];
+  /** The encoded animation as base64, for animated entries */
+  animation_data: string | null;
+}
+
+/** One input of `process_image`; paths are absolute */
+export interface ProcessedFile {
+  input: string;
+  flood_fill: string | null;
+  color_key: string | null;
+  soft_matte: string | null;
+  /** Every variant at the additional output sizes */
+  extra_sizes: string[];
+  warning: string | null;
+  error: AppError | null;
+}
+
+export type Effect =
+  | "shake"
+  | "spin"
+  | "pulse"
+  | "rainbow"
+  | "bounce"
+  | "zoom_in"
+  | "wave"
+  | "intensifies"
+  | "conveyor";
+
+export type AnimationFormat = "gif" | "apng" | "webp";
+
+/** Result of `animate_image` and `generate_animation` */
+export interface Animation {
+  /** Base64 encoded file */
+  data: string;
+  format: AnimationFormat;
+  mime_type: string;
+  frames: number;
+  size: number;
+  delay_ms: number;
+  warning: string | null;
+}
+
+/** Result of `upload_to_discord` */
+export interface DiscordEmoji {
+  id: string;
+  name: string;
+  animated: boolean;
+}
+
+export interface DiscordSticker {
+  id: string;
+  name: string;
+  tags: string;
+}
+
+/** Result of `api_key_storage` */
+export interface ApiKeyStorage {
+  storage: "environment" | "keyring" | "file" | "encrypted_file" | "none";
+  /** Encrypted file and no passphrase entered yet */
+  locked: boolean;
+}
+
+/** An image model a provider offers */
+export interface ModelInfo {
+  id: string;
+  name: string;
+  fast: boolean;
+}
+
+/** Result of `validate_api_key` */
+export interface KeyValidation {
+  status: "valid" | "invalid" | "permission_denied" | "network_unreachable";
+  /** Why the key isn't usable; null when valid */
+  message: string | null;
+  /** Image models the key can generate with */
+  image_models: ModelInfo[];
+}
+
+/** Tokens and images consumed by one or more requests */
+export interface Usage {
+  requests: number;
+  input_tokens: number;
+  /** Includes thinking and image tokens */
+  output_tokens: number;
+  images: number;
+}
+
+export interface UsageTotals extends Usage {
+  cost_usd: number;
+}
+
+export interface GenerationUsage extends UsageTotals {
+  generation_id: string;
+  model: string;
+  profile: string | null;
+  at: number;
+  /** UTC date, "YYYY-MM-DD" */
+  day: string;
+}
+
+/** Result of `get_usage_report`; profiles are keyed by name, "" for the default key */
+export interface UsageReport {
+  total: UsageTotals;
+  /** Newest first */
+  generations: GenerationUsage[];
+  days: Record<string, UsageTotals>;
+  profiles: Record<string, UsageTotals>;
+  models: Record<string, UsageTotals>;
+  /** Models used but missing from the price table; counted as free */
+  unpriced_models: string[];
+}
+
+/** A named API key profile from `list_profiles` */
+export interface ProfileInfo {
+  name: string;
+  default_model: string | null;
+  daily_budget: number | null;
+  active: boolean;
+  /** Images generated with this profile today (UTC) */
+  used_today: number;
+}
+
+/** Normalize anything thrown by `invoke` into an AppError */
+export function toAppError(err: unknown): AppError {
+  if (err && typeof err === "object" && "kind" in err && "message" in err) {
+    return err as AppError;
+  }
+  return {
+    kind: "invalid_input",
+    message: err instanceof Error ? err.message : String(err),
+    retry_after_ms: null,
+  };
+}
+
+/** Errors that mean the stored API key needs to be re-entered */
+export function isAuthError(err: AppError | null): boolean {
+  return err?.kind === "invalid_api_key" || err?.kind === "missing_api_key";
+}